@@ -0,0 +1,110 @@
+//! Compares host transports on bulk responses
+//!
+//! `cargo bench --bench host_transport -- [iterations]`
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use genvm::calldata::Address;
+use genvm::mock::{MockBytes, MockHost, MockState};
+use genvm::public_abi::StorageType;
+use genvm::{AccountAddress, Host, SlotID};
+
+const CONTRACT: AccountAddress = AccountAddress([1; 20]);
+
+const SIZES: [usize; 3] = [64, 64 * 1024, 1024 * 1024];
+
+fn make_state(size: usize) -> MockState {
+    let mut state = MockState::default();
+    state.calldata = MockBytes(vec![7; size]);
+    state.storage.insert(
+        CONTRACT,
+        BTreeMap::from([(SlotID::ZERO, MockBytes(vec![5; size]))]),
+    );
+    state
+}
+
+fn spawn_host(listen: &str, size: usize, protocol_version: u32) -> std::thread::JoinHandle<()> {
+    let path = listen.split_once("://").unwrap().1.to_owned();
+    let _ = std::fs::remove_file(&path);
+    let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let is_shm = listen.starts_with("shm://");
+
+    std::thread::spawn(move || {
+        let mut host = MockHost::new(make_state(size), CONTRACT);
+        let (stream, _) = listener.accept().unwrap();
+
+        // connection is closed by dropping client, which is reported as an error
+        let _ = if is_shm {
+            host.serve_shm(stream)
+        } else {
+            host.serve(stream.try_clone().unwrap(), stream, protocol_version)
+        };
+    })
+}
+
+async fn bench_one(host: &Host, size: usize, iterations: u32) -> Duration {
+    let mut buf = vec![0; size];
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let mut calldata = Vec::new();
+        host.get_calldata(&mut calldata).await.unwrap();
+        assert_eq!(calldata.len(), size);
+
+        host.storage_read(
+            StorageType::Default,
+            Address::from(CONTRACT.raw()),
+            SlotID::ZERO,
+            0,
+            &mut buf,
+        )
+        .await
+        .unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let iterations: u32 = std::env::args()
+        .skip(1)
+        .find(|x| !x.starts_with('-'))
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(1000);
+
+    let dir = std::env::temp_dir().join(format!("genvm-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let sock = dir.join("host.sock");
+    let sock = sock.to_str().unwrap();
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let transports = [
+        ("unix sequential", format!("unix://{sock}"), 1),
+        ("unix pipelined", format!("unix://{sock}"), 3),
+        ("shm", format!("shm://{sock}"), 3),
+    ];
+
+    for size in SIZES {
+        for (name, addr, protocol_version) in &transports {
+            let server = spawn_host(addr, size, *protocol_version);
+
+            let elapsed = runtime.block_on(async {
+                let host = Host::new(addr, *protocol_version).await.unwrap();
+                bench_one(&host, size, iterations).await
+            });
+
+            server.join().unwrap();
+
+            println!(
+                "{name:>16} {size:>8}B: {:>10.2?}/iter",
+                elapsed / iterations
+            );
+        }
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 pub const ADDRESS_SIZE: usize = 20;
 
-#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
 pub struct Address(pub(super) [u8; ADDRESS_SIZE]);
 
 impl std::fmt::Debug for Address {
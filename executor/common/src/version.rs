@@ -53,6 +53,16 @@ impl std::str::FromStr for Version {
     }
 }
 
+impl<'d> serde::Deserialize<'d> for Version {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'d>,
+    {
+        let s = String::deserialize(deserializer)?;
+        <Self as std::str::FromStr>::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 impl Version {
     pub const ZERO: Self = Self {
         major: 0,
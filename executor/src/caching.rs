@@ -1,5 +1,7 @@
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use genvm_common::*;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 /// tries to get cache directory
 pub fn get_cache_dir(base_path: &str) -> Result<PathBuf> {
@@ -19,6 +21,12 @@ pub struct DetNonDetSuffixes {
 
 pub const PRECOMPILE_DIR_NAME: &str = "pc";
 
+/// Name of [`PrecompileManifest`] file inside of `pc/<runner id>/<hash>/`
+pub const PRECOMPILE_MANIFEST_NAME: &str = "manifest.json";
+
+/// Snapshots of runners after `_initialize`, see [`crate::snapshot`]
+pub const SNAPSHOT_DIR_NAME: &str = "snap";
+
 pub const DET_NON_DET_PRECOMPILED_SUFFIX: DetNonDetSuffixes = DetNonDetSuffixes {
     det: "det",
     non_det: "non-det",
@@ -37,6 +45,131 @@ pub fn path_in_zip_to_hash(path: &str) -> String {
     base32::encode(base32::Alphabet::Rfc4648 { padding: false }, digits)
 }
 
+/// Hash of wasm source that was precompiled, same encoding as [`path_in_zip_to_hash`]
+pub fn source_hash(wasm: &[u8]) -> String {
+    use sha3::digest::FixedOutput;
+    use sha3::{Digest, Sha3_256};
+
+    let mut hasher = Sha3_256::new();
+    hasher.update(wasm);
+    let digits = hasher.finalize_fixed();
+
+    base32::encode(
+        base32::Alphabet::Rfc4648 { padding: false },
+        digits.as_slice(),
+    )
+}
+
+/// Identifies everything that affects compiled code of an engine
+///
+/// Precompiled artifacts are valid only for the same genvm (and hence wasmtime) build,
+/// enabled wasm features and determinism mode
+pub fn engine_fingerprint(engine: &wasmtime::Engine, mode: &str) -> String {
+    use sha3::digest::FixedOutput;
+    use sha3::{Digest, Sha3_224};
+
+    let mut hasher = Sha3_224::new();
+    hasher.update(genvm_common::VERSION.as_bytes());
+    hasher.update([0]);
+    hasher.update(std::env::consts::ARCH.as_bytes());
+    hasher.update([0]);
+    hasher.update(mode.as_bytes());
+    hasher.update([0]);
+    hasher.update(engine.config().get_features().bits().to_le_bytes());
+    let digits = hasher.finalize_fixed();
+
+    base32::encode(
+        base32::Alphabet::Rfc4648 { padding: false },
+        digits.as_slice(),
+    )
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct PrecompiledEntry {
+    pub source_hash: String,
+}
+
+/// Describes precompiled modules of a single runner archive
+///
+/// `genvm precompile` writes it after all modules of the archive are written, so its presence
+/// means that the directory is complete
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct PrecompileManifest {
+    pub genvm_version: String,
+    pub det_fingerprint: String,
+    pub non_det_fingerprint: String,
+    /// keys are paths inside of runner archive
+    pub entries: BTreeMap<String, PrecompiledEntry>,
+}
+
+impl PrecompileManifest {
+    pub fn new(engines: &crate::vm::Engines) -> Self {
+        Self {
+            genvm_version: genvm_common::VERSION.to_owned(),
+            det_fingerprint: engine_fingerprint(&engines.det, DET_NON_DET_PRECOMPILED_SUFFIX.det),
+            non_det_fingerprint: engine_fingerprint(
+                &engines.non_det,
+                DET_NON_DET_PRECOMPILED_SUFFIX.non_det,
+            ),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Returns `None` if runner was not precompiled at all
+    pub fn load(runner_dir: &Path) -> Result<Option<Self>> {
+        let path = runner_dir.join(PRECOMPILE_MANIFEST_NAME);
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("reading {path:?}")),
+        };
+
+        let manifest =
+            serde_json::from_slice(&data).with_context(|| format!("parsing {path:?}"))?;
+        Ok(Some(manifest))
+    }
+
+    pub fn save(&self, runner_dir: &Path) -> Result<()> {
+        let path = runner_dir.join(PRECOMPILE_MANIFEST_NAME);
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp_path, &path)?;
+
+        Ok(())
+    }
+
+    /// Checks that precompiled module for `path_in_arch` was produced from `wasm` by compatible engines
+    pub fn verify(
+        &self,
+        engines: &crate::vm::Engines,
+        path_in_arch: &str,
+        wasm: &[u8],
+    ) -> Result<()> {
+        let current = Self::new(engines);
+
+        if self.det_fingerprint != current.det_fingerprint
+            || self.non_det_fingerprint != current.non_det_fingerprint
+        {
+            anyhow::bail!(
+                "engine fingerprint mismatch, precompiled by genvm {}",
+                self.genvm_version
+            );
+        }
+
+        let entry = self
+            .entries
+            .get(path_in_arch)
+            .with_context(|| format!("{path_in_arch} is absent from manifest"))?;
+
+        if entry.source_hash != source_hash(wasm) {
+            anyhow::bail!("source hash mismatch for {path_in_arch}");
+        }
+
+        Ok(())
+    }
+}
+
 pub fn validate_wasm(engines: &crate::vm::Engines, wasm: &[u8]) -> Result<()> {
     use wasmparser::*;
 
@@ -67,3 +200,267 @@ pub fn validate_wasm(engines: &crate::vm::Engines, wasm: &[u8]) -> Result<()> {
 
     Ok(())
 }
+
+/// Smallest part of cache directory that is evicted at once
+///
+/// Per-runner directories of precompiled modules and snapshots are evicted as a whole, so that
+/// manifests never describe missing files; everything else is evicted file by file
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+    /// top level directory of cache, such as `pc`, `snap` or `wasmtime`
+    pub category: String,
+    pub size: u64,
+    pub last_used: std::time::SystemTime,
+}
+
+fn last_used(meta: &std::fs::Metadata) -> std::time::SystemTime {
+    let modified = meta.modified().unwrap_or(std::time::UNIX_EPOCH);
+    match meta.accessed() {
+        Ok(accessed) => accessed.max(modified),
+        Err(_) => modified,
+    }
+}
+
+fn dir_usage(path: &Path) -> Result<(u64, std::time::SystemTime)> {
+    let mut size = 0;
+    let mut used = std::time::UNIX_EPOCH;
+
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            let (sub_size, sub_used) = dir_usage(&entry.path())?;
+            size += sub_size;
+            used = used.max(sub_used);
+        } else {
+            size += meta.len();
+            used = used.max(last_used(&meta));
+        }
+    }
+
+    Ok((size, used))
+}
+
+fn collect_files(path: &Path, category: &str, result: &mut Vec<CacheEntry>) -> Result<()> {
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            collect_files(&entry.path(), category, result)?;
+        } else {
+            result.push(CacheEntry {
+                path: entry.path(),
+                category: category.to_owned(),
+                size: meta.len(),
+                last_used: last_used(&meta),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists all evictable entries of cache directory
+pub fn list_entries(cache_dir: &Path) -> Result<Vec<CacheEntry>> {
+    let mut result = Vec::new();
+
+    for top in std::fs::read_dir(cache_dir)? {
+        let top = top?;
+        let category = top.file_name().to_string_lossy().into_owned();
+
+        if !top.file_type()?.is_dir() {
+            // `.test` file is recreated by `get_cache_dir` anyway
+            continue;
+        }
+
+        if category == PRECOMPILE_DIR_NAME || category == SNAPSHOT_DIR_NAME {
+            for runner_id in std::fs::read_dir(top.path())? {
+                let runner_id = runner_id?;
+                if !runner_id.file_type()?.is_dir() {
+                    continue;
+                }
+                for runner_hash in std::fs::read_dir(runner_id.path())? {
+                    let runner_hash = runner_hash?;
+                    if !runner_hash.file_type()?.is_dir() {
+                        continue;
+                    }
+                    let (size, last_used) = dir_usage(&runner_hash.path())?;
+                    result.push(CacheEntry {
+                        path: runner_hash.path(),
+                        category: category.clone(),
+                        size,
+                        last_used,
+                    });
+                }
+            }
+        } else {
+            collect_files(&top.path(), &category, &mut result)?;
+        }
+    }
+
+    Ok(result)
+}
+
+pub fn remove_entry(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+    .with_context(|| format!("removing {path:?}"))
+}
+
+/// Removes least recently used entries until cache fits into `max_size` bytes
+///
+/// Returns removed entries
+pub fn gc(cache_dir: &Path, max_size: u64, dry_run: bool) -> Result<Vec<CacheEntry>> {
+    let mut entries = list_entries(cache_dir)?;
+    entries.sort_by_key(|e| e.last_used);
+
+    let mut total: u64 = entries.iter().map(|e| e.size).sum();
+    let mut removed = Vec::new();
+
+    for entry in entries {
+        if total <= max_size {
+            break;
+        }
+
+        if !dry_run {
+            // entry may be in use by concurrent process, it will be recreated
+            if let Err(e) = remove_entry(&entry.path) {
+                log_warn!(error:ah = &e; "could not evict cache entry");
+                continue;
+            }
+        }
+
+        total -= entry.size;
+        removed.push(entry);
+    }
+
+    Ok(removed)
+}
+
+/// File in cache directory which modification time is when [`gc_if_due`] last ran
+const GC_MARKER_NAME: &str = ".last_gc";
+
+/// How often [`gc_if_due`] evicts entries
+pub const GC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Evicts entries to fit into `cache_max_size` of the config, unless any process
+/// did it less than [`GC_INTERVAL`] ago
+pub fn gc_if_due(config: &crate::config::Config) {
+    let Some(max_size) = config.cache_max_size else {
+        return;
+    };
+    let Ok(cache_dir) = get_cache_dir(&config.cache_dir) else {
+        return;
+    };
+
+    let marker = cache_dir.join(GC_MARKER_NAME);
+    let recent = std::fs::metadata(&marker)
+        .and_then(|m| m.modified())
+        .is_ok_and(|t| t.elapsed().is_ok_and(|e| e < GC_INTERVAL));
+    if recent {
+        return;
+    }
+    if let Err(e) = std::fs::write(&marker, "") {
+        log_warn!(error:err = e; "could not update cache gc marker");
+    }
+
+    match gc(&cache_dir, max_size, false) {
+        Ok(removed) if !removed.is_empty() => {
+            log_info!(removed = removed.len(), freed = removed.iter().map(|e| e.size).sum::<u64>(), max_size = max_size; "cache evicted");
+        }
+        Ok(_) => {}
+        Err(e) => {
+            log_warn!(error:ah = &e; "cache gc failed");
+        }
+    }
+}
+
+/// Returns cache directories of other genvm versions
+///
+/// Works only if `cache_dir` is versioned, as in default `.../genvm/${genvmVersion}`
+pub fn other_versions(cache_dir: &Path) -> Result<Vec<PathBuf>> {
+    let is_version = |name: &str| {
+        name.strip_prefix('v')
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+    };
+
+    let cache_dir = cache_dir.canonicalize()?;
+    let current = cache_dir
+        .file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or_default();
+
+    if current != genvm_common::VERSION || !is_version(current) {
+        anyhow::bail!("cache_dir {cache_dir:?} does not end with genvm version");
+    }
+
+    let parent = cache_dir.parent().context("cache_dir has no parent")?;
+
+    let mut result = Vec::new();
+    for entry in std::fs::read_dir(parent)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name == current || !is_version(name) || !entry.file_type()?.is_dir() {
+            continue;
+        }
+        result.push(entry.path());
+    }
+
+    result.sort();
+
+    Ok(result)
+}
+
+/// Total size of a directory
+pub fn dir_size(path: &Path) -> Result<u64> {
+    Ok(dir_usage(path)?.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gc_evicts_least_recently_used() {
+        let cache_dir = std::env::temp_dir().join("genvm-test-cache-gc");
+        let _ = std::fs::remove_dir_all(&cache_dir);
+
+        let old_runner = cache_dir.join(PRECOMPILE_DIR_NAME).join("old").join("hash");
+        let new_file = cache_dir.join("wasmtime").join("new");
+        std::fs::create_dir_all(&old_runner).unwrap();
+        std::fs::create_dir_all(new_file.parent().unwrap()).unwrap();
+
+        std::fs::write(old_runner.join("a.det"), [0; 100]).unwrap();
+        std::fs::write(old_runner.join(PRECOMPILE_MANIFEST_NAME), [0; 100]).unwrap();
+        std::fs::write(&new_file, [0; 100]).unwrap();
+
+        let old_time = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        for name in ["a.det", PRECOMPILE_MANIFEST_NAME] {
+            let file = std::fs::File::options()
+                .write(true)
+                .open(old_runner.join(name))
+                .unwrap();
+            file.set_times(
+                std::fs::FileTimes::new()
+                    .set_accessed(old_time)
+                    .set_modified(old_time),
+            )
+            .unwrap();
+        }
+
+        let removed = gc(&cache_dir, 150, false).unwrap();
+
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].path, old_runner);
+        assert_eq!(removed[0].size, 200);
+        assert!(!old_runner.exists());
+        assert!(new_file.exists());
+    }
+}
@@ -1,4 +1,4 @@
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 
 #[derive(Deserialize)]
 pub struct Module {
@@ -11,10 +11,146 @@ pub struct Modules {
     pub web: Module,
 }
 
+/// Memory budgets in bytes, absent ones are unlimited
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default)]
+pub struct MemoryLimits {
+    /// shared by all deterministic VMs of a transaction
+    #[serde(default)]
+    pub det: Option<u32>,
+    /// shared by all non-deterministic VMs of a transaction
+    #[serde(default)]
+    pub non_det: Option<u32>,
+    /// for a single sandbox, in addition to the pool of its mode
+    #[serde(default)]
+    pub sandbox: Option<u32>,
+    /// for a single contract called from another one, in addition to deterministic pool
+    #[serde(default)]
+    pub nested: Option<u32>,
+}
+
+impl MemoryLimits {
+    /// Limits that are set in `other` take precedence
+    pub fn overridden_by(self, other: &Self) -> Self {
+        Self {
+            det: other.det.or(self.det),
+            non_det: other.non_det.or(self.non_det),
+            sandbox: other.sandbox.or(self.sandbox),
+            nested: other.nested.or(self.nested),
+        }
+    }
+}
+
+/// Time budgets in milliseconds, absent ones are unlimited
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+pub struct Timeouts {
+    /// whole transaction
+    #[serde(default)]
+    pub total_ms: Option<u64>,
+    /// each leader or validator VM of `RunNondet`
+    #[serde(default)]
+    pub nondet_ms: Option<u64>,
+    /// each request to web module
+    #[serde(default)]
+    pub web_request_ms: Option<u64>,
+    /// each request to llm module
+    #[serde(default)]
+    pub llm_request_ms: Option<u64>,
+}
+
+/// What to do when a contract is called while it is already on the call stack
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CyclePolicy {
+    /// recursion is bounded only by `max_depth`
+    #[default]
+    Allow,
+    /// calling a contract that is already on the stack, including the caller itself, fails
+    Forbid,
+}
+
+fn default_max_call_depth() -> u32 {
+    32
+}
+
+/// Limits of `CallContract`, violating them results in an error code returned to the caller
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct CallLimits {
+    /// how many callers a called contract may have
+    #[serde(default = "default_max_call_depth")]
+    pub max_depth: u32,
+    #[serde(default)]
+    pub cycles: CyclePolicy,
+}
+
+impl Default for CallLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: default_max_call_depth(),
+            cycles: CyclePolicy::default(),
+        }
+    }
+}
+
+fn default_mirror_connect_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_mirror_timeout_ms() -> u64 {
+    60_000
+}
+
+/// Where runner archives are found and which of them are trusted,
+/// in addition to their content hash matching the id
+#[derive(Deserialize, Clone, Debug)]
+pub struct Runners {
+    /// hex encoded ed25519 public keys, if any is set each runner must be signed by one of them
+    #[serde(default)]
+    pub publisher_keys: Vec<String>,
+    /// directories with `<name>/<hash>.tar` archives, searched in order. Defaults to the one
+    /// of the installation, runners fetched from `mirror` are installed into the first one
+    #[serde(default)]
+    pub search_paths: Vec<String>,
+    /// `http(s)://` url or directory with the same layout, missing runners are fetched from it
+    #[serde(default)]
+    pub mirror: Option<String>,
+    /// connecting to `http(s)://` mirror
+    #[serde(default = "default_mirror_connect_timeout_ms")]
+    pub mirror_connect_timeout_ms: u64,
+    /// whole download of a single file from `http(s)://` mirror
+    #[serde(default = "default_mirror_timeout_ms")]
+    pub mirror_timeout_ms: u64,
+}
+
+impl Default for Runners {
+    fn default() -> Self {
+        Self {
+            publisher_keys: Vec::new(),
+            search_paths: Vec::new(),
+            mirror: None,
+            mirror_connect_timeout_ms: default_mirror_connect_timeout_ms(),
+            mirror_timeout_ms: default_mirror_timeout_ms(),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct Config {
     pub modules: Modules,
     pub cache_dir: String,
+    /// whenever to snapshot runners after `_initialize` and restore them on next runs
+    #[serde(default)]
+    pub snapshots: bool,
+    /// if set, least recently used cache entries are evicted at most hourly to fit into this many bytes, see [`crate::caching::gc_if_due`]
+    #[serde(default)]
+    pub cache_max_size: Option<u64>,
+    #[serde(default)]
+    pub memory_limits: MemoryLimits,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    #[serde(default)]
+    pub call_limits: CallLimits,
+    #[serde(default)]
+    pub runners: Runners,
 
     #[serde(flatten)]
     pub base: genvm_common::BaseConfig,
@@ -19,6 +19,14 @@ impl VMError {
         VMError(public_abi::VmError::Oom.value().into(), cause)
     }
 
+    /// `what` names the budget that was exceeded, such as `total` or `nondet call 0`
+    pub fn timeout(what: &str) -> Self {
+        VMError(
+            format!("{} {what}", public_abi::VmError::Timeout.value()),
+            None,
+        )
+    }
+
     pub fn wrap(message: String, cause: anyhow::Error) -> Self {
         match cause.downcast::<VMError>() {
             Err(cause) => Self(message, Some(cause)),
@@ -38,8 +46,12 @@ pub fn unwrap_vm_errors(err: anyhow::Error) -> anyhow::Result<vm::RunOk> {
             Err(e) => Err(e),
         },
         |e: anyhow::Error| {
-            e.downcast::<wasmtime::Trap>()
-                .map(|v| vm::RunOk::VMError(format!("wasm_trap {v:?}"), Some(v.into())))
+            e.downcast::<wasmtime::Trap>().map(|v| match v {
+                wasmtime::Trap::OutOfFuel => {
+                    vm::RunOk::VMError(public_abi::VmError::OutOfGas.value().into(), Some(v.into()))
+                }
+                v => vm::RunOk::VMError(format!("wasm_trap {v:?}"), Some(v.into())),
+            })
         },
         |e: anyhow::Error| {
             e.downcast::<crate::errors::VMError>()
@@ -69,6 +81,7 @@ pub fn unwrap_vm_errors_fingerprint(
     let mut fingerprint = Fingerprint {
         frames: Vec::new(),
         module_instances: BTreeMap::new(),
+        call_stack: Vec::new(),
     };
 
     if let Some(bt) = err.downcast_ref::<wasmtime::WasmBacktrace>() {
@@ -96,6 +109,51 @@ pub fn unwrap_vm_errors_fingerprint(
     Ok((unwrap_vm_errors(err)?, fingerprint))
 }
 
+/// Why validator rejected the leader's result of a non-deterministic block,
+/// it is attached as a cause of `validator_disagrees` error
+#[derive(Debug, serde::Serialize)]
+pub struct DisagreementReport {
+    pub call_no: u32,
+    /// built-in strategy, absent if validator function was used
+    pub equivalence: Option<calldata::Value>,
+    pub leaders_result: calldata::Value,
+    /// verdict of validator function or own results of validator for built-in strategies
+    pub validator_results: Vec<calldata::Value>,
+}
+
+impl DisagreementReport {
+    /// Encoding used in the result sent to the host
+    pub fn to_calldata(&self) -> calldata::Value {
+        calldata::Value::Map(BTreeMap::from([
+            (
+                "call_no".to_owned(),
+                calldata::Value::Number(self.call_no.into()),
+            ),
+            (
+                "equivalence".to_owned(),
+                self.equivalence.clone().unwrap_or(calldata::Value::Null),
+            ),
+            ("leaders_result".to_owned(), self.leaders_result.clone()),
+            (
+                "validator_results".to_owned(),
+                calldata::Value::Array(self.validator_results.clone()),
+            ),
+        ]))
+    }
+}
+
+impl std::error::Error for DisagreementReport {}
+
+impl std::fmt::Display for DisagreementReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "validator disagrees with leader in call {}",
+            self.call_no
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct UserError(pub String);
 
@@ -116,9 +174,15 @@ pub struct Frame {
 #[derive(Debug, serde::Serialize)]
 pub struct SingleMemoryFP(#[serde(with = "serde_bytes")] pub [u8; 32]);
 
+#[derive(Debug, serde::Serialize)]
+pub struct StackAddress(#[serde(with = "serde_bytes")] pub [u8; 20]);
+
 #[derive(Debug, serde::Serialize)]
 pub struct Fingerprint {
     pub frames: Vec<Frame>,
 
     pub module_instances: BTreeMap<String, wasmtime::ModuleFingerprint>,
+
+    /// callers of the failed contract starting from the entry one, followed by the contract itself
+    pub call_stack: Vec<StackAddress>,
 }
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use genvm::{caching, config, snapshot, ustar::SharedBytes};
+
+use genvm_common::*;
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// show size of cache directory by category
+    Stats,
+    /// evict least recently used entries until cache fits into `--max-size` bytes
+    Gc {
+        #[arg(long)]
+        max_size: u64,
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// remove cache directories of other genvm versions
+    PruneVersions {
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// check precompiled modules and snapshots against runner archives and current engines
+    Verify {
+        #[arg(long, default_value_t = false, help = "remove invalid entries")]
+        remove: bool,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn stats(cache_dir: &Path) -> Result<()> {
+    let mut by_category: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+    for entry in caching::list_entries(cache_dir)? {
+        let cat = by_category.entry(entry.category).or_default();
+        cat.0 += 1;
+        cat.1 += entry.size;
+    }
+
+    let mut total = 0;
+    println!("{cache_dir:?}");
+    for (category, (count, size)) in &by_category {
+        println!("  {category}: {count} entries, {}", format_size(*size));
+        total += size;
+    }
+    println!("  total: {}", format_size(total));
+
+    match caching::other_versions(cache_dir) {
+        Ok(versions) => {
+            for path in versions {
+                println!(
+                    "other version {path:?}: {}",
+                    format_size(caching::dir_size(&path)?)
+                );
+            }
+        }
+        Err(e) => {
+            log_debug!(error:ah = &e; "can't list other versions");
+        }
+    }
+
+    Ok(())
+}
+
+fn gc(cache_dir: &Path, max_size: u64, dry_run: bool) -> Result<()> {
+    let removed = caching::gc(cache_dir, max_size, dry_run)?;
+
+    for entry in &removed {
+        println!("removed {:?} {}", entry.path, format_size(entry.size));
+    }
+    println!(
+        "freed {} in {} entries",
+        format_size(removed.iter().map(|e| e.size).sum()),
+        removed.len()
+    );
+
+    Ok(())
+}
+
+fn prune_versions(cache_dir: &Path, dry_run: bool) -> Result<()> {
+    let mut freed = 0;
+
+    for path in caching::other_versions(cache_dir)? {
+        let size = caching::dir_size(&path)?;
+        println!("removed {path:?} {}", format_size(size));
+
+        if !dry_run {
+            std::fs::remove_dir_all(&path).with_context(|| format!("removing {path:?}"))?;
+        }
+        freed += size;
+    }
+
+    println!("freed {}", format_size(freed));
+
+    Ok(())
+}
+
+/// Returns reason why precompiled runner directory is invalid
+fn verify_precompiled(
+    engines: &genvm::vm::Engines,
+    runners_dir: &Path,
+    dir: &Path,
+) -> Result<Option<String>> {
+    let Some(manifest) = caching::PrecompileManifest::load(dir)? else {
+        return Ok(Some("manifest is absent".into()));
+    };
+
+    let (Some(hash), Some(id)) = (dir.file_name(), dir.parent().and_then(|x| x.file_name())) else {
+        return Ok(Some("unexpected path".into()));
+    };
+
+    let mut arch_path = runners_dir.join(id).join(hash);
+    arch_path.set_extension("tar");
+    if !arch_path.exists() {
+        return Ok(Some(format!("runner archive {arch_path:?} is absent")));
+    }
+
+    let data = genvm::mmap::load_file(&arch_path, None)?;
+    let arch = genvm::ustar::Archive::from_ustar(SharedBytes::new(data))?;
+
+    for path_in_arch in manifest.entries.keys() {
+        let Some(contents) = arch.get(path_in_arch.as_str())? else {
+            return Ok(Some(format!(
+                "{path_in_arch} is absent from runner archive"
+            )));
+        };
+
+        if let Err(e) = manifest.verify(engines, path_in_arch, contents.as_ref()) {
+            return Ok(Some(format!("{e:#}")));
+        }
+
+        let compiled = dir.join(caching::path_in_zip_to_hash(path_in_arch));
+        for suff in [
+            caching::DET_NON_DET_PRECOMPILED_SUFFIX.det,
+            caching::DET_NON_DET_PRECOMPILED_SUFFIX.non_det,
+        ] {
+            if !compiled.with_extension(suff).is_file() {
+                return Ok(Some(format!("{suff} module of {path_in_arch} is absent")));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn verify_snapshots(dir: &Path) -> Result<Option<String>> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Err(e) = snapshot::Snapshot::load(&path) {
+            return Ok(Some(format!("snapshot {path:?}: {e:#}")));
+        }
+    }
+
+    Ok(None)
+}
+
+fn verify(cache_dir: &Path, remove: bool) -> Result<()> {
+    let engines = genvm::vm::Engines::create(|_| Ok(()))?;
+    let runners_dir = genvm::runner::path()?;
+
+    let mut invalid: Vec<(PathBuf, String)> = Vec::new();
+    let mut checked = 0;
+
+    for entry in caching::list_entries(cache_dir)? {
+        let res = if entry.category == caching::PRECOMPILE_DIR_NAME {
+            verify_precompiled(&engines, &runners_dir, &entry.path)
+        } else if entry.category == caching::SNAPSHOT_DIR_NAME {
+            verify_snapshots(&entry.path)
+        } else {
+            // wasmtime verifies its own cache on load
+            continue;
+        };
+
+        checked += 1;
+
+        match res.with_context(|| format!("verifying {:?}", entry.path))? {
+            None => {}
+            Some(reason) => invalid.push((entry.path, reason)),
+        }
+    }
+
+    for (path, reason) in &invalid {
+        println!("invalid {path:?}: {reason}");
+        if remove {
+            caching::remove_entry(path)?;
+        }
+    }
+    println!("checked {checked} entries, {} invalid", invalid.len());
+
+    if !invalid.is_empty() && !remove {
+        anyhow::bail!("cache contains invalid entries, use --remove to delete them");
+    }
+
+    Ok(())
+}
+
+pub fn handle(args: Args, config: config::Config) -> Result<()> {
+    let cache_dir = caching::get_cache_dir(&config.cache_dir)?;
+
+    match args.command {
+        Command::Stats => stats(&cache_dir),
+        Command::Gc { max_size, dry_run } => gc(&cache_dir, max_size, dry_run),
+        Command::PruneVersions { dry_run } => prune_versions(&cache_dir, dry_run),
+        Command::Verify { remove } => verify(&cache_dir, remove),
+    }
+}
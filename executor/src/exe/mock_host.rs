@@ -0,0 +1,76 @@
+use anyhow::{Context, Result};
+use genvm::{config, mock};
+
+use genvm_common::*;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[arg(long, help = "state file, .json or .yaml")]
+    state: std::path::PathBuf,
+    #[arg(long, help = "where to write resulting state, defaults to --state")]
+    out: Option<std::path::PathBuf>,
+    #[arg(
+        long,
+        help = "address to listen on, unix://, shm:// (unix socket + shared memory) or tcp"
+    )]
+    listen: String,
+    #[arg(long, help = "message that will be passed to genvm")]
+    message: String,
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "must match `--host-protocol` of `genvm run`"
+    )]
+    host_protocol: u32,
+}
+
+fn serve_one(host: &mut mock::MockHost, listen: &str, protocol_version: u32) -> Result<()> {
+    const UNIX: &str = "unix://";
+    const SHM: &str = "shm://";
+    if let Some(path) = listen.strip_prefix(SHM) {
+        let _ = std::fs::remove_file(path);
+        let listener = std::os::unix::net::UnixListener::bind(path)
+            .with_context(|| format!("binding {listen}"))?;
+
+        log_info!(address = listen; "mock host is listening");
+
+        let (stream, _) = listener.accept()?;
+        host.protocol_version = protocol_version;
+        host.serve_shm(stream)
+    } else if let Some(path) = listen.strip_prefix(UNIX) {
+        let _ = std::fs::remove_file(path);
+        let listener = std::os::unix::net::UnixListener::bind(path)
+            .with_context(|| format!("binding {listen}"))?;
+
+        log_info!(address = listen; "mock host is listening");
+
+        let (stream, _) = listener.accept()?;
+        host.serve(stream.try_clone()?, stream, protocol_version)
+    } else {
+        let listener =
+            std::net::TcpListener::bind(listen).with_context(|| format!("binding {listen}"))?;
+
+        log_info!(address = listen; "mock host is listening");
+
+        let (stream, _) = listener.accept()?;
+        host.serve(stream.try_clone()?, stream, protocol_version)
+    }
+}
+
+pub fn handle(args: Args, _config: config::Config) -> Result<()> {
+    let message: genvm::MessageData = serde_json::from_str(&args.message)?;
+    let state = mock::MockState::load(&args.state)?;
+
+    let mut host = mock::MockHost::new(state, message.contract_address);
+
+    let res = serve_one(&mut host, &args.listen, args.host_protocol);
+    if let Err(err) = &res {
+        log_error!(error:ah = err; "serving genvm failed");
+    }
+
+    host.state
+        .save(args.out.as_ref().unwrap_or(&args.state))
+        .with_context(|| "saving state")?;
+
+    res
+}
@@ -1,2 +1,8 @@
+pub mod cache;
+pub mod mock_host;
+pub mod output;
 pub mod precompile;
+pub mod replay;
 pub mod run;
+pub mod runners;
+pub mod serve;
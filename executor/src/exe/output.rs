@@ -0,0 +1,102 @@
+//! Machine readable result of a single transaction, used by `--print json` and `genvm serve`
+
+use anyhow::Result;
+use genvm::{
+    calldata,
+    vm::{self, RunOk, SupervisorStats},
+};
+
+#[derive(serde::Serialize)]
+pub struct JsonMemory {
+    pub det: u32,
+    pub non_det: u32,
+}
+
+#[derive(serde::Serialize)]
+pub struct JsonTiming {
+    pub setup_ms: f64,
+    pub run_ms: f64,
+}
+
+#[derive(serde::Serialize)]
+pub struct JsonOutput {
+    /// `return`, `user_error`, `vm_error` or `internal_error`
+    kind: &'static str,
+    /// decoded calldata for `return`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<calldata::Value>,
+    /// hex of raw returned bytes, present even if they can't be decoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    fingerprint: Option<serde_json::Value>,
+    /// why validator rejected leader's result, for `validator_disagrees`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disagreement: Option<serde_json::Value>,
+    gas_used: Option<u64>,
+    stats: Option<SupervisorStats>,
+    memory_high_water: Option<JsonMemory>,
+    /// per VM, in order of spawning
+    memory_per_vm: Option<Vec<vm::VmMemoryReport>>,
+    timing: JsonTiming,
+}
+
+impl JsonOutput {
+    pub fn new(res: &Result<vm::FullRunOk>, timing: JsonTiming) -> Self {
+        let mut out = Self {
+            kind: "internal_error",
+            value: None,
+            raw: None,
+            message: None,
+            fingerprint: None,
+            disagreement: None,
+            gas_used: None,
+            stats: None,
+            memory_high_water: None,
+            memory_per_vm: None,
+            timing,
+        };
+
+        match res {
+            Ok((res, fp)) => {
+                out.fingerprint = fp.as_ref().and_then(|fp| serde_json::to_value(fp).ok());
+                match res {
+                    RunOk::Return(data) => {
+                        out.kind = "return";
+                        out.value = calldata::decode(data).ok();
+                        out.raw = Some(hex::encode(data));
+                    }
+                    RunOk::UserError(msg) => {
+                        out.kind = "user_error";
+                        out.message = Some(msg.clone());
+                    }
+                    RunOk::VMError(msg, cause) => {
+                        out.kind = "vm_error";
+                        out.message = Some(msg.clone());
+                        out.disagreement = cause
+                            .as_ref()
+                            .and_then(|c| c.downcast_ref::<genvm::errors::DisagreementReport>())
+                            .and_then(|report| serde_json::to_value(report).ok());
+                    }
+                }
+            }
+            Err(err) => {
+                out.message = Some(format!("{err:#}"));
+            }
+        }
+
+        out
+    }
+
+    /// Adds gas, stats and memory usage of the transaction that supervisor has just run
+    pub fn add_supervisor(&mut self, supervisor: &vm::Supervisor) {
+        self.gas_used = Some(supervisor.shared_data.gas_used());
+        self.stats = Some(supervisor.stats().clone());
+        self.memory_high_water = Some(JsonMemory {
+            det: supervisor.shared_data.limiter_det.high_water_mark(),
+            non_det: supervisor.shared_data.limiter_non_det.high_water_mark(),
+        });
+        self.memory_per_vm = Some(supervisor.shared_data.memory_report());
+    }
+}
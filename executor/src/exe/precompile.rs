@@ -1,3 +1,5 @@
+use std::sync::Mutex;
+
 use anyhow::{Context, Result};
 use clap::builder::OsStr;
 use genvm::{caching, config, ustar::SharedBytes};
@@ -12,6 +14,15 @@ pub struct Args {
         help = "instead of precompiling show information"
     )]
     info: bool,
+
+    #[arg(
+        long,
+        help = "how many archives to compile at the same time, defaults to amount of cpus"
+    )]
+    jobs: Option<usize>,
+
+    #[arg(help = "runners to precompile, either `<id>` or `<id>:<hash>`, all if omitted")]
+    runners: Vec<String>,
 }
 
 fn compile_single_file_single_mode(
@@ -59,12 +70,20 @@ fn compile_single_file(
     let mut result_dir_path = precompile_dir.to_owned();
     result_dir_path.push(base_path);
 
+    // manifest must not describe files that are being overwritten
+    match std::fs::remove_file(result_dir_path.join(caching::PRECOMPILE_MANIFEST_NAME)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
     let data = genvm::mmap::load_file(zip_path, None)?;
 
     let arch = genvm::ustar::Archive::from_ustar(SharedBytes::new(data))?;
 
+    let mut manifest = caching::PrecompileManifest::new(engines);
+
     for (entry_name, contents) in arch
-        .data
+        .files()?
         .iter()
         .filter(|(k, _v)| k.ends_with(".wasm") || k.ends_with(".so"))
     {
@@ -98,10 +117,66 @@ fn compile_single_file(
             entry_name,
         )
         .with_context(|| format!("processing non-det {entry_name}"))?;
+
+        manifest.entries.insert(
+            entry_name.to_string(),
+            caching::PrecompiledEntry {
+                source_hash: caching::source_hash(contents.as_ref()),
+            },
+        );
     }
+
+    std::fs::create_dir_all(&result_dir_path)?;
+    manifest.save(&result_dir_path)?;
+
     Ok(())
 }
 
+/// Lists runner archives as `(id, hash, path)`
+fn list_runners(
+    runners_dir: &std::path::Path,
+) -> Result<Vec<(String, String, std::path::PathBuf)>> {
+    let mut result = Vec::new();
+
+    for runner_id in std::fs::read_dir(runners_dir)? {
+        let runner_id = runner_id?;
+        if !runner_id.file_type()?.is_dir() {
+            continue;
+        }
+        for zip_path in std::fs::read_dir(runner_id.path())? {
+            let zip_path = zip_path?;
+            if !zip_path.file_type()?.is_file() {
+                continue;
+            }
+            let zip_path = zip_path.path();
+            if zip_path.extension() != Some(&OsStr::from("tar")) {
+                continue;
+            }
+            let Some(hash) = zip_path.file_stem() else {
+                continue;
+            };
+
+            result.push((
+                runner_id.file_name().to_string_lossy().into_owned(),
+                hash.to_string_lossy().into_owned(),
+                zip_path.clone(),
+            ));
+        }
+    }
+
+    result.sort();
+
+    Ok(result)
+}
+
+fn is_selected(selection: &[String], id: &str, hash: &str) -> bool {
+    selection.is_empty()
+        || selection.iter().any(|sel| match sel.split_once(':') {
+            Some((sel_id, sel_hash)) => sel_id == id && sel_hash == hash,
+            None => sel == id,
+        })
+}
+
 pub fn handle(args: Args, config: config::Config) -> Result<()> {
     log_info!(version = genvm_common::version::CURRENT.clone(); "current version");
 
@@ -121,24 +196,57 @@ pub fn handle(args: Args, config: config::Config) -> Result<()> {
 
     let runners_dir = genvm::runner::path()?;
 
-    for runner_id in std::fs::read_dir(&runners_dir)? {
-        let runner_id = runner_id?;
-        if !runner_id.file_type()?.is_dir() {
-            continue;
+    let all_runners = list_runners(&runners_dir)?;
+
+    for sel in &args.runners {
+        let found = all_runners
+            .iter()
+            .any(|(id, hash, _)| is_selected(std::slice::from_ref(sel), id, hash));
+        if !found {
+            anyhow::bail!("runner {sel} not found in {runners_dir:?}");
         }
-        for zip_path in std::fs::read_dir(runner_id.path())? {
-            let zip_path = zip_path?;
-            if !zip_path.file_type()?.is_file() {
-                continue;
-            }
-            let zip_path = zip_path.path();
-            if zip_path.extension() != Some(&OsStr::from("tar")) {
-                continue;
-            }
+    }
 
-            compile_single_file(&precompile_dir, &engines, &runners_dir, &zip_path)
-                .with_context(|| format!("processing {zip_path:?}"))?;
+    let queue = Mutex::new(
+        all_runners
+            .into_iter()
+            .filter(|(id, hash, _)| is_selected(&args.runners, id, hash))
+            .map(|(_, _, path)| path)
+            .collect::<Vec<_>>(),
+    );
+
+    let jobs = args
+        .jobs
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|x| x.get())
+                .unwrap_or(1)
+        })
+        .max(1);
+
+    let failures = Mutex::new(Vec::new());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let Some(zip_path) = queue.lock().unwrap().pop() else {
+                    break;
+                };
+
+                if let Err(e) =
+                    compile_single_file(&precompile_dir, &engines, &runners_dir, &zip_path)
+                        .with_context(|| format!("processing {zip_path:?}"))
+                {
+                    log_error!(error:ah = &e; "precompilation failed");
+                    failures.lock().unwrap().push(zip_path);
+                }
+            });
         }
+    });
+
+    let failures = failures.into_inner().unwrap();
+    if !failures.is_empty() {
+        anyhow::bail!("failed to precompile {failures:?}");
     }
 
     Ok(())
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use genvm::{calldata, config, public_abi::ResultCode, record, PublicArgs};
+
+use genvm_common::*;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[arg(help = "file produced by `genvm run --record`")]
+    file: std::path::PathBuf,
+}
+
+/// Human readable form of `consume_result` payload, which carries used gas since
+/// [`genvm::Host::GAS_PROTOCOL_VERSION`]
+fn describe(data: &[u8], host_protocol: u32) -> String {
+    let parse = || -> Result<String> {
+        let len = u32::from_le_bytes(data.get(..4).context("truncated")?.try_into()?) as usize;
+        let encoded = data.get(4..4 + len).context("truncated")?;
+        let rest = data.get(4 + len..).context("truncated")?;
+
+        let gas = if host_protocol >= genvm::Host::GAS_PROTOCOL_VERSION {
+            let gas = u64::from_le_bytes(rest.try_into()?);
+            format!(" gas_used={gas}")
+        } else if rest.is_empty() {
+            String::new()
+        } else {
+            anyhow::bail!("trailing bytes")
+        };
+
+        let (code, rest) = encoded.split_first().context("empty result")?;
+        let code = ResultCode::try_from(*code)
+            .map(|x| x.str_snake_case().to_owned())
+            .unwrap_or_else(|_| format!("unknown({code})"));
+
+        let value = match calldata::decode(rest) {
+            Ok(v) => format!("{v:?}"),
+            Err(_) => format!("{rest:?}"),
+        };
+
+        Ok(format!("{code}{gas} {value}"))
+    };
+
+    parse().unwrap_or_else(|e| format!("<invalid: {e}>"))
+}
+
+pub fn handle(args: Args, config: config::Config) -> Result<()> {
+    let recording = record::Recording::load(&args.file)?;
+
+    if recording.genvm_version != genvm_common::VERSION {
+        log_warn!(recorded = recording.genvm_version, current = genvm_common::VERSION; "replaying recording made by another genvm version");
+    }
+
+    let replayer = Arc::new(record::Replayer::new(&recording));
+
+    let runtime = config.base.create_rt()?;
+    let (token, _canceller) = genvm_common::cancellation::make();
+
+    let res = runtime.block_on(async {
+        let host = genvm::Host::replay(replayer.clone(), recording.host_protocol);
+
+        let supervisor = genvm::create_supervisor(
+            &config,
+            host,
+            token,
+            recording.host_data.clone(),
+            PublicArgs {
+                cookie: "replay".into(),
+                is_sync: recording.is_sync,
+                allow_latest: recording.allow_latest,
+                message: &recording.message,
+                tape: record::Tape::Replay(replayer.clone()),
+            },
+        )
+        .await?;
+
+        genvm::run_with(
+            recording.message.clone(),
+            supervisor,
+            &recording.permissions,
+        )
+        .await
+    });
+
+    if let Err(err) = &res {
+        log_error!(error:ah = err; "error replaying");
+    }
+
+    let (unused_host, unused_modules) = replayer.unused();
+    if unused_host != 0 || unused_modules != 0 {
+        log_warn!(host = unused_host, modules = unused_modules; "recorded exchanges were not requested during replay");
+    }
+
+    let recorded = recording.result.map(|x| x.0);
+    let replayed = replayer.result();
+
+    if recorded == replayed {
+        println!("result and fingerprint match the recording");
+        if let Some(data) = &recorded {
+            println!("{}", describe(data, recording.host_protocol));
+        }
+        return Ok(());
+    }
+
+    let show = |x: &Option<Vec<u8>>| match x {
+        Some(data) => describe(data, recording.host_protocol),
+        None => "<no result>".to_owned(),
+    };
+
+    println!("result differs from the recording");
+    println!("- recorded: {}", show(&recorded));
+    println!("+ replayed: {}", show(&replayed));
+
+    anyhow::bail!("replay diverged")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(result: &[u8], gas: Option<u64>) -> Vec<u8> {
+        let mut data = Vec::from((result.len() as u32).to_le_bytes());
+        data.extend_from_slice(result);
+        if let Some(gas) = gas {
+            data.extend_from_slice(&gas.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn describe_result() {
+        let mut result = vec![ResultCode::Return as u8];
+        result.extend_from_slice(&calldata::encode(&calldata::Value::Null));
+
+        assert_eq!(describe(&payload(&result, None), 1), "return null");
+        assert_eq!(
+            describe(
+                &payload(&result, Some(42)),
+                genvm::Host::GAS_PROTOCOL_VERSION
+            ),
+            "return gas_used=42 null"
+        );
+        assert!(describe(&payload(&result, Some(42)), 1).starts_with("<invalid"));
+    }
+}
@@ -1,10 +1,13 @@
 use std::io::Write;
+use std::sync::Arc;
 
 use genvm_common::*;
 
 use anyhow::{Context, Result};
 use clap::ValueEnum;
-use genvm::{config, vm::RunOk, PublicArgs};
+use genvm::{config, record, vm::RunOk, PublicArgs};
+
+use super::output::{JsonOutput, JsonTiming};
 
 #[derive(Debug, Clone, ValueEnum, PartialEq, Eq)]
 #[clap(rename_all = "kebab_case")]
@@ -12,6 +15,8 @@ enum PrintOption {
     Result,
     Fingerprint,
     StderrFull,
+    /// single json object with result, fingerprint, stats and timing
+    Json,
 }
 
 impl std::fmt::Display for PrintOption {
@@ -69,29 +74,59 @@ pub struct Args {
     #[clap(
         long,
         default_value = "rwscn",
-        help = "r?w?s?c?n?, read/write/send messages/call contracts/spawn nondet"
+        help = "r?w?s?c?n?o?, read/write/send messages/call contracts/spawn nondet/read other contracts"
     )]
     permissions: String,
 
     #[clap(long, default_value = "{}", help = "value to pass to modules")]
     host_data: String,
-}
 
-pub fn handle(args: Args, config: config::Config) -> Result<()> {
-    let message: genvm::MessageData = serde_json::from_str(&args.message)?;
+    #[clap(
+        long,
+        default_value_t = 1,
+        help = "version of host protocol that host supports, 2 enables storage write batching, 3 enables pipelining, 4 enables account writes, 5 enables event names, 6 enables gas limit and used gas reporting; see doc/internal/node.md#protocol-versions"
+    )]
+    host_protocol: u32,
 
-    let host = genvm::Host::new(&args.host)?;
+    #[clap(
+        long,
+        help = "write all host and module interactions to this file, see `genvm replay`"
+    )]
+    record: Option<std::path::PathBuf>,
+}
 
+pub fn check_permissions(permissions: &str) -> Result<()> {
     let mut perm_size = 0;
-    for perm in ["r", "w", "s", "c", "n"] {
-        if args.permissions.contains(perm) {
+    for perm in ["r", "w", "s", "c", "n", "o"] {
+        if permissions.contains(perm) {
             perm_size += 1;
         }
     }
 
-    if perm_size != args.permissions.len() {
-        anyhow::bail!("Invalid permissions {}", &args.permissions)
+    if perm_size != permissions.len() {
+        anyhow::bail!("Invalid permissions {}", permissions)
+    }
+
+    Ok(())
+}
+
+pub fn make_cookie() -> String {
+    let mut cookie = [0; 8];
+    let _ = getrandom::fill(&mut cookie);
+
+    let mut cookie_str = String::new();
+    for c in cookie {
+        cookie_str.push_str(&format!("{c:x}"));
     }
+    cookie_str
+}
+
+pub fn handle(args: Args, config: config::Config) -> Result<()> {
+    let message: genvm::MessageData = serde_json::from_str(&args.message)?;
+
+    check_permissions(&args.permissions)?;
+
+    genvm::caching::gc_if_due(&config);
 
     let runtime = config.base.create_rt()?;
 
@@ -106,37 +141,62 @@ pub fn handle(args: Args, config: config::Config) -> Result<()> {
         signal_hook::low_level::register(signal_hook::consts::SIGINT, handle_sigterm)?;
     }
 
-    let host_data = serde_json::from_str(&args.host_data)?;
+    let host_data: genvm_modules_interfaces::HostData = serde_json::from_str(&args.host_data)?;
 
     let cookie = match &args.cookie {
-        None => {
-            let mut cookie = [0; 8];
-            let _ = getrandom::fill(&mut cookie);
-
-            let mut cookie_str = String::new();
-            for c in cookie {
-                cookie_str.push_str(&format!("{c:x}"));
-            }
-            cookie_str
-        }
+        None => make_cookie(),
         Some(v) => v.clone(),
     };
 
     log_info!(cookie = cookie; "genvm cookie");
 
-    let supervisor = genvm::create_supervisor(
-        &config,
-        host,
-        token,
-        host_data,
-        PublicArgs {
-            cookie,
+    let recorder = args.record.as_ref().map(|_| {
+        Arc::new(record::Recorder::new(record::Recording {
+            genvm_version: genvm_common::VERSION.to_owned(),
+            message: message.clone(),
+            permissions: args.permissions.clone(),
+            host_data: host_data.clone(),
+            host_protocol: args.host_protocol,
             is_sync: args.sync,
             allow_latest: args.allow_latest,
-            message: &message,
-        },
-    )
-    .with_context(|| "creating supervisor")?;
+            host: Vec::new(),
+            modules: Vec::new(),
+            result: None,
+        }))
+    });
+
+    let tape = match &recorder {
+        Some(recorder) => record::Tape::Record(recorder.clone()),
+        None => record::Tape::None,
+    };
+
+    let setup_start = std::time::Instant::now();
+
+    let supervisor = runtime
+        .block_on(async {
+            let mut host = genvm::Host::new(&args.host, args.host_protocol).await?;
+            if let Some(recorder) = &recorder {
+                host.record_to(recorder.clone());
+            }
+
+            genvm::create_supervisor(
+                &config,
+                host,
+                token,
+                host_data,
+                PublicArgs {
+                    cookie,
+                    is_sync: args.sync,
+                    allow_latest: args.allow_latest,
+                    message: &message,
+                    tape,
+                },
+            )
+            .await
+        })
+        .with_context(|| "creating supervisor")?;
+
+    let run_start = std::time::Instant::now();
 
     let res = runtime
         .block_on(genvm::run_with(
@@ -146,10 +206,21 @@ pub fn handle(args: Args, config: config::Config) -> Result<()> {
         ))
         .with_context(|| "running genvm");
 
+    let timing = JsonTiming {
+        setup_ms: (run_start - setup_start).as_secs_f64() * 1000.0,
+        run_ms: run_start.elapsed().as_secs_f64() * 1000.0,
+    };
+
     if let Err(err) = &res {
         log_error!(error:ah = err; "error running genvm");
     }
 
+    if let (Some(recorder), Some(path)) = (&recorder, &args.record) {
+        if let Err(err) = recorder.finish().and_then(|rec| rec.save(path)) {
+            log_error!(error:ah = &err; "saving recording failed");
+        }
+    }
+
     if args.print.contains(&PrintOption::StderrFull) {
         eprintln!("{res:?}");
     }
@@ -178,6 +249,16 @@ pub fn handle(args: Args, config: config::Config) -> Result<()> {
         }
     }
 
+    if args.print.contains(&PrintOption::Json) {
+        let mut out = JsonOutput::new(&res, timing);
+
+        runtime.block_on(async {
+            out.add_supervisor(&*supervisor.lock().await);
+        });
+
+        println!("{}", serde_json::to_string(&out)?);
+    }
+
     runtime.block_on(async {
         let supervisor = supervisor.lock().await;
         supervisor.shared_data.modules.llm.close().await;
@@ -194,3 +275,18 @@ pub fn handle(args: Args, config: config::Config) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissions() {
+        check_permissions("rwscn").unwrap();
+        check_permissions("rwscno").unwrap();
+        check_permissions("o").unwrap();
+
+        assert!(check_permissions("rwscnx").is_err());
+        assert!(check_permissions("rr").is_err());
+    }
+}
@@ -0,0 +1,587 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use genvm::{
+    config, public_abi,
+    registry::Registry,
+    runner::{ConditionCtx, InitAction, WasmMode, ZipCache},
+    ustar::SharedBytes,
+};
+use genvm_common::version::Version;
+use serde::Serialize;
+use symbol_table::GlobalSymbol;
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+#[clap(rename_all = "kebab_case")]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+#[clap(rename_all = "kebab_case")]
+enum Mode {
+    Det,
+    Nondet,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+#[clap(rename_all = "kebab_case")]
+enum EntryKind {
+    Main,
+    Sandbox,
+    ConsensusStage,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// list runners installed in search paths
+    List,
+    /// resolve action tree of a contract or a runner without running it and report problems
+    Check {
+        #[arg(help = "contract code file, or runner id `<name>:<hash>`")]
+        target: String,
+        #[arg(long, value_enum, default_value_t = Mode::Det, help = "mode for `When` conditions")]
+        mode: Mode,
+        #[arg(long, value_enum, default_value_t = EntryKind::Main, help = "entry kind for `When` conditions")]
+        entry_kind: EntryKind,
+        #[arg(
+            long,
+            help = "GenVM version for `When` conditions, current one by default"
+        )]
+        genvm_version: Option<Version>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+#[derive(Serialize)]
+struct Node {
+    action: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    unreachable: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    problems: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(action: String, unreachable: bool) -> Self {
+        Self {
+            action,
+            unreachable,
+            problems: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    fn problems_count(&self) -> usize {
+        self.problems.len()
+            + self
+                .children
+                .iter()
+                .map(Node::problems_count)
+                .sum::<usize>()
+    }
+
+    fn print(&self, indent: usize) {
+        let pad = "  ".repeat(indent);
+        let suffix = if self.unreachable {
+            " (unreachable)"
+        } else {
+            ""
+        };
+        println!("{pad}{}{suffix}", self.action);
+        for problem in &self.problems {
+            println!("{pad}  ! {problem}");
+        }
+        for child in &self.children {
+            child.print(indent + 1);
+        }
+    }
+}
+
+/// Mirrors `Supervisor::apply_action_recursive`, but only loads archives
+struct Resolver<'a> {
+    registry: &'a Registry,
+    archives: BTreeMap<GlobalSymbol, ZipCache>,
+    contract_id: GlobalSymbol,
+    mode: WasmMode,
+    genvm_version: Version,
+    contract_version: Version,
+    entry_kind: public_abi::EntryKind,
+    env: BTreeMap<String, String>,
+
+    visited: BTreeSet<GlobalSymbol>,
+    stack: Vec<GlobalSymbol>,
+    started: bool,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(
+        registry: &'a Registry,
+        mode: WasmMode,
+        genvm_version: Version,
+        entry_kind: public_abi::EntryKind,
+    ) -> Self {
+        Self {
+            registry,
+            archives: BTreeMap::new(),
+            contract_id: GlobalSymbol::from("<contract>"),
+            mode,
+            genvm_version,
+            contract_version: Version::ZERO,
+            entry_kind,
+            env: BTreeMap::new(),
+            visited: BTreeSet::new(),
+            stack: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Same as `Supervisor::unfold_test_id_if_any` with latest runners allowed
+    fn unfold(&self, id: GlobalSymbol) -> GlobalSymbol {
+        if id.as_str() == "<contract>" {
+            return self.contract_id;
+        }
+
+        match genvm::runner::verify_runner(id.as_str()) {
+            Ok((runner_id, "test" | "latest")) => match self.registry.latest(runner_id) {
+                Some(hash) => GlobalSymbol::from(format!("{runner_id}:{hash}")),
+                None => id,
+            },
+            _ => id,
+        }
+    }
+
+    fn load(&mut self, id: GlobalSymbol) -> Result<&mut ZipCache, String> {
+        if !self.archives.contains_key(&id) {
+            let arch = self
+                .registry
+                .load(id)
+                .map_err(|e| format!("missing dependency: {e:#}"))?;
+            self.archives.insert(id, ZipCache::new(id, arch));
+        }
+        Ok(self.archives.get_mut(&id).unwrap())
+    }
+
+    fn path_problem(&self, runner: GlobalSymbol, file: &str) -> Option<String> {
+        let arch = self.archives.get(&runner)?;
+        let found = if file.ends_with('/') {
+            arch.files.with_prefix(file).map(|files| !files.is_empty())
+        } else {
+            arch.files.get(file).map(|x| x.is_some())
+        };
+        match found {
+            Ok(true) => None,
+            Ok(false) => Some(format!("invalid path: no `{file}` in {runner}")),
+            Err(e) => Some(format!("invalid archive {runner}: {e:#}")),
+        }
+    }
+
+    fn resolve(&mut self, action: &InitAction, current: GlobalSymbol) -> Node {
+        let unreachable = self.started;
+
+        match action {
+            InitAction::MapFile { to, file } => {
+                let mut node = Node::new(format!("MapFile {file} -> {to}"), unreachable);
+                node.problems.extend(self.path_problem(current, file));
+                node
+            }
+            InitAction::AddEnv { name, val } => {
+                let mut node = Node::new(format!("AddEnv {name}={val}"), unreachable);
+                match genvm_common::templater::patch_str(
+                    &self.env,
+                    val,
+                    &genvm_common::templater::DOLLAR_UNFOLDER_RE,
+                ) {
+                    Ok(val) => {
+                        self.env.insert(name.clone(), val);
+                    }
+                    Err(e) => node.problems.push(format!("invalid value: {e:#}")),
+                }
+                node
+            }
+            InitAction::SetArgs(args) => Node::new(format!("SetArgs {args:?}"), unreachable),
+            InitAction::LinkWasm(file) => {
+                let mut node = Node::new(format!("LinkWasm {file}"), unreachable);
+                node.problems.extend(self.path_problem(current, file));
+                node
+            }
+            InitAction::StartWasm(file) => {
+                let mut node = Node::new(format!("StartWasm {file}"), unreachable);
+                node.problems.extend(self.path_problem(current, file));
+                if unreachable {
+                    node.problems
+                        .push("unreachable: previous action already started wasm".into());
+                }
+                self.started = true;
+                node
+            }
+            InitAction::When { cond, action } => {
+                let mut node = Node::new(format!("When {cond:?}"), unreachable);
+                let holds = cond.holds(&ConditionCtx {
+                    mode: self.mode,
+                    genvm_version: self.genvm_version,
+                    contract_version: self.contract_version,
+                    entry_kind: self.entry_kind,
+                    env: &self.env,
+                });
+                if holds {
+                    node.children.push(self.resolve(action, current));
+                } else {
+                    // inactive with given facts, but its dependencies must still exist
+                    let saved = (self.visited.clone(), self.started, self.env.clone());
+                    node.children.push(self.resolve(action, current));
+                    (self.visited, self.started, self.env) = saved;
+                    node.action.push_str(" (inactive)");
+                }
+                node
+            }
+            InitAction::Seq(actions) => {
+                let mut node = Node::new("Seq".into(), unreachable);
+                for act in actions {
+                    node.children.push(self.resolve(act, current));
+                }
+                node
+            }
+            InitAction::With { runner, action } => {
+                let id = self.unfold(*runner);
+                let mut node = Node::new(format!("With {id}"), unreachable);
+                match self.load(id) {
+                    Ok(_) => node.children.push(self.resolve(action, id)),
+                    Err(e) => node.problems.push(e),
+                }
+                node
+            }
+            InitAction::Depends(runner) => {
+                let id = self.unfold(*runner);
+                let mut node = Node::new(format!("Depends {id}"), unreachable);
+
+                if let Some(pos) = self.stack.iter().position(|x| *x == id) {
+                    let cycle = self.stack[pos..]
+                        .iter()
+                        .chain(std::iter::once(&id))
+                        .map(|x| x.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" -> ");
+                    node.problems.push(format!("cycle: {cycle}"));
+                    return node;
+                }
+
+                if !self.visited.insert(id) {
+                    node.action.push_str(" (already applied)");
+                    return node;
+                }
+
+                let actions = match self.load(id).and_then(|arch| {
+                    arch.get_actions()
+                        .map_err(|e| format!("invalid runner.json: {e:#}"))
+                }) {
+                    Ok(actions) => actions,
+                    Err(e) => {
+                        node.problems.push(e);
+                        return node;
+                    }
+                };
+
+                self.stack.push(id);
+                node.children.push(self.resolve(&actions, id));
+                self.stack.pop();
+
+                node
+            }
+        }
+    }
+
+    /// Tree of `root` with all its actions, `root` is loaded from registry
+    /// unless its archive is already present
+    fn resolve_root(&mut self, root: GlobalSymbol) -> Result<Node> {
+        let mut tree = Node::new(root.as_str().to_owned(), false);
+        match self.load(root).and_then(|arch| {
+            arch.get_actions()
+                .map_err(|e| format!("invalid runner.json: {e:#}"))
+        }) {
+            Ok(actions) => {
+                if root == self.contract_id {
+                    self.contract_version = self.archives[&root].get_version()?;
+                }
+                self.visited.insert(root);
+                self.stack.push(root);
+                tree.children.push(self.resolve(&actions, root));
+                if !self.started {
+                    tree.problems.push("no StartWasm is reachable".into());
+                }
+            }
+            Err(e) => tree.problems.push(e),
+        }
+
+        Ok(tree)
+    }
+}
+
+fn list(registry: &Registry, format: Format) -> Result<()> {
+    let runners = registry.list()?;
+
+    if format == Format::Json {
+        println!("{}", serde_json::to_string_pretty(&runners)?);
+        return Ok(());
+    }
+
+    for runner in &runners {
+        let latest = if registry.latest(&runner.name).as_ref() == Some(&runner.hash) {
+            " (latest)"
+        } else {
+            ""
+        };
+        println!("{}:{}{latest}\t{:?}", runner.name, runner.hash, runner.path);
+    }
+
+    Ok(())
+}
+
+struct Facts {
+    mode: Mode,
+    entry_kind: EntryKind,
+    genvm_version: Option<Version>,
+}
+
+fn check(registry: &Registry, target: &str, facts: Facts, format: Format) -> Result<()> {
+    let mut resolver = Resolver::new(
+        registry,
+        match facts.mode {
+            Mode::Det => WasmMode::Det,
+            Mode::Nondet => WasmMode::Nondet,
+        },
+        facts
+            .genvm_version
+            .unwrap_or(*genvm_common::version::CURRENT),
+        match facts.entry_kind {
+            EntryKind::Main => public_abi::EntryKind::Main,
+            EntryKind::Sandbox => public_abi::EntryKind::Sandbox,
+            EntryKind::ConsensusStage => public_abi::EntryKind::ConsensusStage,
+        },
+    );
+
+    let path = std::path::Path::new(target);
+    let root = if !path.exists() && genvm::runner::verify_runner(target).is_ok() {
+        resolver.unfold(GlobalSymbol::from(target))
+    } else {
+        let code = std::fs::read(path).with_context(|| format!("reading {path:?}"))?;
+        let arch = genvm::vm::Supervisor::code_to_archive(SharedBytes::new(code))
+            .with_context(|| format!("parsing {path:?}"))?;
+        resolver.archives.insert(
+            resolver.contract_id,
+            ZipCache::new(resolver.contract_id, arch),
+        );
+        resolver.contract_id
+    };
+
+    let tree = resolver.resolve_root(root)?;
+
+    let problems = tree.problems_count();
+
+    match format {
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "tree": tree,
+                "problems": problems,
+            }))?
+        ),
+        Format::Text => {
+            tree.print(0);
+            println!("{problems} problems");
+        }
+    }
+
+    if problems != 0 {
+        anyhow::bail!("action tree of {root} has problems");
+    }
+
+    Ok(())
+}
+
+pub fn handle(args: Args, config: config::Config) -> Result<()> {
+    let registry = Registry::new(&config.runners)?;
+
+    match args.command {
+        Command::List => list(&registry, args.format),
+        Command::Check {
+            target,
+            mode,
+            entry_kind,
+            genvm_version,
+        } => check(
+            &registry,
+            &target,
+            Facts {
+                mode,
+                entry_kind,
+                genvm_version,
+            },
+            args.format,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use genvm::ustar::Archive;
+
+    fn registry(name: &str) -> Registry {
+        let search_path = std::env::temp_dir().join(format!("genvm-test-runners-{name}"));
+        std::fs::create_dir_all(&search_path).unwrap();
+
+        Registry::new(&config::Runners {
+            search_paths: vec![search_path.to_str().unwrap().to_owned()],
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// Resolves `root` among in-memory runners, each of which has a single `file`,
+    /// returns all problems found
+    fn problems(name: &str, root: &str, runners: &[(&str, serde_json::Value)]) -> Vec<String> {
+        fn collect(node: &Node, out: &mut Vec<String>) {
+            out.extend(node.problems.iter().cloned());
+            for child in &node.children {
+                collect(child, out);
+            }
+        }
+
+        let registry = registry(name);
+        let mut resolver = Resolver::new(
+            &registry,
+            WasmMode::Det,
+            *genvm_common::version::CURRENT,
+            public_abi::EntryKind::Main,
+        );
+        for (id, actions) in runners {
+            let id = GlobalSymbol::from(*id);
+            let arch = Archive::from_file_and_runner(
+                SharedBytes::from(b"wasm".as_ref()),
+                SharedBytes::from(b"v0.1.0".as_ref()),
+                SharedBytes::new(serde_json::to_vec(actions).unwrap()),
+            );
+            resolver.archives.insert(id, ZipCache::new(id, arch));
+        }
+
+        let tree = resolver.resolve_root(GlobalSymbol::from(root)).unwrap();
+
+        let mut res = Vec::new();
+        collect(&tree, &mut res);
+        assert_eq!(res.len(), tree.problems_count());
+        res
+    }
+
+    #[test]
+    fn valid_tree() {
+        let res = problems(
+            "valid",
+            "a:1",
+            &[
+                (
+                    "a:1",
+                    serde_json::json!({ "Seq": [
+                        { "Depends": "b:1" },
+                        { "MapFile": { "to": "/lib/", "file": "file" } },
+                        { "StartWasm": "file" },
+                    ] }),
+                ),
+                (
+                    "b:1",
+                    serde_json::json!({ "AddEnv": { "name": "x", "val": "y" } }),
+                ),
+            ],
+        );
+        assert_eq!(res, Vec::<String>::new());
+    }
+
+    #[test]
+    fn cycle() {
+        let res = problems(
+            "cycle",
+            "a:1",
+            &[
+                (
+                    "a:1",
+                    serde_json::json!({ "Seq": [{ "Depends": "b:1" }, { "StartWasm": "file" }] }),
+                ),
+                ("b:1", serde_json::json!({ "Depends": "a:1" })),
+            ],
+        );
+        assert_eq!(res, vec!["cycle: a:1 -> b:1 -> a:1".to_owned()]);
+    }
+
+    #[test]
+    fn missing_dependency() {
+        let res = problems(
+            "missing",
+            "a:1",
+            &[(
+                "a:1",
+                serde_json::json!({ "Seq": [{ "Depends": "absent:1" }, { "StartWasm": "file" }] }),
+            )],
+        );
+        assert_eq!(res.len(), 1);
+        assert!(res[0].starts_with("missing dependency: "), "{res:?}");
+    }
+
+    #[test]
+    fn unreachable_start() {
+        let res = problems(
+            "unreachable",
+            "a:1",
+            &[(
+                "a:1",
+                serde_json::json!({ "Seq": [{ "StartWasm": "file" }, { "StartWasm": "file" }] }),
+            )],
+        );
+        assert_eq!(
+            res,
+            vec!["unreachable: previous action already started wasm".to_owned()]
+        );
+
+        let res = problems(
+            "no-start",
+            "a:1",
+            &[("a:1", serde_json::json!({ "LinkWasm": "file" }))],
+        );
+        assert_eq!(res, vec!["no StartWasm is reachable".to_owned()]);
+    }
+
+    #[test]
+    fn invalid_map_file() {
+        let res = problems(
+            "map-file",
+            "a:1",
+            &[(
+                "a:1",
+                serde_json::json!({ "Seq": [
+                    { "MapFile": { "to": "/lib/", "file": "lib/" } },
+                    { "MapFile": { "to": "/x", "file": "absent" } },
+                    { "StartWasm": "file" },
+                ] }),
+            )],
+        );
+        assert_eq!(
+            res,
+            vec![
+                "invalid path: no `lib/` in a:1".to_owned(),
+                "invalid path: no `absent` in a:1".to_owned(),
+            ]
+        );
+    }
+}
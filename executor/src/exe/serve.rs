@@ -0,0 +1,255 @@
+//! Long-lived daemon that runs transactions with warm engines and caches
+//!
+//! Each connection sends newline delimited json [`Request`]s and receives one json line per
+//! request, same as `genvm run --print json` outputs. Each worker owns a supervisor that is
+//! reused across transactions, per-transaction state is recreated for every request
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+use genvm::{config, record, vm, PublicArgs};
+use genvm_common::*;
+
+use super::output::{JsonOutput, JsonTiming};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[arg(long, help = "address to listen on, unix:// or tcp")]
+    listen: String,
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "how many transactions can be executed at the same time"
+    )]
+    workers: usize,
+}
+
+fn default_host_protocol() -> u32 {
+    1
+}
+
+fn default_permissions() -> String {
+    "rwscn".into()
+}
+
+/// Same as arguments of `genvm run`
+#[derive(serde::Deserialize)]
+struct Request {
+    message: genvm::MessageData,
+    host: String,
+    #[serde(default = "default_host_protocol")]
+    host_protocol: u32,
+    #[serde(default = "default_permissions")]
+    permissions: String,
+    host_data: genvm_modules_interfaces::HostData,
+    #[serde(default)]
+    cookie: Option<String>,
+    #[serde(default)]
+    sync: bool,
+    #[serde(default)]
+    allow_latest: bool,
+}
+
+type Worker = Option<Arc<tokio::sync::Mutex<vm::Supervisor>>>;
+
+struct Ctx {
+    config: config::Config,
+    idle: tokio::sync::Mutex<tokio::sync::mpsc::Receiver<Worker>>,
+    release: tokio::sync::mpsc::Sender<Worker>,
+}
+
+impl Ctx {
+    async fn run(&self, req: Request) -> Result<JsonOutput> {
+        super::run::check_permissions(&req.permissions)?;
+
+        let Some(mut worker) = self.idle.lock().await.recv().await else {
+            anyhow::bail!("daemon is shutting down");
+        };
+
+        // it is fine to reuse supervisor even after an error, `reset` drops all transaction state
+        let res = self.run_on(&mut worker, req).await;
+        let _ = self.release.send(worker).await;
+
+        res
+    }
+
+    async fn run_on(&self, worker: &mut Worker, req: Request) -> Result<JsonOutput> {
+        let (token, _canceller) = genvm_common::cancellation::make();
+
+        let cookie = req.cookie.unwrap_or_else(super::run::make_cookie);
+        log_info!(cookie = cookie; "genvm cookie");
+
+        let setup_start = std::time::Instant::now();
+
+        let host = genvm::Host::new(&req.host, req.host_protocol).await?;
+        let pub_args = PublicArgs {
+            cookie,
+            is_sync: req.sync,
+            allow_latest: req.allow_latest,
+            message: &req.message,
+            tape: record::Tape::None,
+        };
+
+        let supervisor = match worker.clone() {
+            Some(supervisor) => {
+                genvm::reuse_supervisor(
+                    &self.config,
+                    &supervisor,
+                    host,
+                    token,
+                    req.host_data,
+                    pub_args,
+                )
+                .await?;
+                supervisor
+            }
+            None => {
+                let supervisor =
+                    genvm::create_supervisor(&self.config, host, token, req.host_data, pub_args)
+                        .await?;
+                *worker = Some(supervisor.clone());
+                supervisor
+            }
+        };
+
+        let run_start = std::time::Instant::now();
+
+        let res = genvm::run_with(req.message, supervisor.clone(), &req.permissions)
+            .await
+            .with_context(|| "running genvm");
+
+        if let Err(err) = &res {
+            log_error!(error:ah = err; "error running genvm");
+        }
+
+        let mut out = JsonOutput::new(
+            &res,
+            JsonTiming {
+                setup_ms: (run_start - setup_start).as_secs_f64() * 1000.0,
+                run_ms: run_start.elapsed().as_secs_f64() * 1000.0,
+            },
+        );
+
+        let supervisor = supervisor.lock().await;
+        out.add_supervisor(&supervisor);
+        supervisor.shared_data.modules.llm.close().await;
+        supervisor.shared_data.modules.web.close().await;
+
+        Ok(out)
+    }
+
+    async fn handle_request(&self, line: &str) -> JsonOutput {
+        let res = match serde_json::from_str::<Request>(line) {
+            Ok(req) => self.run(req).await,
+            Err(e) => Err(anyhow::Error::from(e).context("parsing request")),
+        };
+
+        res.unwrap_or_else(|e| {
+            log_error!(error:ah = &e; "transaction failed");
+            JsonOutput::new(
+                &Err(e),
+                JsonTiming {
+                    setup_ms: 0.0,
+                    run_ms: 0.0,
+                },
+            )
+        })
+    }
+
+    async fn serve_connection(
+        self: Arc<Self>,
+        stream: impl tokio::io::AsyncRead + tokio::io::AsyncWrite,
+    ) -> Result<()> {
+        let (read, write) = tokio::io::split(stream);
+        let mut lines = tokio::io::BufReader::new(read).lines();
+        let mut write = tokio::io::BufWriter::new(write);
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let out = self.handle_request(&line).await;
+
+            let mut out = serde_json::to_vec(&out)?;
+            out.push(b'\n');
+            write.write_all(&out).await?;
+            write.flush().await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn spawn_connection(
+    ctx: &Arc<Ctx>,
+    stream: impl tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+) {
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        if let Err(e) = ctx.serve_connection(stream).await {
+            log_warn!(error:ah = &e; "connection failed");
+        }
+    });
+}
+
+pub fn handle(args: Args, config: config::Config) -> Result<()> {
+    if args.workers == 0 {
+        anyhow::bail!("at least one worker is required");
+    }
+
+    let runtime = config.base.create_rt()?;
+
+    let (release, idle) = tokio::sync::mpsc::channel(args.workers);
+    for _ in 0..args.workers {
+        release
+            .try_send(None)
+            .map_err(|_| anyhow::anyhow!("can't add worker"))?;
+    }
+
+    let ctx = Arc::new(Ctx {
+        config,
+        idle: tokio::sync::Mutex::new(idle),
+        release,
+    });
+
+    runtime.block_on(async {
+        let gc_ctx = ctx.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(genvm::caching::GC_INTERVAL);
+            loop {
+                interval.tick().await;
+                let ctx = gc_ctx.clone();
+                let _ = tokio::task::spawn_blocking(move || genvm::caching::gc_if_due(&ctx.config))
+                    .await;
+            }
+        });
+
+        const UNIX: &str = "unix://";
+        if let Some(path) = args.listen.strip_prefix(UNIX) {
+            let _ = std::fs::remove_file(path);
+            let listener = tokio::net::UnixListener::bind(path)
+                .with_context(|| format!("binding {}", args.listen))?;
+
+            log_info!(address = args.listen, workers = args.workers; "genvm is serving");
+
+            loop {
+                let (stream, _) = listener.accept().await?;
+                spawn_connection(&ctx, stream);
+            }
+        } else {
+            let listener = tokio::net::TcpListener::bind(&args.listen)
+                .await
+                .with_context(|| format!("binding {}", args.listen))?;
+
+            log_info!(address = args.listen, workers = args.workers; "genvm is serving");
+
+            loop {
+                let (stream, _) = listener.accept().await?;
+                spawn_connection(&ctx, stream);
+            }
+        }
+    })
+}
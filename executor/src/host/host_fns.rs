@@ -21,6 +21,8 @@ pub enum Methods {
     EthSend = 11,
     GetBalance = 12,
     RemainingFuelAsGen = 13,
+    GetGasLimit = 14,
+    StorageWriteBatch = 15,
 }
 
 impl Methods {
@@ -40,6 +42,8 @@ impl Methods {
             Methods::EthSend => 11,
             Methods::GetBalance => 12,
             Methods::RemainingFuelAsGen => 13,
+            Methods::GetGasLimit => 14,
+            Methods::StorageWriteBatch => 15,
         }
     }
     pub fn str_snake_case(self) -> &'static str {
@@ -58,6 +62,8 @@ impl Methods {
             Methods::EthSend => "eth_send",
             Methods::GetBalance => "get_balance",
             Methods::RemainingFuelAsGen => "remaining_fuel_as_gen",
+            Methods::GetGasLimit => "get_gas_limit",
+            Methods::StorageWriteBatch => "storage_write_batch",
         }
     }
 }
@@ -81,10 +87,13 @@ impl TryFrom<u8> for Methods {
             11 => Ok(Methods::EthSend),
             12 => Ok(Methods::GetBalance),
             13 => Ok(Methods::RemainingFuelAsGen),
+            14 => Ok(Methods::GetGasLimit),
+            15 => Ok(Methods::StorageWriteBatch),
             _ => Err(()),
         }
     }
 }
+pub const PROTOCOL_VERSION: u32 = 6;
 #[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum Errors {
@@ -4,7 +4,7 @@ use sha3::Digest;
 use std::sync::Arc;
 
 #[serde_as]
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Hash, Copy)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Hash, Copy, PartialOrd, Ord)]
 pub struct AccountAddress(#[serde_as(as = "Base64")] pub [u8; 20]);
 
 impl AccountAddress {
@@ -77,4 +77,9 @@ pub struct MessageData {
     pub is_init: bool,
     #[serde(default = "default_datetime")]
     pub datetime: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub gas_limit: Option<u64>,
+    /// overrides `memory_limits` of the config
+    #[serde(default)]
+    pub memory_limits: crate::config::MemoryLimits,
 }
@@ -0,0 +1,507 @@
+//! Implementation of the host side of the protocol
+//!
+//! It is used by `genvm mock-host` and by rust tests, which can run [`crate::run_with`]
+//! against [`super::Host::new_in_process`] without spawning any process
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+
+use genvm_common::*;
+
+use super::host_fns;
+use super::message::{AccountAddress, SlotID};
+use crate::public_abi::{ResultCode, StorageType};
+
+#[serde_as]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct MockBytes(#[serde_as(as = "Base64")] pub Vec<u8>);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum MockResult {
+    /// calldata encoded value
+    Return(MockBytes),
+    Rollback(String),
+    ContractError(String),
+}
+
+impl MockResult {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            MockResult::Return(MockBytes(data)) => {
+                let mut res = Vec::from([ResultCode::Return as u8]);
+                res.extend_from_slice(data);
+                res
+            }
+            MockResult::Rollback(msg) => {
+                let mut res = Vec::from([ResultCode::UserError as u8]);
+                res.extend_from_slice(msg.as_bytes());
+                res
+            }
+            MockResult::ContractError(msg) => {
+                let mut res = Vec::from([ResultCode::VmError as u8]);
+                res.extend_from_slice(msg.as_bytes());
+                res
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MockMessage {
+    PostMessage {
+        address: AccountAddress,
+        calldata: MockBytes,
+        data: serde_json::Value,
+    },
+    DeployContract {
+        calldata: MockBytes,
+        code: MockBytes,
+        data: serde_json::Value,
+    },
+    EthSend {
+        address: AccountAddress,
+        calldata: MockBytes,
+        data: serde_json::Value,
+    },
+    PostEvent {
+        topics: Vec<MockBytes>,
+        blob: MockBytes,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockConsumedResult {
+    pub code: u8,
+    pub data: MockBytes,
+    pub gas_used: u64,
+}
+
+fn default_gas_limit() -> u64 {
+    u64::MAX
+}
+
+fn default_remaining_fuel_as_gen() -> u64 {
+    1 << 32
+}
+
+/// State of the mock host, it is both read from and written to the state file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockState {
+    #[serde(default)]
+    pub calldata: MockBytes,
+    #[serde(default)]
+    pub storage: BTreeMap<AccountAddress, BTreeMap<SlotID, MockBytes>>,
+    #[serde(default)]
+    pub balances: BTreeMap<AccountAddress, primitive_types::U256>,
+    /// `None` means that host is the leader
+    #[serde(default)]
+    pub leader_nondet: Option<Vec<MockResult>>,
+    #[serde(default = "default_gas_limit")]
+    pub gas_limit: u64,
+    #[serde(default = "default_remaining_fuel_as_gen")]
+    pub remaining_fuel_as_gen: u64,
+
+    #[serde(default)]
+    pub messages: Vec<MockMessage>,
+    #[serde(default)]
+    pub nondet_results: BTreeMap<u32, MockBytes>,
+    #[serde(default)]
+    pub consumed_fuel: u64,
+    #[serde(default)]
+    pub result: Option<MockConsumedResult>,
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        Self {
+            calldata: MockBytes::default(),
+            storage: BTreeMap::new(),
+            balances: BTreeMap::new(),
+            leader_nondet: None,
+            gas_limit: default_gas_limit(),
+            remaining_fuel_as_gen: default_remaining_fuel_as_gen(),
+            messages: Vec::new(),
+            nondet_results: BTreeMap::new(),
+            consumed_fuel: 0,
+            result: None,
+        }
+    }
+}
+
+impl MockState {
+    fn is_json(path: &std::path::Path) -> bool {
+        path.extension().is_some_and(|x| x == "json")
+    }
+
+    /// loads state from `.json` or `.yaml` file
+    pub fn load(path: &std::path::Path) -> Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("reading {path:?}"))?;
+        if Self::is_json(path) {
+            Ok(serde_json::from_str(&contents)?)
+        } else {
+            Ok(serde_yaml::from_str(&contents)?)
+        }
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> Result<()> {
+        let contents = if Self::is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            serde_yaml::to_string(self)?
+        };
+        std::fs::write(path, contents).with_context(|| format!("writing {path:?}"))
+    }
+
+    pub fn storage_read(&self, account: AccountAddress, slot: SlotID, index: u32, buf: &mut [u8]) {
+        buf.fill(0);
+
+        let Some(MockBytes(data)) = self.storage.get(&account).and_then(|x| x.get(&slot)) else {
+            return;
+        };
+
+        let index = index as usize;
+        if index >= data.len() {
+            return;
+        }
+
+        let available = usize::min(data.len() - index, buf.len());
+        buf[..available].copy_from_slice(&data[index..index + available]);
+    }
+
+    pub fn storage_write(
+        &mut self,
+        account: AccountAddress,
+        slot: SlotID,
+        index: u32,
+        what: &[u8],
+    ) {
+        let MockBytes(data) = self
+            .storage
+            .entry(account)
+            .or_default()
+            .entry(slot)
+            .or_default();
+
+        let index = index as usize;
+        if data.len() < index + what.len() {
+            data.resize(index + what.len(), 0);
+        }
+        data[index..index + what.len()].copy_from_slice(what);
+    }
+}
+
+pub struct MockHost {
+    pub state: MockState,
+    /// account to which storage writes are applied
+    pub running_address: AccountAddress,
+    /// protocol version of the connection that is being served
+    pub protocol_version: u32,
+}
+
+fn read_exact<const N: usize>(input: &mut impl Read) -> Result<[u8; N]> {
+    let mut buf = [0; N];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32> {
+    Ok(u32::from_le_bytes(read_exact(input)?))
+}
+
+fn read_slice(input: &mut impl Read) -> Result<Vec<u8>> {
+    let len = read_u32(input)?;
+    let mut res = vec![0; len as usize];
+    input.read_exact(&mut res)?;
+    Ok(res)
+}
+
+fn read_json(input: &mut impl Read) -> Result<serde_json::Value> {
+    Ok(serde_json::from_slice(&read_slice(input)?)?)
+}
+
+fn write_slice(output: &mut impl Write, data: &[u8]) -> Result<()> {
+    output.write_all(&(data.len() as u32).to_le_bytes())?;
+    output.write_all(data)?;
+    Ok(())
+}
+
+fn write_ok(output: &mut impl Write) -> Result<()> {
+    output.write_all(&[host_fns::Errors::Ok as u8])?;
+    Ok(())
+}
+
+impl MockHost {
+    pub fn new(state: MockState, running_address: AccountAddress) -> Self {
+        Self {
+            state,
+            running_address,
+            protocol_version: super::PROTOCOL_VERSION,
+        }
+    }
+
+    /// handles a single request, returns `true` if it was `consume_result`
+    pub fn handle_request(
+        &mut self,
+        input: &mut impl Read,
+        output: &mut impl Write,
+    ) -> Result<bool> {
+        let [method] = read_exact(input)?;
+        let method = host_fns::Methods::try_from(method)
+            .map_err(|_| anyhow::anyhow!("unknown method {method}"))?;
+
+        log_trace!(method:? = method; "mock host request");
+
+        match method {
+            host_fns::Methods::GetCalldata => {
+                write_ok(output)?;
+                write_slice(output, &self.state.calldata.0)?;
+            }
+            host_fns::Methods::StorageRead => {
+                let [mode] = read_exact(input)?;
+                StorageType::try_from(mode)
+                    .map_err(|_| anyhow::anyhow!("invalid storage type {mode}"))?;
+                let account = AccountAddress(read_exact(input)?);
+                let slot = SlotID(read_exact(input)?);
+                let index = read_u32(input)?;
+                let len = read_u32(input)?;
+
+                let mut res = vec![0; len as usize];
+                self.state.storage_read(account, slot, index, &mut res);
+
+                write_ok(output)?;
+                output.write_all(&res)?;
+            }
+            host_fns::Methods::StorageWrite => {
+                let slot = SlotID(read_exact(input)?);
+                let index = read_u32(input)?;
+                let data = read_slice(input)?;
+
+                self.state
+                    .storage_write(self.running_address, slot, index, &data);
+
+                write_ok(output)?;
+            }
+            host_fns::Methods::StorageWriteBatch => {
+                let count = read_u32(input)?;
+                for _ in 0..count {
+                    let account = if self.protocol_version >= super::PROTOCOL_VERSION_ACCOUNT_WRITES
+                    {
+                        AccountAddress(read_exact(input)?)
+                    } else {
+                        self.running_address
+                    };
+                    let slot = SlotID(read_exact(input)?);
+                    let index = read_u32(input)?;
+                    let data = read_slice(input)?;
+
+                    self.state.storage_write(account, slot, index, &data);
+                }
+
+                write_ok(output)?;
+            }
+            host_fns::Methods::ConsumeResult => {
+                let data = read_slice(input)?;
+                let gas_used = if self.protocol_version >= super::PROTOCOL_VERSION_GAS {
+                    u64::from_le_bytes(read_exact(input)?)
+                } else {
+                    0
+                };
+
+                let (code, data) = data
+                    .split_first()
+                    .ok_or_else(|| anyhow::anyhow!("empty result"))?;
+                self.state.result = Some(MockConsumedResult {
+                    code: *code,
+                    data: MockBytes(Vec::from(data)),
+                    gas_used,
+                });
+
+                output.write_all(&[0])?;
+                output.flush()?;
+                return Ok(true);
+            }
+            host_fns::Methods::GetLeaderNondetResult => {
+                let call_no = read_u32(input)?;
+                match &self.state.leader_nondet {
+                    None => output.write_all(&[host_fns::Errors::IAmLeader as u8])?,
+                    Some(results) => match results.get(call_no as usize) {
+                        None => output.write_all(&[host_fns::Errors::Absent as u8])?,
+                        Some(res) => {
+                            write_ok(output)?;
+                            write_slice(output, &res.encode())?;
+                        }
+                    },
+                }
+            }
+            host_fns::Methods::PostNondetResult => {
+                let call_no = read_u32(input)?;
+                let data = read_slice(input)?;
+                self.state.nondet_results.insert(call_no, MockBytes(data));
+                write_ok(output)?;
+            }
+            host_fns::Methods::PostMessage => {
+                let address = AccountAddress(read_exact(input)?);
+                let calldata = MockBytes(read_slice(input)?);
+                let data = read_json(input)?;
+                self.state.messages.push(MockMessage::PostMessage {
+                    address,
+                    calldata,
+                    data,
+                });
+                write_ok(output)?;
+            }
+            host_fns::Methods::PostEvent => {
+                let [topics_len] = read_exact(input)?;
+                let mut topics = Vec::new();
+                for _ in 0..topics_len {
+                    topics.push(MockBytes(Vec::from(read_exact::<32>(input)?)));
+                }
+                let blob = MockBytes(read_slice(input)?);
+                let name = if self.protocol_version >= super::PROTOCOL_VERSION_EVENT_NAMES {
+                    Some(String::from_utf8(read_slice(input)?)?).filter(|x| !x.is_empty())
+                } else {
+                    None
+                };
+                self.state
+                    .messages
+                    .push(MockMessage::PostEvent { topics, blob, name });
+                write_ok(output)?;
+            }
+            host_fns::Methods::ConsumeFuel => {
+                let gas = u64::from_le_bytes(read_exact(input)?);
+                self.state.consumed_fuel += gas;
+                self.state.remaining_fuel_as_gen =
+                    self.state.remaining_fuel_as_gen.saturating_sub(gas);
+            }
+            host_fns::Methods::DeployContract => {
+                let calldata = MockBytes(read_slice(input)?);
+                let code = MockBytes(read_slice(input)?);
+                let data = read_json(input)?;
+                self.state.messages.push(MockMessage::DeployContract {
+                    calldata,
+                    code,
+                    data,
+                });
+                write_ok(output)?;
+            }
+            host_fns::Methods::EthCall => {
+                let _address: [u8; 20] = read_exact(input)?;
+                let _calldata = read_slice(input)?;
+                output.write_all(&[host_fns::Errors::Forbidden as u8])?;
+            }
+            host_fns::Methods::EthSend => {
+                let address = AccountAddress(read_exact(input)?);
+                let calldata = MockBytes(read_slice(input)?);
+                let data = read_json(input)?;
+                self.state.messages.push(MockMessage::EthSend {
+                    address,
+                    calldata,
+                    data,
+                });
+                write_ok(output)?;
+            }
+            host_fns::Methods::GetBalance => {
+                let address = AccountAddress(read_exact(input)?);
+                let balance = self
+                    .state
+                    .balances
+                    .get(&address)
+                    .cloned()
+                    .unwrap_or_default();
+                write_ok(output)?;
+                output.write_all(&balance.to_little_endian())?;
+            }
+            host_fns::Methods::RemainingFuelAsGen => {
+                write_ok(output)?;
+                output.write_all(&self.state.remaining_fuel_as_gen.to_le_bytes())?;
+            }
+            host_fns::Methods::GetGasLimit => {
+                write_ok(output)?;
+                output.write_all(&self.state.gas_limit.to_le_bytes())?;
+            }
+        }
+
+        output.flush()?;
+
+        Ok(false)
+    }
+
+    /// serves requests until result is consumed
+    pub fn serve(
+        &mut self,
+        input: impl Read,
+        output: impl Write,
+        protocol_version: u32,
+    ) -> Result<()> {
+        self.protocol_version = protocol_version;
+
+        let mut input = std::io::BufReader::new(input);
+        let mut output = std::io::BufWriter::new(output);
+
+        if protocol_version < super::PROTOCOL_VERSION_PIPELINED {
+            while !self.handle_request(&mut input, &mut output)? {}
+            return Ok(());
+        }
+
+        self.serve_framed(&mut input, &mut output, None)
+    }
+
+    /// Serves connection made with `shm://` address: receives the ring first and then places
+    /// large responses into it
+    pub fn serve_shm(&mut self, stream: std::os::unix::net::UnixStream) -> Result<()> {
+        let (fd, size) = super::shm::recv_fd(std::os::fd::AsFd::as_fd(&stream))?;
+        let ring = super::shm::Ring::open(&fd, size)?;
+
+        let mut input = std::io::BufReader::new(stream.try_clone()?);
+        let mut output = std::io::BufWriter::new(stream);
+
+        self.serve_framed(&mut input, &mut output, Some(&ring))
+    }
+
+    fn serve_framed(
+        &mut self,
+        input: &mut impl Read,
+        output: &mut impl Write,
+        ring: Option<&super::shm::Ring>,
+    ) -> Result<()> {
+        loop {
+            let id = read_u32(input)?;
+            let request = read_slice(input)?;
+
+            let mut response = Vec::new();
+            let done = self.handle_request(&mut request.as_slice(), &mut response)?;
+
+            if !response.is_empty() {
+                output.write_all(&id.to_le_bytes())?;
+
+                let pos = ring
+                    .filter(|_| response.len() >= super::shm::THRESHOLD)
+                    .and_then(|ring| ring.write(&response));
+                match pos {
+                    Some(pos) => {
+                        let len = response.len() as u32 | super::shm::RING_BIT;
+                        output.write_all(&len.to_le_bytes())?;
+                        output.write_all(&pos.to_le_bytes())?;
+                    }
+                    None => write_slice(output, &response)?,
+                }
+
+                output.flush()?;
+            }
+
+            if done {
+                return Ok(());
+            }
+        }
+    }
+}
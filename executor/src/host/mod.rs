@@ -1,5 +1,9 @@
 mod host_fns;
 pub mod message;
+pub mod mock;
+mod shm;
+mod storage;
+mod transport;
 
 use genvm_common::*;
 
@@ -7,6 +11,7 @@ use crate::public_abi::{ResultCode, StorageType};
 use genvm_common::calldata::Address;
 use genvm_common::calldata::ADDRESS_SIZE;
 use message::root_offsets;
+use transport::Reply;
 
 use core::str;
 use std::collections::BTreeMap;
@@ -17,80 +22,186 @@ use anyhow::{Context, Result};
 use crate::calldata;
 use crate::errors::VMError;
 use crate::memlimiter;
+use crate::record;
 use crate::vm::{self, RunOk};
 pub use message::{MessageData, SlotID};
 
-trait Sock: std::io::Read + std::io::Write + Send + Sync {}
+use host_fns::PROTOCOL_VERSION;
 
-impl Sock for bufreaderwriter::seq::BufReaderWriterSeq<std::os::unix::net::UnixStream> {}
+/// First protocol version in which storage writes are sent in a single batch at the end
+const PROTOCOL_VERSION_STORAGE_BATCH: u32 = 2;
 
-impl Sock for bufreaderwriter::seq::BufReaderWriterSeq<std::net::TcpStream> {}
+/// First protocol version in which requests and responses are framed and carry ids
+const PROTOCOL_VERSION_PIPELINED: u32 = 3;
 
+/// First protocol version in which entries of a storage batch carry account address
+const PROTOCOL_VERSION_ACCOUNT_WRITES: u32 = 4;
+
+/// First protocol version in which `post_event` carries name of the event
+const PROTOCOL_VERSION_EVENT_NAMES: u32 = 5;
+
+/// First protocol version in which `consume_result` carries used gas and `get_gas_limit` is available
+const PROTOCOL_VERSION_GAS: u32 = 6;
+
+/// Client of the host. All methods take `&self`, so that it can be shared between tasks
 pub struct Host {
-    sock: Box<Mutex<dyn Sock>>,
+    transport: transport::Transport,
+    protocol_version: u32,
+    storage: Mutex<Option<storage::Overlay>>,
+    tape: record::Tape,
+}
+
+fn push_slice(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(data);
+}
+
+fn host_error(e: host_fns::Errors) -> anyhow::Error {
+    VMError(e.str_snake_case().to_owned(), None).into()
 }
 
 impl Host {
-    pub fn new(addr: &str) -> Result<Host> {
+    pub const LATEST_PROTOCOL_VERSION: u32 = PROTOCOL_VERSION;
+    pub const GAS_PROTOCOL_VERSION: u32 = PROTOCOL_VERSION_GAS;
+
+    /// Connects to the host, must be called within tokio runtime
+    pub async fn new(addr: &str, protocol_version: u32) -> Result<Host> {
+        if protocol_version == 0 || protocol_version > PROTOCOL_VERSION {
+            anyhow::bail!(
+                "unsupported host protocol version {protocol_version}, latest is {PROTOCOL_VERSION}"
+            );
+        }
+
         const UNIX: &str = "unix://";
-        let sock: Box<Mutex<dyn Sock>> = if let Some(addr_suff) = addr.strip_prefix(UNIX) {
-            Box::new(Mutex::new(
-                bufreaderwriter::seq::BufReaderWriterSeq::new_writer(
-                    std::os::unix::net::UnixStream::connect(std::path::Path::new(addr_suff))
-                        .with_context(|| format!("connecting to {addr}"))?,
-                ),
-            ))
+        const SHM: &str = "shm://";
+        if let Some(addr_suff) = addr.strip_prefix(SHM) {
+            return Self::new_shm(addr_suff, protocol_version)
+                .await
+                .with_context(|| format!("connecting to {addr}"));
+        }
+
+        let sock: Box<dyn transport::AsyncSock> = if let Some(addr_suff) = addr.strip_prefix(UNIX) {
+            Box::new(
+                tokio::net::UnixStream::connect(std::path::Path::new(addr_suff))
+                    .await
+                    .with_context(|| format!("connecting to {addr}"))?,
+            )
         } else {
-            Box::new(Mutex::new(
-                bufreaderwriter::seq::BufReaderWriterSeq::new_writer(
-                    std::net::TcpStream::connect(addr)
-                        .with_context(|| format!("connecting to {addr}"))?,
-                ),
-            ))
+            Box::new(
+                tokio::net::TcpStream::connect(addr)
+                    .await
+                    .with_context(|| format!("connecting to {addr}"))?,
+            )
         };
-        Ok(Host { sock })
+
+        Ok(Self::from_sock(sock, protocol_version))
     }
-}
 
-fn read_u32(sock: &mut dyn Sock) -> Result<u32> {
-    let mut int_buf = [0; 4];
-    sock.read_exact(&mut int_buf)?;
-    Ok(u32::from_le_bytes(int_buf))
-}
+    /// Control messages go through unix socket at `path`, bulk responses through a memfd ring that
+    /// is passed to the host right after connecting
+    async fn new_shm(path: &str, protocol_version: u32) -> Result<Host> {
+        if protocol_version < PROTOCOL_VERSION_PIPELINED {
+            anyhow::bail!(
+                "shared memory transport requires host protocol version {PROTOCOL_VERSION_PIPELINED}"
+            );
+        }
 
-fn read_bytes(sock: &mut dyn Sock) -> Result<Box<[u8]>> {
-    let len = read_u32(sock)?;
+        let sock = tokio::net::UnixStream::connect(std::path::Path::new(path)).await?;
 
-    let res = Box::new_uninit_slice(len as usize);
-    let mut res = unsafe { res.assume_init() };
-    sock.read_exact(&mut res)?;
-    Ok(res)
-}
+        let (ring, fd) = shm::Ring::create(shm::DEFAULT_SIZE)?;
+        shm::send_fd(std::os::fd::AsFd::as_fd(&sock), &fd, shm::DEFAULT_SIZE)?;
+
+        Ok(Host {
+            transport: transport::Transport::with_ring(Box::new(sock), ring),
+            protocol_version,
+            storage: Mutex::new(None),
+            tape: record::Tape::None,
+        })
+    }
 
-fn write_slice(sock: &mut dyn Sock, data: &[u8]) -> Result<()> {
-    let len = data.len() as u32;
+    fn from_sock(sock: Box<dyn transport::AsyncSock>, protocol_version: u32) -> Host {
+        Host {
+            transport: transport::Transport::new(
+                sock,
+                protocol_version >= PROTOCOL_VERSION_PIPELINED,
+            ),
+            protocol_version,
+            storage: Mutex::new(None),
+            tape: record::Tape::None,
+        }
+    }
 
-    sock.write_all(&len.to_le_bytes())?;
-    sock.write_all(data)?;
+    /// Host that is answered from the recording, without any connection
+    pub fn replay(replayer: std::sync::Arc<record::Replayer>, protocol_version: u32) -> Host {
+        Host {
+            transport: transport::Transport::Replay(replayer),
+            protocol_version,
+            storage: Mutex::new(None),
+            tape: record::Tape::None,
+        }
+    }
 
-    Ok(())
-}
+    /// Records all further exchanges with the host
+    pub fn record_to(&mut self, recorder: std::sync::Arc<record::Recorder>) {
+        self.tape = record::Tape::Record(recorder);
+    }
 
-fn read_host_error(sock: &mut dyn Sock) -> Result<host_fns::Errors> {
-    let mut has_some = [0; 1];
-    sock.read_exact(&mut has_some)?;
+    /// Serves `host` on a separate thread and connects to it using the latest protocol version
+    ///
+    /// `host` is locked until result is consumed
+    pub async fn new_in_process(host: std::sync::Arc<Mutex<mock::MockHost>>) -> Result<Host> {
+        let (mine, theirs) = std::os::unix::net::UnixStream::pair()?;
+
+        std::thread::spawn(move || {
+            let Ok(mut host) = host.lock() else {
+                return;
+            };
+            let res = theirs
+                .try_clone()
+                .map_err(anyhow::Error::from)
+                .and_then(|input| host.serve(input, theirs, PROTOCOL_VERSION));
+            if let Err(e) = res {
+                log_error!(error:ah = &e; "in process mock host failed");
+            }
+        });
 
-    host_fns::Errors::try_from(has_some[0])
-        .map_err(|_| anyhow::anyhow!("invalid error id {}", has_some[0]))
-}
+        mine.set_nonblocking(true)?;
+        let mine = tokio::net::UnixStream::from_std(mine)?;
 
-fn handle_host_error(sock: &mut dyn Sock) -> Result<()> {
-    let e = read_host_error(sock)?;
+        Ok(Self::from_sock(Box::new(mine), PROTOCOL_VERSION))
+    }
 
-    if e == host_fns::Errors::Ok {
-        Ok(())
-    } else {
-        Err(crate::errors::VMError(e.str_snake_case().to_owned(), None).into())
+    /// Enables storage overlay if host supports it. Must be called before any storage access
+    pub fn begin_transaction(&self, running_address: calldata::Address) {
+        if self.protocol_version < PROTOCOL_VERSION_STORAGE_BATCH {
+            return;
+        }
+
+        if let Ok(mut storage) = self.storage.lock() {
+            *storage = Some(storage::Overlay::new(running_address));
+        }
+    }
+
+    async fn exchange(&self, request: &[u8], reply: Reply) -> Result<transport::Response> {
+        let res = self.transport.call(request, reply).await?;
+
+        if let record::Tape::Record(recorder) = &self.tape {
+            if request.first() == Some(&(host_fns::Methods::ConsumeResult as u8)) {
+                recorder.result(&request[1..]);
+            } else if !matches!(reply, Reply::None) {
+                let response = match &res {
+                    Ok(data) => record::HostResponse::Ok(mock::MockBytes(data.clone())),
+                    Err(e) => record::HostResponse::Error(e.value()),
+                };
+                recorder.host(request, response);
+            }
+        }
+
+        Ok(res)
+    }
+
+    async fn call(&self, request: &[u8], reply: Reply) -> Result<Vec<u8>> {
+        self.exchange(request, reply).await?.map_err(host_error)
     }
 }
 
@@ -103,27 +214,17 @@ impl LockedSlotsSet {
 }
 
 impl Host {
-    pub fn get_calldata(&mut self, calldata: &mut Vec<u8>) -> Result<()> {
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::GetCalldata as u8])?;
+    pub async fn get_calldata(&self, calldata: &mut Vec<u8>) -> Result<()> {
+        let res = self
+            .call(&[host_fns::Methods::GetCalldata as u8], Reply::Slice)
+            .await?;
 
-        handle_host_error(sock)?;
-
-        let len = read_u32(sock)? as usize;
-        calldata.reserve(len);
-        let index = calldata.len();
-        unsafe {
-            calldata.set_len(index + len);
-        }
-        sock.read_exact(&mut calldata[index..index + len])?;
+        calldata.extend_from_slice(&res);
         Ok(())
     }
 
-    fn get_locked_slots(
-        &mut self,
+    async fn get_locked_slots(
+        &self,
         contract_address: calldata::Address,
         limiter: &memlimiter::Limiter,
     ) -> Result<LockedSlotsSet> {
@@ -136,7 +237,8 @@ impl Host {
             locked_slot,
             0,
             &mut len_buf,
-        )?;
+        )
+        .await?;
         let len = u32::from_le_bytes(len_buf);
 
         if !limiter.consume_mul(len, SlotID::SIZE) {
@@ -158,15 +260,16 @@ impl Host {
             locked_slot,
             4,
             read_to,
-        )?;
+        )
+        .await?;
 
         res.sort();
 
         Ok(LockedSlotsSet(res))
     }
 
-    pub fn get_locked_slots_for_sender(
-        &mut self,
+    pub async fn get_locked_slots_for_sender(
+        &self,
         contract_address: calldata::Address,
         sender: calldata::Address,
         limiter: &memlimiter::Limiter,
@@ -180,7 +283,8 @@ impl Host {
             upgraders_slot,
             0,
             &mut len_buf,
-        )?;
+        )
+        .await?;
         let len = u32::from_le_bytes(len_buf);
 
         for i in 0..len {
@@ -192,18 +296,19 @@ impl Host {
                 upgraders_slot,
                 4 + i * Address::SIZE,
                 &mut read_sender,
-            )?;
+            )
+            .await?;
 
             if read_sender == sender.raw() {
                 return Ok(LockedSlotsSet(Box::from([])));
             }
         }
 
-        self.get_locked_slots(contract_address, limiter)
+        self.get_locked_slots(contract_address, limiter).await
     }
 
-    pub fn get_code(
-        &mut self,
+    pub async fn get_code(
+        &self,
         mode: StorageType,
         account: calldata::Address,
         limiter: &memlimiter::Limiter,
@@ -211,7 +316,8 @@ impl Host {
         let code_slot = SlotID::ZERO.indirection(root_offsets::CODE);
 
         let mut len_buf = [0; 4];
-        self.storage_read(mode, account, code_slot, 0, &mut len_buf)?;
+        self.storage_read(mode, account, code_slot, 0, &mut len_buf)
+            .await?;
         let code_size = u32::from_le_bytes(len_buf);
 
         if !limiter.consume(code_size) {
@@ -221,63 +327,180 @@ impl Host {
         let res = Box::new_uninit_slice(code_size as usize);
         let mut res = unsafe { res.assume_init() };
 
-        self.storage_read(mode, account, code_slot, 4, &mut res)?;
+        self.storage_read(mode, account, code_slot, 4, &mut res)
+            .await?;
 
         Ok(res)
     }
 
-    pub fn storage_read(
-        &mut self,
+    pub async fn storage_read(
+        &self,
         mode: StorageType,
         account: calldata::Address,
         slot: SlotID,
         index: u32,
         buf: &mut [u8],
     ) -> Result<()> {
-        let Ok(mut sock) = (*self.sock).lock() else {
+        {
+            let Ok(mut storage) = self.storage.lock() else {
+                anyhow::bail!("can't take lock")
+            };
+            if let Some(overlay) = storage.as_mut() {
+                if overlay.read_cached(mode, account, slot, index, buf) {
+                    log_trace!(slot:? = slot.0, index = index, data:serde = buf; "read cached");
+                    return Ok(());
+                }
+            }
+        }
+
+        self.storage_read_from_host(mode, account, slot, index, buf)
+            .await?;
+
+        let Ok(mut storage) = self.storage.lock() else {
             anyhow::bail!("can't take lock")
         };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::StorageRead as u8])?;
-        sock.write_all(&[mode as u8; 1])?;
-        sock.write_all(&account.raw())?;
-        sock.write_all(&slot.raw())?;
-        sock.write_all(&index.to_le_bytes())?;
-        sock.write_all(&(buf.len() as u32).to_le_bytes())?;
+        if let Some(overlay) = storage.as_mut() {
+            overlay.on_host_read(mode, account, slot, index, buf);
+        }
 
-        handle_host_error(sock)?;
+        Ok(())
+    }
+
+    async fn storage_read_from_host(
+        &self,
+        mode: StorageType,
+        account: calldata::Address,
+        slot: SlotID,
+        index: u32,
+        buf: &mut [u8],
+    ) -> Result<()> {
+        let mut request = Vec::from([host_fns::Methods::StorageRead as u8, mode as u8]);
+        request.extend_from_slice(&account.raw());
+        request.extend_from_slice(&slot.raw());
+        request.extend_from_slice(&index.to_le_bytes());
+        request.extend_from_slice(&(buf.len() as u32).to_le_bytes());
 
-        sock.read_exact(buf)?;
+        let res = self.call(&request, Reply::Fixed(buf.len())).await?;
+        buf.copy_from_slice(&res);
 
         log_trace!(slot:? = slot.0, index = index, data:serde = buf; "read");
 
         Ok(())
     }
 
-    pub fn storage_write(&mut self, slot: SlotID, index: u32, buf: &[u8]) -> Result<()> {
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::StorageWrite as u8])?;
-        sock.write_all(&slot.raw())?;
-        sock.write_all(&index.to_le_bytes())?;
-        write_slice(sock, buf)?;
+    /// Writes to `account` storage, only running contract can be written without overlay
+    pub async fn storage_write(
+        &self,
+        account: calldata::Address,
+        slot: SlotID,
+        index: u32,
+        buf: &[u8],
+    ) -> Result<()> {
+        {
+            let Ok(mut storage) = self.storage.lock() else {
+                anyhow::bail!("can't take lock")
+            };
+            if let Some(overlay) = storage.as_mut() {
+                overlay.write(account, slot, index, buf);
+                return Ok(());
+            }
+        }
 
-        sock.flush()?;
+        let mut request = Vec::from([host_fns::Methods::StorageWrite as u8]);
+        request.extend_from_slice(&slot.raw());
+        request.extend_from_slice(&index.to_le_bytes());
+        push_slice(&mut request, buf);
 
-        handle_host_error(sock)?;
+        self.call(&request, Reply::Status).await?;
 
         Ok(())
     }
 
-    pub fn consume_result(&mut self, res: &Result<vm::FullRunOk>) -> Result<()> {
-        log_trace!("consume_result");
+    /// Whenever storage of contracts other than the running one can be written
+    pub fn supports_nested_writes(&self) -> bool {
+        self.protocol_version >= PROTOCOL_VERSION_ACCOUNT_WRITES
+    }
 
-        let Ok(mut sock) = (*self.sock).lock() else {
+    fn with_overlay(&self, f: impl FnOnce(&mut storage::Overlay)) -> Result<()> {
+        let Ok(mut storage) = self.storage.lock() else {
             anyhow::bail!("can't take lock")
         };
-        let sock: &mut dyn Sock = &mut *sock;
+        let Some(overlay) = storage.as_mut() else {
+            anyhow::bail!("nested writes require storage overlay")
+        };
+        f(overlay);
+        Ok(())
+    }
+
+    /// Starts a layer of writes that belongs to a mutating call
+    pub fn begin_nested_writes(&self) -> Result<()> {
+        self.with_overlay(|overlay| overlay.begin_layer())
+    }
+
+    /// Merges writes of the finished mutating call into the enclosing layer
+    pub fn commit_nested_writes(&self) -> Result<()> {
+        self.with_overlay(|overlay| overlay.commit_layer())
+    }
+
+    /// Drops writes of the failed mutating call
+    pub fn discard_nested_writes(&self) -> Result<()> {
+        self.with_overlay(|overlay| overlay.discard_layer())
+    }
+
+    /// Sends all pending writes in one message
+    async fn flush_storage(&self) -> Result<()> {
+        let request = {
+            let Ok(mut storage) = self.storage.lock() else {
+                anyhow::bail!("can't take lock")
+            };
+            let Some(overlay) = storage.as_mut() else {
+                return Ok(());
+            };
+
+            log_debug!(stats:serde = overlay.stats; "storage overlay stats");
+
+            if !overlay.has_delta() {
+                return Ok(());
+            }
+
+            let delta = overlay.take_delta();
+
+            let entries: Vec<(calldata::Address, SlotID, u32, &[u8])> = delta
+                .iter()
+                .flat_map(|((account, slot), seg)| {
+                    seg.iter()
+                        .map(|(index, data)| (*account, *slot, index, data))
+                })
+                .collect();
+
+            let with_accounts = self.protocol_version >= PROTOCOL_VERSION_ACCOUNT_WRITES;
+            let running_address = overlay.running_address();
+
+            let mut request = Vec::from([host_fns::Methods::StorageWriteBatch as u8]);
+            request.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+
+            for (account, slot, index, data) in &entries {
+                if with_accounts {
+                    request.extend_from_slice(&account.raw());
+                } else if *account != running_address {
+                    anyhow::bail!("host protocol doesn't support writes to {account:?}");
+                }
+                request.extend_from_slice(&slot.raw());
+                request.extend_from_slice(&index.to_le_bytes());
+                push_slice(&mut request, data);
+            }
+
+            log_debug!(entries = entries.len(); "sending storage batch to host");
+
+            request
+        };
+
+        self.call(&request, Reply::Status).await?;
+
+        Ok(())
+    }
+
+    fn encode_result(res: &Result<vm::FullRunOk>) -> Result<Vec<u8>> {
         let data = match res {
             Ok((RunOk::Return(data), _)) => {
                 let mut encoded = Vec::from([ResultCode::Return as u8]);
@@ -297,14 +520,23 @@ impl Host {
 
                 encoded
             }
-            Ok((RunOk::VMError(data, _), fp)) => {
+            Ok((RunOk::VMError(data, cause), fp)) => {
                 let mut encoded = Vec::from([ResultCode::VmError as u8]);
 
                 let fp = calldata::to_value(fp)?;
-                let val = calldata::Value::Map(BTreeMap::from([
+                let mut val = BTreeMap::from([
                     ("message".to_owned(), data.as_str().into()),
                     ("fingerprint".to_owned(), fp),
-                ]));
+                ]);
+
+                if let Some(report) = cause
+                    .as_ref()
+                    .and_then(|c| c.downcast_ref::<crate::errors::DisagreementReport>())
+                {
+                    val.insert("disagreement".to_owned(), report.to_calldata());
+                }
+
+                let val = calldata::Value::Map(val);
 
                 calldata::encode_to(&mut encoded, &val);
 
@@ -318,42 +550,60 @@ impl Host {
             }
         };
 
-        sock.write_all(&[host_fns::Methods::ConsumeResult as u8])?;
-        write_slice(sock, &data)?;
+        Ok(data)
+    }
+
+    pub async fn consume_result(&self, res: &Result<vm::FullRunOk>, gas_used: u64) -> Result<()> {
+        log_trace!("consume_result");
+
+        // storage delta is committed only on success, otherwise it is discarded
+        let data = match res {
+            Ok((RunOk::Return(_), _)) => match self.flush_storage().await {
+                Ok(()) => Self::encode_result(res)?,
+                Err(e) => {
+                    log_warn!(error:ah = &e; "flushing storage failed");
+                    // host waits for the result, so it must be sent even for internal errors
+                    match crate::errors::unwrap_vm_errors(e) {
+                        Ok(e) => Self::encode_result(&Ok((e, None)))?,
+                        Err(e) => Self::encode_result(&Err(e))?,
+                    }
+                }
+            },
+            _ => Self::encode_result(res)?,
+        };
 
-        log_debug!("wrote consumed result to host");
+        let mut request = Vec::from([host_fns::Methods::ConsumeResult as u8]);
+        push_slice(&mut request, &data);
+        if self.protocol_version >= PROTOCOL_VERSION_GAS {
+            request.extend_from_slice(&gas_used.to_le_bytes());
+        }
 
-        let mut int_buf = [0; 1];
-        sock.read_exact(&mut int_buf)?;
+        self.call(&request, Reply::Ack).await?;
 
         log_debug!("consume_result: ACK");
 
         Ok(())
     }
 
-    pub fn get_leader_result(&mut self, call_no: u32) -> Result<Option<vm::RunOk>> {
+    pub async fn get_leader_result(&self, call_no: u32) -> Result<Option<vm::RunOk>> {
         log_trace!("get_leader_result");
 
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::GetLeaderNondetResult as u8])?;
-        sock.write_all(&call_no.to_le_bytes())?;
+        let mut request = Vec::from([host_fns::Methods::GetLeaderNondetResult as u8]);
+        request.extend_from_slice(&call_no.to_le_bytes());
 
-        match read_host_error(sock)? {
-            host_fns::Errors::Ok => {}
-            host_fns::Errors::IAmLeader => {
+        let leaders_result = match self.exchange(&request, Reply::Slice).await? {
+            Ok(res) => res,
+            Err(host_fns::Errors::IAmLeader) => {
                 return Ok(None);
             }
-            e => return Err(crate::errors::VMError(e.str_snake_case().to_owned(), None).into()),
-        }
-
-        let leaders_result = read_bytes(sock)?;
+            Err(e) => return Err(host_error(e)),
+        };
 
-        let rest = &leaders_result[1..];
+        let Some((code, rest)) = leaders_result.split_first() else {
+            anyhow::bail!("host returned empty leader result");
+        };
 
-        let res = match leaders_result[0] {
+        let res = match *code {
             x if x == ResultCode::Return as u8 => vm::RunOk::Return(rest.into()),
             x if x == ResultCode::UserError as u8 => {
                 vm::RunOk::UserError(String::from(str::from_utf8(rest)?))
@@ -366,186 +616,200 @@ impl Host {
         Ok(Some(res))
     }
 
-    pub fn post_nondet_result(&mut self, call_no: u32, res: &vm::RunOk) -> Result<()> {
+    pub async fn post_nondet_result(&self, call_no: u32, res: &vm::RunOk) -> Result<()> {
         log_trace!(call_no = call_no; "post_nondet_result");
 
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::PostNondetResult as u8])?;
-        sock.write_all(&call_no.to_le_bytes())?;
-
-        write_slice(sock, &Vec::from_iter(res.as_bytes_iter()))?;
+        let mut request = Vec::from([host_fns::Methods::PostNondetResult as u8]);
+        request.extend_from_slice(&call_no.to_le_bytes());
+        push_slice(&mut request, &Vec::from_iter(res.as_bytes_iter()));
 
-        sock.flush()?;
-
-        handle_host_error(sock)?;
+        self.call(&request, Reply::Status).await?;
 
         Ok(())
     }
 
-    pub fn post_message(
-        &mut self,
+    pub async fn post_message(
+        &self,
         account: &calldata::Address,
         calldata: &[u8],
         data: &str,
     ) -> Result<()> {
         log_trace!("post_message");
 
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::PostMessage as u8])?;
-        sock.write_all(&account.raw())?;
-
-        write_slice(sock, calldata)?;
-        write_slice(sock, data.as_bytes())?;
-
-        sock.flush()?;
+        let mut request = Vec::from([host_fns::Methods::PostMessage as u8]);
+        request.extend_from_slice(&account.raw());
+        push_slice(&mut request, calldata);
+        push_slice(&mut request, data.as_bytes());
 
-        handle_host_error(sock)?;
+        self.call(&request, Reply::Status).await?;
 
         Ok(())
     }
 
-    pub fn deploy_contract(&mut self, calldata: &[u8], code: &[u8], data: &str) -> Result<()> {
+    pub async fn deploy_contract(&self, calldata: &[u8], code: &[u8], data: &str) -> Result<()> {
         log_trace!("deploy_contract");
 
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::DeployContract as u8])?;
-
-        write_slice(sock, calldata)?;
-        write_slice(sock, code)?;
-        write_slice(sock, data.as_bytes())?;
-
-        sock.flush()?;
+        let mut request = Vec::from([host_fns::Methods::DeployContract as u8]);
+        push_slice(&mut request, calldata);
+        push_slice(&mut request, code);
+        push_slice(&mut request, data.as_bytes());
 
-        handle_host_error(sock)?;
+        self.call(&request, Reply::Status).await?;
 
         Ok(())
     }
 
-    pub fn consume_fuel(&mut self, gas: u64) -> Result<()> {
+    pub async fn consume_fuel(&self, gas: u64) -> Result<()> {
         log_trace!("consume_fuel");
 
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::ConsumeFuel as u8])?;
-        sock.write_all(&gas.to_le_bytes())?;
+        let mut request = Vec::from([host_fns::Methods::ConsumeFuel as u8]);
+        request.extend_from_slice(&gas.to_le_bytes());
+
+        self.call(&request, Reply::None).await?;
 
-        sock.flush()?;
         Ok(())
     }
 
-    pub fn eth_call(&mut self, address: calldata::Address, calldata: &[u8]) -> Result<Box<[u8]>> {
+    pub async fn eth_call(&self, address: calldata::Address, calldata: &[u8]) -> Result<Box<[u8]>> {
         log_trace!("eth_call");
 
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::EthCall as u8])?;
-
-        sock.write_all(&address.raw())?;
+        let mut request = Vec::from([host_fns::Methods::EthCall as u8]);
+        request.extend_from_slice(&address.raw());
+        push_slice(&mut request, calldata);
 
-        sock.write_all(&(calldata.len() as u32).to_le_bytes())?;
-        sock.write_all(calldata)?;
+        let res = self.call(&request, Reply::Slice).await?;
 
-        handle_host_error(sock)?;
-
-        read_bytes(sock)
+        Ok(res.into_boxed_slice())
     }
 
-    pub fn eth_send(
-        &mut self,
+    pub async fn eth_send(
+        &self,
         address: calldata::Address,
         calldata: &[u8],
         data: &str,
     ) -> Result<()> {
         log_trace!("eth_send");
 
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::EthSend as u8])?;
-
-        sock.write_all(&address.raw())?;
-
-        sock.write_all(&(calldata.len() as u32).to_le_bytes())?;
-        sock.write_all(calldata)?;
-
-        sock.write_all(&(data.len() as u32).to_le_bytes())?;
-        sock.write_all(data.as_bytes())?;
-
-        sock.flush()?;
+        let mut request = Vec::from([host_fns::Methods::EthSend as u8]);
+        request.extend_from_slice(&address.raw());
+        push_slice(&mut request, calldata);
+        push_slice(&mut request, data.as_bytes());
 
-        handle_host_error(sock)?;
+        self.call(&request, Reply::Status).await?;
 
         Ok(())
     }
 
-    pub fn get_balance(&mut self, address: calldata::Address) -> Result<primitive_types::U256> {
+    pub async fn get_balance(&self, address: calldata::Address) -> Result<primitive_types::U256> {
         log_trace!("get_balance");
 
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::GetBalance as u8])?;
-
-        sock.write_all(&address.raw())?;
+        let mut request = Vec::from([host_fns::Methods::GetBalance as u8]);
+        request.extend_from_slice(&address.raw());
 
-        handle_host_error(sock)?;
+        let res = self.call(&request, Reply::Fixed(32)).await?;
 
-        let mut buf: [u8; 32] = [0; 32];
-        sock.read_exact(&mut buf)?;
-        Ok(primitive_types::U256::from_little_endian(&buf))
+        Ok(primitive_types::U256::from_little_endian(&res))
     }
 
-    pub fn remaining_fuel_as_gen(&mut self) -> Result<u64> {
+    pub async fn remaining_fuel_as_gen(&self) -> Result<u64> {
         log_trace!("remaining_fuel_as_gen");
 
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::RemainingFuelAsGen as u8])?;
+        let res = self
+            .call(
+                &[host_fns::Methods::RemainingFuelAsGen as u8],
+                Reply::Fixed(8),
+            )
+            .await?;
+
+        let mut buf: [u8; 8] = [0; 8];
+        buf.copy_from_slice(&res);
+        Ok(u64::from_le_bytes(buf))
+    }
 
-        handle_host_error(sock)?;
+    /// Older protocols have no gas limit, so execution is not limited by gas
+    pub async fn get_gas_limit(&self) -> Result<u64> {
+        log_trace!("get_gas_limit");
+
+        if self.protocol_version < PROTOCOL_VERSION_GAS {
+            return Ok(u64::MAX);
+        }
+
+        let res = self
+            .call(&[host_fns::Methods::GetGasLimit as u8], Reply::Fixed(8))
+            .await?;
 
         let mut buf: [u8; 8] = [0; 8];
-        sock.read_exact(&mut buf)?;
+        buf.copy_from_slice(&res);
         Ok(u64::from_le_bytes(buf))
     }
 
-    pub fn post_event(&mut self, topics: &[[u8; 32]], blob: &[u8]) -> Result<()> {
+    /// `name` is present for events declared in contract schema, older protocols don't receive it
+    pub async fn post_event(
+        &self,
+        topics: &[[u8; 32]],
+        blob: &[u8],
+        name: Option<&str>,
+    ) -> Result<()> {
         log_trace!("post_event");
 
-        let Ok(mut sock) = (*self.sock).lock() else {
-            anyhow::bail!("can't take lock")
-        };
-        let sock: &mut dyn Sock = &mut *sock;
-        sock.write_all(&[host_fns::Methods::PostEvent as u8])?;
-        sock.write_all(&[topics.len() as u8])?;
+        let mut request = Vec::from([host_fns::Methods::PostEvent as u8, topics.len() as u8]);
 
         for topic in topics {
-            sock.write_all(topic.as_ref())?;
+            request.extend_from_slice(topic.as_ref());
         }
 
-        write_slice(sock, blob)?;
+        push_slice(&mut request, blob);
 
-        sock.flush()?;
+        if self.protocol_version >= PROTOCOL_VERSION_EVENT_NAMES {
+            push_slice(&mut request, name.unwrap_or_default().as_bytes());
+        }
 
-        handle_host_error(sock)?;
+        self.call(&request, Reply::Status).await?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::DisagreementReport;
+
+    #[test]
+    fn vm_error_carries_disagreement() {
+        let abs = num_bigint::BigInt::from(u64::MAX) * 1000;
+        let equivalence = calldata::Value::Map(BTreeMap::from([(
+            "Tolerance".to_owned(),
+            calldata::Value::Map(BTreeMap::from([(
+                "abs".to_owned(),
+                calldata::Value::Number(abs),
+            )])),
+        )]));
+        let report = DisagreementReport {
+            call_no: 1,
+            equivalence: Some(equivalence.clone()),
+            leaders_result: calldata::Value::Null,
+            validator_results: Vec::new(),
+        };
+        let res = Ok((
+            RunOk::VMError("validator_disagrees".into(), Some(report.into())),
+            None,
+        ));
+
+        let encoded = Host::encode_result(&res).unwrap();
+        assert_eq!(encoded[0], ResultCode::VmError as u8);
+
+        let calldata::Value::Map(val) = calldata::decode(&encoded[1..]).unwrap() else {
+            panic!("result is not a map");
+        };
+        let disagreement = val.get("disagreement").expect("disagreement is absent");
+        let calldata::Value::Map(disagreement) = disagreement else {
+            panic!("disagreement is not a map");
+        };
+        assert_eq!(
+            disagreement.get("call_no"),
+            Some(&calldata::Value::Number(1.into()))
+        );
+        assert_eq!(disagreement.get("equivalence"), Some(&equivalence));
+    }
+}
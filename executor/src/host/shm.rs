@@ -0,0 +1,247 @@
+//! Shared memory ring buffer for bulk host responses
+//!
+//! GenVM creates a memfd and passes it to the host over the control socket (`SCM_RIGHTS`)
+//! together with `size: u32_le` of the data region. Memory layout is
+//! `head: u32, tail: u32, <padding up to HEADER_SIZE>, data: [u8; size]`, where `head` and `tail`
+//! are monotonic (wrapping) byte counters: host advances `head` after writing a payload and GenVM
+//! advances `tail` after copying it out. Payload may wrap around the end of the data region, `size`
+//! must be a power of two for counters to stay consistent when they wrap
+//!
+//! Framing stays on the socket: if the highest bit of frame `len` is set, frame body is
+//! `pos: u32_le` and `len & !RING_BIT` bytes of payload are located at `pos % size` in the ring.
+//! Host must place payloads in the same order as it writes frames to the socket
+
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use anyhow::{Context, Result};
+
+use genvm_common::*;
+
+pub const RING_BIT: u32 = 1 << 31;
+
+/// Payloads smaller than this are sent inline, copying them twice is not worth it
+pub const THRESHOLD: usize = 4 * 1024;
+
+pub const DEFAULT_SIZE: u32 = 16 * 1024 * 1024;
+
+const HEADER_SIZE: usize = 64;
+
+pub struct Ring {
+    map: NonNull<u8>,
+    size: u32,
+}
+
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe {
+            match rustix::mm::munmap(self.map.as_ptr().cast(), self.map_len()) {
+                Ok(_) => {}
+                Err(e) => {
+                    log_error!(errno:? = e; "munmap failed")
+                }
+            }
+        }
+    }
+}
+
+impl Ring {
+    fn map_len(&self) -> usize {
+        HEADER_SIZE + self.size as usize
+    }
+
+    fn map(fd: &OwnedFd, size: u32) -> Result<Self> {
+        let ptr = unsafe {
+            rustix::mm::mmap(
+                std::ptr::null_mut(),
+                HEADER_SIZE + size as usize,
+                rustix::mm::ProtFlags::READ | rustix::mm::ProtFlags::WRITE,
+                rustix::mm::MapFlags::SHARED,
+                fd,
+                0,
+            )?
+        };
+
+        Ok(Self {
+            map: NonNull::new(ptr.cast()).unwrap(),
+            size,
+        })
+    }
+
+    /// Creates a ring with `size` bytes of data and returns the fd that must be sent to the host
+    pub fn create(size: u32) -> Result<(Self, OwnedFd)> {
+        if !size.is_power_of_two() || size >= RING_BIT {
+            anyhow::bail!("invalid ring size {size}");
+        }
+
+        let fd = rustix::fs::memfd_create("genvm-host-ring", rustix::fs::MemfdFlags::CLOEXEC)
+            .context("memfd_create")?;
+        rustix::fs::ftruncate(&fd, (HEADER_SIZE + size as usize) as u64).context("ftruncate")?;
+
+        let ring = Self::map(&fd, size)?;
+        Ok((ring, fd))
+    }
+
+    /// Maps a ring received from GenVM
+    pub fn open(fd: &OwnedFd, size: u32) -> Result<Self> {
+        let file_size = rustix::fs::fstat(fd)?.st_size as u64;
+        if !size.is_power_of_two()
+            || size >= RING_BIT
+            || file_size < (HEADER_SIZE + size as usize) as u64
+        {
+            anyhow::bail!("invalid ring size {size}, file size is {file_size}");
+        }
+
+        Self::map(fd, size)
+    }
+
+    fn head(&self) -> &AtomicU32 {
+        unsafe { &*(self.map.as_ptr() as *const AtomicU32) }
+    }
+
+    fn tail(&self) -> &AtomicU32 {
+        unsafe { &*(self.map.as_ptr().add(4) as *const AtomicU32) }
+    }
+
+    fn data(&self) -> *mut u8 {
+        unsafe { self.map.as_ptr().add(HEADER_SIZE) }
+    }
+
+    /// Consumer side: copies payload out and releases its space
+    pub fn read(&self, pos: u32, len: u32) -> Result<Vec<u8>> {
+        let tail = self.tail().load(Ordering::Acquire);
+        let head = self.head().load(Ordering::Acquire);
+
+        if pos != tail || len > head.wrapping_sub(tail) {
+            anyhow::bail!("invalid ring payload pos={pos} len={len} head={head} tail={tail}");
+        }
+
+        let mut res = vec![0; len as usize];
+
+        let off = (pos % self.size) as usize;
+        let first = usize::min(len as usize, self.size as usize - off);
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.data().add(off), res.as_mut_ptr(), first);
+            std::ptr::copy_nonoverlapping(
+                self.data(),
+                res.as_mut_ptr().add(first),
+                len as usize - first,
+            );
+        }
+
+        self.tail().store(pos.wrapping_add(len), Ordering::Release);
+
+        Ok(res)
+    }
+
+    /// Producer side: returns position of the payload or `None` if there is not enough space
+    pub fn write(&self, what: &[u8]) -> Option<u32> {
+        let len = u32::try_from(what.len()).ok().filter(|x| *x < RING_BIT)?;
+
+        let head = self.head().load(Ordering::Acquire);
+        let tail = self.tail().load(Ordering::Acquire);
+
+        if len > self.size - head.wrapping_sub(tail) {
+            return None;
+        }
+
+        let off = (head % self.size) as usize;
+        let first = usize::min(what.len(), self.size as usize - off);
+        unsafe {
+            std::ptr::copy_nonoverlapping(what.as_ptr(), self.data().add(off), first);
+            std::ptr::copy_nonoverlapping(
+                what.as_ptr().add(first),
+                self.data(),
+                what.len() - first,
+            );
+        }
+
+        self.head().store(head.wrapping_add(len), Ordering::Release);
+
+        Some(head)
+    }
+}
+
+pub fn send_fd(sock: BorrowedFd<'_>, fd: &OwnedFd, size: u32) -> Result<()> {
+    use rustix::net::{SendAncillaryBuffer, SendAncillaryMessage, SendFlags};
+
+    let mut space = [0; rustix::cmsg_space!(ScmRights(1))];
+    let mut cmsg = SendAncillaryBuffer::new(&mut space);
+    let fds = [fd.as_fd()];
+    if !cmsg.push(SendAncillaryMessage::ScmRights(&fds)) {
+        anyhow::bail!("can't push fd to ancillary buffer");
+    }
+
+    let size = size.to_le_bytes();
+    let sent = rustix::net::sendmsg(
+        sock,
+        &[std::io::IoSlice::new(&size)],
+        &mut cmsg,
+        SendFlags::empty(),
+    )
+    .context("sending ring fd")?;
+
+    if sent != size.len() {
+        anyhow::bail!("short write while sending ring fd");
+    }
+
+    Ok(())
+}
+
+pub fn recv_fd(sock: BorrowedFd<'_>) -> Result<(OwnedFd, u32)> {
+    use rustix::net::{RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags};
+
+    let mut space = [0; rustix::cmsg_space!(ScmRights(1))];
+    let mut cmsg = RecvAncillaryBuffer::new(&mut space);
+    let mut size = [0; 4];
+
+    let res = rustix::net::recvmsg(
+        sock,
+        &mut [std::io::IoSliceMut::new(&mut size)],
+        &mut cmsg,
+        RecvFlags::CMSG_CLOEXEC,
+    )
+    .context("receiving ring fd")?;
+
+    if res.bytes != size.len() {
+        anyhow::bail!("short read while receiving ring fd");
+    }
+
+    let mut fd = None;
+    for msg in cmsg.drain() {
+        if let RecvAncillaryMessage::ScmRights(mut fds) = msg {
+            fd = fd.or(fds.next());
+        }
+    }
+
+    let fd = fd.ok_or_else(|| anyhow::anyhow!("host didn't receive ring fd"))?;
+
+    Ok((fd, u32::from_le_bytes(size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_wraps() {
+        let (ring, fd) = Ring::create(16).unwrap();
+        let other = Ring::open(&fd, 16).unwrap();
+
+        let pos = other.write(&[1; 10]).unwrap();
+        assert_eq!(ring.read(pos, 10).unwrap(), [1; 10]);
+
+        let pos = other.write(&[2, 3, 4, 5, 6, 7, 8, 9, 10, 11]).unwrap();
+        assert!(other.write(&[0; 7]).is_none());
+        assert_eq!(
+            ring.read(pos, 10).unwrap(),
+            [2, 3, 4, 5, 6, 7, 8, 9, 10, 11]
+        );
+
+        assert!(ring.read(pos, 1).is_err());
+    }
+}
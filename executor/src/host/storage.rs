@@ -0,0 +1,365 @@
+//! Per-transaction storage overlay, it implements "mid-level storage" from `doc/adr/8. storage.md`
+//!
+//! Reads are cached by `(mode, account, slot)`, writes are coalesced into a delta that is sent
+//! to the host in a single `storage_write_batch` message. Writes of contracts called in mutating mode
+//! are kept in separate layers until the call finishes
+
+use std::collections::{BTreeMap, HashMap};
+
+use genvm_common::calldata::Address;
+
+use super::SlotID;
+use crate::public_abi::StorageType;
+
+/// If cache grows above this size it is dropped, delta is kept
+const MAX_CACHED_BYTES: usize = 64 * 1024 * 1024;
+
+/// Known non-overlapping and non-adjacent byte ranges of a single slot
+#[derive(Default, Debug)]
+pub struct Segments(BTreeMap<u32, Vec<u8>>);
+
+impl Segments {
+    /// Copies bytes to `buf` only if whole range is known
+    pub fn read(&self, index: u32, buf: &mut [u8]) -> bool {
+        let Some((start, data)) = self.0.range(..=index).next_back() else {
+            return false;
+        };
+
+        let off = (index - start) as usize;
+        if off + buf.len() > data.len() {
+            return false;
+        }
+
+        buf.copy_from_slice(&data[off..off + buf.len()]);
+        true
+    }
+
+    /// Copies all known bytes that intersect with `buf`
+    pub fn patch(&self, index: u32, buf: &mut [u8]) {
+        let buf_start = index as u64;
+        let buf_end = buf_start + buf.len() as u64;
+
+        let first = self
+            .0
+            .range(..=index)
+            .next_back()
+            .map(|(k, _)| *k)
+            .unwrap_or(index);
+
+        for (start, data) in self.0.range(first..) {
+            let start = *start as u64;
+            if start >= buf_end {
+                break;
+            }
+
+            let from = u64::max(start, buf_start);
+            let to = u64::min(start + data.len() as u64, buf_end);
+            if from >= to {
+                continue;
+            }
+
+            buf[(from - buf_start) as usize..(to - buf_start) as usize]
+                .copy_from_slice(&data[(from - start) as usize..(to - start) as usize]);
+        }
+    }
+
+    /// Writes `what` at `index`, merging it with all touching segments
+    ///
+    /// Returns new total size
+    pub fn write(&mut self, index: u32, what: &[u8]) -> usize {
+        let write_start = index as u64;
+        let write_end = write_start + what.len() as u64;
+
+        let mut merged_start = write_start;
+        let mut merged_end = write_end;
+
+        let touching: Vec<u32> = self
+            .0
+            .range(..=u32::try_from(write_end).unwrap_or(u32::MAX))
+            .rev()
+            .take_while(|(start, data)| **start as u64 + data.len() as u64 >= write_start)
+            .map(|(start, _)| *start)
+            .collect();
+
+        let touching: Vec<(u32, Vec<u8>)> = touching
+            .into_iter()
+            .map(|k| (k, self.0.remove(&k).unwrap()))
+            .collect();
+
+        for (start, data) in &touching {
+            merged_start = u64::min(merged_start, *start as u64);
+            merged_end = u64::max(merged_end, *start as u64 + data.len() as u64);
+        }
+
+        let mut merged = vec![0; (merged_end - merged_start) as usize];
+        for (start, data) in &touching {
+            let off = (*start as u64 - merged_start) as usize;
+            merged[off..off + data.len()].copy_from_slice(data);
+        }
+
+        let off = (write_start - merged_start) as usize;
+        merged[off..off + what.len()].copy_from_slice(what);
+
+        self.0.insert(merged_start as u32, merged);
+
+        self.size()
+    }
+
+    pub fn size(&self) -> usize {
+        self.0.values().map(|x| x.len()).sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.0.iter().map(|(k, v)| (*k, v.as_slice()))
+    }
+}
+
+#[derive(Default, Debug, serde::Serialize)]
+pub struct Stats {
+    pub cache_hits: u64,
+    pub host_reads: u64,
+    pub writes: u64,
+}
+
+type Delta = BTreeMap<(Address, SlotID), Segments>;
+
+pub struct Overlay {
+    running_address: Address,
+    cache: HashMap<(u8, Address, SlotID), Segments>,
+    cached_bytes: usize,
+    /// delta of the transaction followed by deltas of unfinished mutating calls
+    layers: Vec<Delta>,
+    pub stats: Stats,
+}
+
+impl Overlay {
+    pub fn new(running_address: Address) -> Self {
+        Self {
+            running_address,
+            cache: HashMap::new(),
+            cached_bytes: 0,
+            layers: vec![Delta::new()],
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn running_address(&self) -> Address {
+        self.running_address
+    }
+
+    pub fn read_cached(
+        &mut self,
+        mode: StorageType,
+        account: Address,
+        slot: SlotID,
+        index: u32,
+        buf: &mut [u8],
+    ) -> bool {
+        let hit = self
+            .cache
+            .get(&(mode as u8, account, slot))
+            .is_some_and(|seg| seg.read(index, buf));
+
+        if hit {
+            self.stats.cache_hits += 1;
+        } else {
+            self.stats.host_reads += 1;
+        }
+
+        hit
+    }
+
+    /// Applies pending writes to data that was read from the host and caches it
+    pub fn on_host_read(
+        &mut self,
+        mode: StorageType,
+        account: Address,
+        slot: SlotID,
+        index: u32,
+        buf: &mut [u8],
+    ) {
+        if Self::sees_delta(mode) {
+            for layer in &self.layers {
+                if let Some(seg) = layer.get(&(account, slot)) {
+                    seg.patch(index, buf);
+                }
+            }
+        }
+
+        self.cache_write(mode, account, slot, index, buf);
+    }
+
+    pub fn write(&mut self, account: Address, slot: SlotID, index: u32, what: &[u8]) {
+        self.stats.writes += 1;
+
+        self.layers
+            .last_mut()
+            .unwrap()
+            .entry((account, slot))
+            .or_default()
+            .write(index, what);
+
+        self.cache_write(StorageType::Default, account, slot, index, what);
+    }
+
+    /// Following writes can be discarded with [`Self::discard_layer`]
+    pub fn begin_layer(&mut self) {
+        self.layers.push(Delta::new());
+    }
+
+    /// Moves writes of the innermost layer to the enclosing one
+    pub fn commit_layer(&mut self) {
+        if self.layers.len() < 2 {
+            return;
+        }
+
+        let top = self.layers.pop().unwrap();
+        let below = self.layers.last_mut().unwrap();
+        for (key, seg) in top {
+            let dst = below.entry(key).or_default();
+            for (index, data) in seg.iter() {
+                dst.write(index, data);
+            }
+        }
+    }
+
+    /// Drops writes of the innermost layer along with cached data they were merged into
+    pub fn discard_layer(&mut self) {
+        if self.layers.len() < 2 {
+            return;
+        }
+
+        let top = self.layers.pop().unwrap();
+        for (account, slot) in top.keys() {
+            if let Some(seg) = self
+                .cache
+                .remove(&(StorageType::Default as u8, *account, *slot))
+            {
+                self.cached_bytes -= seg.size();
+            }
+        }
+    }
+
+    /// Only reads in the default mode observe writes of the current transaction
+    fn sees_delta(mode: StorageType) -> bool {
+        mode == StorageType::Default
+    }
+
+    fn cache_write(
+        &mut self,
+        mode: StorageType,
+        account: Address,
+        slot: SlotID,
+        index: u32,
+        what: &[u8],
+    ) {
+        let seg = self.cache.entry((mode as u8, account, slot)).or_default();
+        let old_size = seg.size();
+        let new_size = seg.write(index, what);
+        self.cached_bytes = self.cached_bytes + new_size - old_size;
+
+        if self.cached_bytes > MAX_CACHED_BYTES {
+            self.cache.clear();
+            self.cached_bytes = 0;
+        }
+    }
+
+    pub fn has_delta(&self) -> bool {
+        !self.layers[0].is_empty()
+    }
+
+    /// Takes writes of the transaction, unfinished mutating calls are discarded
+    pub fn take_delta(&mut self) -> Delta {
+        while self.layers.len() > 1 {
+            self.discard_layer();
+        }
+
+        std::mem::take(&mut self.layers[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_merge() {
+        let mut seg = Segments::default();
+        seg.write(10, &[1, 2, 3]);
+        seg.write(13, &[4]);
+        seg.write(0, &[9]);
+
+        assert_eq!(seg.0.len(), 2);
+
+        let mut buf = [0; 4];
+        assert!(seg.read(10, &mut buf));
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        let mut buf = [0; 4];
+        assert!(!seg.read(11, &mut buf));
+
+        seg.write(1, &[0; 9]);
+        assert_eq!(seg.0.len(), 1);
+        assert_eq!(seg.size(), 14);
+    }
+
+    #[test]
+    fn segments_patch() {
+        let mut seg = Segments::default();
+        seg.write(2, &[1, 2]);
+        seg.write(6, &[3]);
+
+        let mut buf = [7; 6];
+        seg.patch(1, &mut buf);
+        assert_eq!(buf, [7, 1, 2, 7, 7, 3]);
+    }
+
+    #[test]
+    fn overlay_reads_own_writes() {
+        let addr = Address::from([1; 20]);
+        let mut overlay = Overlay::new(addr);
+        overlay.write(addr, SlotID::ZERO, 2, &[5, 6]);
+
+        let mut buf = [0; 2];
+        assert!(overlay.read_cached(StorageType::Default, addr, SlotID::ZERO, 2, &mut buf));
+        assert_eq!(buf, [5, 6]);
+
+        let mut buf = [0; 4];
+        assert!(!overlay.read_cached(StorageType::Default, addr, SlotID::ZERO, 0, &mut buf));
+        overlay.on_host_read(StorageType::Default, addr, SlotID::ZERO, 0, &mut buf);
+        assert_eq!(buf, [0, 0, 5, 6]);
+
+        let mut buf = [0; 2];
+        assert!(!overlay.read_cached(StorageType::LatestFinal, addr, SlotID::ZERO, 2, &mut buf));
+    }
+
+    #[test]
+    fn overlay_layers() {
+        let addr = Address::from([1; 20]);
+        let other = Address::from([2; 20]);
+        let mut overlay = Overlay::new(addr);
+
+        overlay.begin_layer();
+        overlay.write(other, SlotID::ZERO, 0, &[1]);
+        overlay.discard_layer();
+
+        let mut buf = [0; 1];
+        assert!(!overlay.read_cached(StorageType::Default, other, SlotID::ZERO, 0, &mut buf));
+        assert!(!overlay.has_delta());
+
+        overlay.begin_layer();
+        overlay.write(other, SlotID::ZERO, 0, &[2]);
+        overlay.begin_layer();
+        overlay.write(other, SlotID::ZERO, 1, &[3]);
+        overlay.commit_layer();
+        overlay.commit_layer();
+
+        let mut buf = [0; 2];
+        overlay.on_host_read(StorageType::Default, other, SlotID::ZERO, 0, &mut buf);
+        assert_eq!(buf, [2, 3]);
+
+        let delta = overlay.take_delta();
+        assert_eq!(delta.len(), 1);
+        assert!(delta.contains_key(&(other, SlotID::ZERO)));
+    }
+}
@@ -0,0 +1,282 @@
+//! Async client side of the host protocol
+//!
+//! Before protocol version 3 only one request can be in flight, starting from it each request and
+//! response is framed as `id: u32, len: u32, payload`, where payload is the same as in the
+//! sequential protocol. It allows to have multiple requests in flight and host may answer them in
+//! any order. [`super::shm`] can additionally be used to move bulk response payloads out of the
+//! socket
+
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU32;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use genvm_common::*;
+
+use super::{host_fns, shm};
+use crate::record;
+
+/// Shape of a response to a request
+#[derive(Debug, Clone, Copy)]
+pub enum Reply {
+    /// there is no response at all, for instance for `consume_fuel`
+    None,
+    /// single byte that is not an error code, used by `consume_result`
+    Ack,
+    /// only an error code
+    Status,
+    /// error code followed by exactly `n` bytes
+    Fixed(usize),
+    /// error code followed by a slice
+    Slice,
+}
+
+pub type Response = std::result::Result<Vec<u8>, host_fns::Errors>;
+
+pub trait AsyncSock: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+impl<T> AsyncSock for T where T: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+async fn read_reply(reader: &mut (impl AsyncRead + Unpin), reply: Reply) -> Result<Response> {
+    let mut byte = [0; 1];
+
+    match reply {
+        Reply::None => return Ok(Ok(Vec::new())),
+        Reply::Ack => {
+            reader.read_exact(&mut byte).await?;
+            return Ok(Ok(Vec::new()));
+        }
+        Reply::Status | Reply::Fixed(_) | Reply::Slice => {}
+    }
+
+    reader.read_exact(&mut byte).await?;
+    let err = host_fns::Errors::try_from(byte[0])
+        .map_err(|_| anyhow::anyhow!("invalid error id {}", byte[0]))?;
+
+    if err != host_fns::Errors::Ok {
+        return Ok(Err(err));
+    }
+
+    let len = match reply {
+        Reply::Fixed(len) => len,
+        Reply::Slice => reader.read_u32_le().await? as usize,
+        _ => 0,
+    };
+
+    let mut res = vec![0; len];
+    reader.read_exact(&mut res).await?;
+
+    Ok(Ok(res))
+}
+
+pub struct Sequential {
+    sock: tokio::sync::Mutex<tokio::io::BufStream<Box<dyn AsyncSock>>>,
+}
+
+impl Sequential {
+    fn new(sock: Box<dyn AsyncSock>) -> Self {
+        Self {
+            sock: tokio::sync::Mutex::new(tokio::io::BufStream::new(sock)),
+        }
+    }
+
+    async fn call(&self, request: &[u8], reply: Reply) -> Result<Response> {
+        let mut sock = self.sock.lock().await;
+
+        sock.write_all(request).await?;
+        sock.flush().await?;
+
+        read_reply(&mut *sock, reply).await
+    }
+}
+
+type Pending = HashMap<u32, tokio::sync::oneshot::Sender<Vec<u8>>>;
+
+pub struct Pipelined {
+    writer: tokio::sync::Mutex<tokio::io::BufWriter<tokio::io::WriteHalf<Box<dyn AsyncSock>>>>,
+    /// becomes `None` when connection is closed
+    pending: Arc<Mutex<Option<Pending>>>,
+    next_id: AtomicU32,
+    reader: tokio::task::JoinHandle<()>,
+}
+
+impl Pipelined {
+    fn new(sock: Box<dyn AsyncSock>, ring: Option<shm::Ring>) -> Self {
+        let (read, write) = tokio::io::split(sock);
+
+        let pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        let reader = tokio::spawn(Self::read_loop(
+            tokio::io::BufReader::new(read),
+            pending.clone(),
+            ring,
+        ));
+
+        Self {
+            writer: tokio::sync::Mutex::new(tokio::io::BufWriter::new(write)),
+            pending,
+            next_id: AtomicU32::new(0),
+            reader,
+        }
+    }
+
+    async fn read_loop(
+        mut reader: tokio::io::BufReader<tokio::io::ReadHalf<Box<dyn AsyncSock>>>,
+        pending: Arc<Mutex<Option<Pending>>>,
+        ring: Option<shm::Ring>,
+    ) {
+        let res: Result<()> = async {
+            loop {
+                let id = reader.read_u32_le().await?;
+                let len = reader.read_u32_le().await?;
+
+                // ring payloads must be consumed in order, so it is done here and not by the caller
+                let payload = if len & shm::RING_BIT != 0 {
+                    let pos = reader.read_u32_le().await?;
+                    let Some(ring) = &ring else {
+                        anyhow::bail!("host used ring, but it was not set up");
+                    };
+                    ring.read(pos, len & !shm::RING_BIT)?
+                } else {
+                    let mut payload = vec![0; len as usize];
+                    reader.read_exact(&mut payload).await?;
+                    payload
+                };
+
+                let sender = match pending.lock() {
+                    Ok(mut pending) => pending.as_mut().and_then(|p| p.remove(&id)),
+                    Err(_) => anyhow::bail!("can't take lock"),
+                };
+
+                match sender {
+                    Some(sender) => {
+                        let _ = sender.send(payload);
+                    }
+                    None => {
+                        log_warn!(id = id; "host responded to unknown request");
+                    }
+                }
+            }
+        }
+        .await;
+
+        if let Err(e) = res {
+            log_debug!(error:ah = &e; "host reader finished");
+        }
+
+        // dropping senders wakes up all pending requests
+        if let Ok(mut pending) = pending.lock() {
+            pending.take();
+        }
+    }
+
+    async fn call(&self, request: &[u8], reply: Reply) -> Result<Response> {
+        let id = self
+            .next_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+        let receiver = match reply {
+            Reply::None => None,
+            _ => {
+                let (sender, receiver) = tokio::sync::oneshot::channel();
+
+                let Ok(mut pending) = self.pending.lock() else {
+                    anyhow::bail!("can't take lock")
+                };
+                let Some(pending) = pending.as_mut() else {
+                    anyhow::bail!("host connection is closed")
+                };
+                pending.insert(id, sender);
+
+                Some(receiver)
+            }
+        };
+
+        let written: Result<()> = async {
+            let mut writer = self.writer.lock().await;
+
+            writer.write_u32_le(id).await?;
+            writer.write_u32_le(request.len() as u32).await?;
+            writer.write_all(request).await?;
+            writer.flush().await?;
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = written {
+            // host will never answer a request it didn't receive
+            if let Ok(mut pending) = self.pending.lock() {
+                if let Some(pending) = pending.as_mut() {
+                    pending.remove(&id);
+                }
+            }
+            return Err(e);
+        }
+
+        let Some(receiver) = receiver else {
+            return Ok(Ok(Vec::new()));
+        };
+
+        let payload = receiver
+            .await
+            .map_err(|_| anyhow::anyhow!("host connection is closed"))?;
+
+        read_reply(&mut payload.as_slice(), reply).await
+    }
+}
+
+impl Drop for Pipelined {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+pub enum Transport {
+    Sequential(Sequential),
+    Pipelined(Pipelined),
+    Replay(Arc<record::Replayer>),
+}
+
+fn replay(replayer: &record::Replayer, request: &[u8], reply: Reply) -> Result<Response> {
+    if let Reply::None = reply {
+        return Ok(Ok(Vec::new()));
+    }
+
+    // result is not answered from the recording, it is what replay is compared by
+    if request.first() == Some(&(host_fns::Methods::ConsumeResult as u8)) {
+        replayer.set_result(&request[1..]);
+        return Ok(Ok(Vec::new()));
+    }
+
+    match replayer.host(request)? {
+        record::HostResponse::Ok(data) => Ok(Ok(data.0)),
+        record::HostResponse::Error(code) => Ok(Err(host_fns::Errors::try_from(code)
+            .map_err(|_| anyhow::anyhow!("invalid error id {code}"))?)),
+    }
+}
+
+impl Transport {
+    /// must be called within tokio runtime
+    pub fn new(sock: Box<dyn AsyncSock>, pipelined: bool) -> Self {
+        if pipelined {
+            Transport::Pipelined(Pipelined::new(sock, None))
+        } else {
+            Transport::Sequential(Sequential::new(sock))
+        }
+    }
+
+    /// Pipelined transport which receives bulk payloads through `ring`, must be called within
+    /// tokio runtime
+    pub fn with_ring(sock: Box<dyn AsyncSock>, ring: shm::Ring) -> Self {
+        Transport::Pipelined(Pipelined::new(sock, Some(ring)))
+    }
+
+    pub async fn call(&self, request: &[u8], reply: Reply) -> Result<Response> {
+        match self {
+            Transport::Sequential(t) => t.call(request, reply).await,
+            Transport::Pipelined(t) => t.call(request, reply).await,
+            Transport::Replay(r) => replay(r, request, reply),
+        }
+    }
+}
@@ -5,19 +5,23 @@ mod host;
 pub mod memlimiter;
 pub mod mmap;
 pub mod modules;
+pub mod registry;
 pub mod runner;
+pub mod snapshot;
 pub mod ustar;
 pub mod vm;
 pub mod wasi;
 
 pub mod public_abi;
+pub mod record;
 
 pub mod version_timestamps;
 
 pub use genvm_common::calldata;
 use genvm_common::*;
 
-pub use host::{Host, MessageData, SlotID};
+pub use host::message::{root_offsets, AccountAddress};
+pub use host::{mock, Host, MessageData, SlotID};
 
 use anyhow::{Context, Result};
 use wasi::genlayer_sdk::TransformedMessage;
@@ -31,15 +35,20 @@ pub struct PublicArgs<'a> {
     pub allow_latest: bool,
     pub is_sync: bool,
     pub message: &'a MessageData,
+    /// where to record module answers to, or where to replay them from
+    pub tape: record::Tape,
 }
 
-pub fn create_supervisor(
+/// Everything that belongs to a single transaction
+async fn create_shared_data(
     config: &config::Config,
-    mut host: Host,
+    host: Host,
     cancellation: Arc<genvm_common::cancellation::Token>,
     host_data: genvm_modules_interfaces::HostData,
-    pub_args: PublicArgs,
-) -> Result<Arc<tokio::sync::Mutex<vm::Supervisor>>> {
+    pub_args: PublicArgs<'_>,
+) -> Result<Arc<vm::SharedData>> {
+    let deadline = vm::Deadline::after(config.timeouts.total_ms, "total");
+
     let modules = Modules {
         web: Arc::new(modules::Module::new(
             "web".into(),
@@ -47,6 +56,9 @@ pub fn create_supervisor(
             cancellation.clone(),
             pub_args.cookie.clone(),
             host_data.clone(),
+            pub_args.tape.clone(),
+            config.timeouts.web_request_ms,
+            deadline.clone(),
         )),
         llm: Arc::new(modules::Module::new(
             "llm".into(),
@@ -54,32 +66,92 @@ pub fn create_supervisor(
             cancellation.clone(),
             pub_args.cookie.clone(),
             host_data,
+            pub_args.tape.clone(),
+            config.timeouts.llm_request_ms,
+            deadline.clone(),
         )),
     };
 
-    let limiter_det = memlimiter::Limiter::new("det");
+    host.begin_transaction(calldata::Address::from(
+        pub_args.message.contract_address.raw(),
+    ));
+
+    let memory_limits = config
+        .memory_limits
+        .overridden_by(&pub_args.message.memory_limits);
+    let limiter_det = memlimiter::Limiter::new("det", memory_limits.det.unwrap_or(u32::MAX));
+
+    let gas_limit = match pub_args.message.gas_limit {
+        Some(gas_limit) => gas_limit,
+        None => host
+            .get_gas_limit()
+            .await
+            .with_context(|| "getting gas limit from host")?,
+    };
 
-    let locked_slots = host.get_locked_slots_for_sender(
-        calldata::Address::from(pub_args.message.contract_address.raw()),
-        calldata::Address::from(pub_args.message.sender_address.raw()),
-        &limiter_det,
-    )?;
+    let locked_slots = host
+        .get_locked_slots_for_sender(
+            calldata::Address::from(pub_args.message.contract_address.raw()),
+            calldata::Address::from(pub_args.message.sender_address.raw()),
+            &limiter_det,
+        )
+        .await?;
 
-    let shared_data = Arc::new(crate::vm::SharedData::new(
+    Ok(Arc::new(crate::vm::SharedData::new(
         modules,
         cancellation,
         pub_args.is_sync,
         pub_args.cookie.clone(),
         pub_args.allow_latest,
         limiter_det,
+        memory_limits,
         locked_slots,
-    ));
+        gas_limit,
+        config.timeouts,
+        deadline,
+        config.call_limits,
+        Arc::new(host),
+    )))
+}
+
+#[allow(clippy::unnecessary_literal_unwrap)]
+pub async fn create_supervisor(
+    config: &config::Config,
+    host: Host,
+    cancellation: Arc<genvm_common::cancellation::Token>,
+    host_data: genvm_modules_interfaces::HostData,
+    pub_args: PublicArgs<'_>,
+) -> Result<Arc<tokio::sync::Mutex<vm::Supervisor>>> {
+    let shared_data = create_shared_data(config, host, cancellation, host_data, pub_args).await?;
+    let host = shared_data.host.clone();
+
+    let supervisor = match vm::Supervisor::new(config, shared_data) {
+        Ok(supervisor) => supervisor,
+        Err(e) => {
+            let err = Err(e);
+            host.consume_result(&err, 0).await?;
+            return Err(err.unwrap_err());
+        }
+    };
+
+    Ok(Arc::new(tokio::sync::Mutex::new(supervisor)))
+}
+
+/// Same as [`create_supervisor`], but keeps engines and caches of an existing supervisor.
+/// Supervisor must not be running any other transaction
+pub async fn reuse_supervisor(
+    config: &config::Config,
+    supervisor: &tokio::sync::Mutex<vm::Supervisor>,
+    host: Host,
+    cancellation: Arc<genvm_common::cancellation::Token>,
+    host_data: genvm_modules_interfaces::HostData,
+    pub_args: PublicArgs<'_>,
+) -> Result<()> {
+    let shared_data = create_shared_data(config, host, cancellation, host_data, pub_args).await?;
+
+    supervisor.lock().await.reset(shared_data);
 
-    Ok(Arc::new(tokio::sync::Mutex::new(vm::Supervisor::new(
-        config,
-        host,
-        shared_data,
-    )?)))
+    Ok(())
 }
 
 pub async fn run_with_impl(
@@ -93,13 +165,19 @@ pub async fn run_with_impl(
         let mut supervisor = supervisor.lock().await;
 
         let mut entrypoint = Vec::new();
-        supervisor.host.get_calldata(&mut entrypoint)?;
+        supervisor
+            .shared_data
+            .host
+            .get_calldata(&mut entrypoint)
+            .await?;
 
         let essential_data = wasi::genlayer_sdk::SingleVMData {
+            kind: wasi::genlayer_sdk::VmKind::Main,
             conf: wasi::base::Config {
                 needs_error_fingerprint: true,
                 is_deterministic: true,
                 can_read_storage: permissions.contains("r"),
+                can_read_others: permissions.contains("o"),
                 can_write_storage: permissions.contains("w"),
                 can_send_messages: permissions.contains("s"),
                 can_call_others: permissions.contains("c"),
@@ -123,6 +201,7 @@ pub async fn run_with_impl(
             },
             supervisor: supervisor_clone,
             version: genvm_common::version::Version::ZERO,
+            locked_slots: None,
         };
 
         let mut vm = supervisor.spawn(essential_data).await?;
@@ -178,7 +257,12 @@ pub async fn run_with(
 
     log_debug!("sending final result to host");
 
-    supervisor.host.consume_result(&res)?;
+    let gas_used = supervisor.shared_data.gas_used();
+    supervisor
+        .shared_data
+        .host
+        .consume_result(&res, gas_used)
+        .await?;
 
     res
 }
@@ -10,6 +10,11 @@ mod exe;
 enum Commands {
     Run(exe::run::Args),
     Precompile(exe::precompile::Args),
+    MockHost(exe::mock_host::Args),
+    Replay(exe::replay::Args),
+    Serve(exe::serve::Args),
+    Cache(exe::cache::Args),
+    Runners(exe::runners::Args),
 }
 
 #[derive(clap::Parser)]
@@ -47,5 +52,10 @@ fn main() -> Result<()> {
     match args.command {
         Commands::Run(args) => exe::run::handle(args, config),
         Commands::Precompile(args) => exe::precompile::handle(args, config),
+        Commands::MockHost(args) => exe::mock_host::handle(args, config),
+        Commands::Replay(args) => exe::replay::handle(args, config),
+        Commands::Serve(args) => exe::serve::handle(args, config),
+        Commands::Cache(args) => exe::cache::handle(args, config),
+        Commands::Runners(args) => exe::runners::handle(args, config),
     }
 }
@@ -4,8 +4,11 @@ use std::sync::{atomic::AtomicU32, Arc};
 #[derive(Clone)]
 pub struct Limiter {
     id: &'static str,
+    limit: u32,
     remaining_memory: Arc<AtomicU32>,
     least_remaining_memory: Arc<AtomicU32>,
+    /// pool that is charged together with this limiter, see [`Limiter::child`]
+    parent: Option<Box<Limiter>>,
 }
 
 pub struct SaveTok {
@@ -18,11 +21,30 @@ impl Limiter {
             .load(std::sync::atomic::Ordering::SeqCst)
     }
 
-    pub fn new(id: &'static str) -> Self {
+    /// Maximal amount of memory that was in use at the same time
+    pub fn high_water_mark(&self) -> u32 {
+        self.limit - self.get_least_remaining_memory()
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    pub fn new(id: &'static str, limit: u32) -> Self {
         Self {
             id,
-            remaining_memory: Arc::new(AtomicU32::new(u32::MAX)),
-            least_remaining_memory: Arc::new(AtomicU32::new(u32::MAX)),
+            limit,
+            remaining_memory: Arc::new(AtomicU32::new(limit)),
+            least_remaining_memory: Arc::new(AtomicU32::new(limit)),
+            parent: None,
+        }
+    }
+
+    /// Creates limiter with its own budget that also consumes from `self`
+    pub fn child(&self, id: &'static str, limit: u32) -> Self {
+        Self {
+            parent: Some(Box::new(self.clone())),
+            ..Self::new(id, limit)
         }
     }
 
@@ -49,6 +71,26 @@ impl Limiter {
     }
 
     pub fn consume(&self, delta: u32) -> bool {
+        let Some(remaining) = self.take(delta) else {
+            return false;
+        };
+
+        if let Some(parent) = &self.parent {
+            if !parent.consume(delta) {
+                self.remaining_memory
+                    .fetch_add(delta, std::sync::atomic::Ordering::SeqCst);
+                return false;
+            }
+        }
+
+        self.least_remaining_memory
+            .fetch_min(remaining, std::sync::atomic::Ordering::SeqCst);
+
+        true
+    }
+
+    /// Returns remaining memory after subtraction
+    fn take(&self, delta: u32) -> Option<u32> {
         let mut remaining = self
             .remaining_memory
             .load(std::sync::atomic::Ordering::SeqCst);
@@ -57,7 +99,7 @@ impl Limiter {
 
         loop {
             if delta > remaining {
-                return false;
+                return None;
             }
 
             match self.remaining_memory.compare_exchange(
@@ -66,16 +108,32 @@ impl Limiter {
                 std::sync::atomic::Ordering::SeqCst,
                 std::sync::atomic::Ordering::SeqCst,
             ) {
-                Ok(_) => {
-                    let least_for_test = remaining - delta;
-                    self.least_remaining_memory
-                        .fetch_min(least_for_test, std::sync::atomic::Ordering::SeqCst);
-                    break;
-                }
+                Ok(_) => return Some(remaining - delta),
                 Err(new_remaining) => remaining = new_remaining,
             }
         }
+    }
+}
 
-        true
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn child_charges_pool() {
+        let pool = Limiter::new("pool", 100);
+        let child = pool.child("child", 60);
+
+        assert!(child.consume(50));
+        assert!(!child.consume(20));
+        assert_eq!(pool.high_water_mark(), 50);
+
+        let other = pool.child("other", u32::MAX);
+        assert!(!other.consume(60));
+        assert!(other.consume(50));
+
+        assert_eq!(child.high_water_mark(), 50);
+        assert_eq!(other.high_water_mark(), 50);
+        assert_eq!(pool.high_water_mark(), 100);
     }
 }
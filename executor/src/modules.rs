@@ -1,6 +1,7 @@
 use genvm_common::*;
 use std::sync::Arc;
 
+use crate::record;
 use anyhow::Context;
 use futures_util::{stream::FusedStream, SinkExt, StreamExt};
 use genvm_common::calldata;
@@ -21,6 +22,10 @@ pub struct Module {
     imp: tokio::sync::Mutex<ModuleImpl>,
     cookie: String,
     host_data: genvm_modules_interfaces::HostData,
+    tape: record::Tape,
+    request_timeout_ms: Option<u64>,
+    /// of the whole transaction
+    deadline: Option<crate::vm::Deadline>,
 }
 
 async fn read_handling_pings(stream: &mut WSStream) -> anyhow::Result<Bytes> {
@@ -47,12 +52,16 @@ async fn read_handling_pings(stream: &mut WSStream) -> anyhow::Result<Bytes> {
 }
 
 impl Module {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         name: String,
         url: String,
         cancellation: Arc<genvm_common::cancellation::Token>,
         cookie: String,
         host_data: genvm_modules_interfaces::HostData,
+        tape: record::Tape,
+        request_timeout_ms: Option<u64>,
+        deadline: Option<crate::vm::Deadline>,
     ) -> Self {
         Self {
             imp: tokio::sync::Mutex::new(ModuleImpl { url, stream: None }),
@@ -60,6 +69,9 @@ impl Module {
             cookie,
             name,
             host_data,
+            tape,
+            request_timeout_ms,
+            deadline,
         }
     }
 
@@ -75,11 +87,8 @@ impl Module {
         }
     }
 
-    async fn send_impl<R, V>(&self, val: V) -> anyhow::Result<std::result::Result<R, GenericValue>>
-    where
-        V: serde::Serialize,
-        R: serde::Serialize + serde::de::DeserializeOwned,
-    {
+    /// Sends raw payload, connecting to the module if needed
+    async fn exchange(&self, payload: Vec<u8>) -> anyhow::Result<Bytes> {
         let mut zelf = self.imp.lock().await;
 
         if zelf.stream.is_none() {
@@ -104,26 +113,44 @@ impl Module {
         match &mut zelf.stream {
             None => unreachable!(),
             Some(stream) => {
-                let val = calldata::to_value(&val)?;
-                let payload = calldata::encode(&val);
                 stream.send(Message::Binary(payload.into())).await?;
-                let response = read_handling_pings(stream).await?;
+                read_handling_pings(stream).await
+            }
+        }
+    }
+
+    async fn send_impl<R, V>(&self, val: V) -> anyhow::Result<std::result::Result<R, GenericValue>>
+    where
+        V: serde::Serialize,
+        R: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let val = calldata::to_value(&val)?;
+        let payload = calldata::encode(&val);
 
-                let response = calldata::decode(&response)?;
+        let response = match &self.tape {
+            record::Tape::Replay(replayer) => replayer.module(&self.name, &payload)?,
+            tape => {
+                let response = self.exchange(payload.clone()).await?;
+                if let record::Tape::Record(recorder) = tape {
+                    recorder.module(&self.name, &payload, &response);
+                }
+                response.to_vec()
+            }
+        };
 
-                log_info!(name = self.name, question:serde = val, response:? = response; "answer from module");
+        let response = calldata::decode(&response)?;
 
-                let res: genvm_modules_interfaces::Result<R> =
-                    calldata::from_value(response).with_context(|| "parsing result of module")?;
+        log_info!(name = self.name, question:serde = val, response:? = response; "answer from module");
 
-                match res {
-                    genvm_modules_interfaces::Result::Ok(v) => Ok(Ok(v)),
-                    genvm_modules_interfaces::Result::UserError(value) => Ok(Err(value)),
-                    genvm_modules_interfaces::Result::FatalError(value) => {
-                        log_error!(error = value; "module error");
-                        Err(anyhow::anyhow!("module error: {value}"))
-                    }
-                }
+        let res: genvm_modules_interfaces::Result<R> =
+            calldata::from_value(response).with_context(|| "parsing result of module")?;
+
+        match res {
+            genvm_modules_interfaces::Result::Ok(v) => Ok(Ok(v)),
+            genvm_modules_interfaces::Result::UserError(value) => Ok(Err(value)),
+            genvm_modules_interfaces::Result::FatalError(value) => {
+                log_error!(error = value; "module error");
+                Err(anyhow::anyhow!("module error: {value}"))
             }
         }
     }
@@ -133,13 +160,99 @@ impl Module {
         V: serde::Serialize,
         R: serde::Serialize + serde::de::DeserializeOwned,
     {
-        tokio::select! {
+        let request_deadline =
+            crate::vm::Deadline::after(self.request_timeout_ms, format!("{} request", self.name));
+        let deadline = request_deadline
+            .into_iter()
+            .chain(self.deadline.clone())
+            .min_by_key(|d| d.at);
+
+        let expired = async {
+            match &deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline.at.into()).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let res = tokio::select! {
             _ = self.cancellation.chan.closed() => {
                 anyhow::bail!("timeout") // it will be replaced later
             }
-            res = self.send_impl(val) => {
-                res.with_context(|| "sending request to module")
+            _ = expired => None,
+            res = self.send_impl(val) => Some(res),
+        };
+
+        match (res, deadline) {
+            (Some(res), _) => res.with_context(|| "sending request to module"),
+            (None, deadline) => {
+                // answer to the abandoned request may still arrive, so connection can't be reused
+                self.imp.lock().await.stream = None;
+
+                let what = deadline.map(|d| d.what).unwrap_or_default();
+                log_warn!(name = self.name, budget = what; "module request timed out");
+                Err(crate::errors::VMError::timeout(&what).into())
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends a request to a listener that accepts connections and never answers,
+    /// returns message of the resulting error
+    fn timed_out_budget(
+        request_timeout_ms: Option<u64>,
+        deadline: Option<crate::vm::Deadline>,
+    ) -> String {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (token, _canceller) = genvm_common::cancellation::make();
+
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("ws://{}", listener.local_addr().unwrap());
+            let accepting = tokio::spawn(async move {
+                let _conn = listener.accept().await;
+                std::future::pending::<()>().await
+            });
+
+            let host_data =
+                serde_json::from_value(serde_json::json!({ "node_address": "", "tx_id": "" }))
+                    .unwrap();
+            let module = Module::new(
+                "web".into(),
+                url,
+                token,
+                String::new(),
+                host_data,
+                record::Tape::None,
+                request_timeout_ms,
+                deadline,
+            );
+
+            let err = module
+                .send::<GenericValue, _>(GenericValue::Null)
+                .await
+                .unwrap_err();
+            accepting.abort();
+
+            err.downcast::<crate::errors::VMError>().unwrap().0
+        })
+    }
+
+    #[test]
+    fn request_budget() {
+        assert_eq!(timed_out_budget(Some(50), None), "timeout web request");
+    }
+
+    #[test]
+    fn transaction_deadline_bounds_request() {
+        // too large timeout is the same as absent one
+        let deadline = crate::vm::Deadline::after(Some(50), "total");
+        assert_eq!(timed_out_budget(Some(u64::MAX), deadline), "timeout total");
+    }
+}
@@ -191,6 +191,8 @@ pub enum VmError {
     VersionTooBig,
     Oom,
     InvalidContract,
+    OutOfGas,
+    InvalidRunner,
 }
 
 impl VmError {
@@ -201,6 +203,8 @@ impl VmError {
             VmError::VersionTooBig => "version_too_big",
             VmError::Oom => "OOM",
             VmError::InvalidContract => "invalid_contract",
+            VmError::OutOfGas => "out_of_gas",
+            VmError::InvalidRunner => "invalid_runner",
         }
     }
     pub fn str_snake_case(self) -> &'static str {
@@ -210,6 +214,8 @@ impl VmError {
             VmError::VersionTooBig => "version_too_big",
             VmError::Oom => "oom",
             VmError::InvalidContract => "invalid_contract",
+            VmError::OutOfGas => "out_of_gas",
+            VmError::InvalidRunner => "invalid_runner",
         }
     }
 }
@@ -224,6 +230,8 @@ impl TryFrom<&str> for VmError {
             "version_too_big" => Ok(VmError::VersionTooBig),
             "OOM" => Ok(VmError::Oom),
             "invalid_contract" => Ok(VmError::InvalidContract),
+            "out_of_gas" => Ok(VmError::OutOfGas),
+            "invalid_runner" => Ok(VmError::InvalidRunner),
             _ => Err(()),
         }
     }
@@ -0,0 +1,201 @@
+//! Recording of all external interactions of a single transaction and their replay
+//!
+//! Host exchanges are recorded as raw protocol payloads, module exchanges as calldata encoded
+//! question and raw answer. Replay matches requests by content, so it does not depend on the
+//! order in which concurrent requests were issued
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::host::mock::MockBytes;
+use crate::MessageData;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostResponse {
+    Ok(MockBytes),
+    /// error code from the host protocol
+    Error(u8),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostExchange {
+    pub request: MockBytes,
+    pub response: HostResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleExchange {
+    pub module: String,
+    /// calldata encoded
+    pub question: MockBytes,
+    /// exactly what module responded with
+    pub answer: MockBytes,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub genvm_version: String,
+    pub message: MessageData,
+    pub permissions: String,
+    pub host_data: genvm_modules_interfaces::HostData,
+    pub host_protocol: u32,
+    pub is_sync: bool,
+    pub allow_latest: bool,
+
+    #[serde(default)]
+    pub host: Vec<HostExchange>,
+    #[serde(default)]
+    pub modules: Vec<ModuleExchange>,
+
+    /// payload of `consume_result`: encoded result, fingerprint and gas
+    pub result: Option<MockBytes>,
+}
+
+impl Recording {
+    pub fn load(path: &std::path::Path) -> Result<Self> {
+        let file = std::fs::File::open(path).with_context(|| format!("opening {path:?}"))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("parsing {path:?}"))
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> Result<()> {
+        let file = std::fs::File::create(path).with_context(|| format!("creating {path:?}"))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+/// Appends all interactions to the recording
+pub struct Recorder(Mutex<Recording>);
+
+impl Recorder {
+    pub fn new(recording: Recording) -> Self {
+        Self(Mutex::new(recording))
+    }
+
+    pub fn host(&self, request: &[u8], response: HostResponse) {
+        if let Ok(mut rec) = self.0.lock() {
+            rec.host.push(HostExchange {
+                request: MockBytes(request.to_vec()),
+                response,
+            });
+        }
+    }
+
+    pub fn module(&self, module: &str, question: &[u8], answer: &[u8]) {
+        if let Ok(mut rec) = self.0.lock() {
+            rec.modules.push(ModuleExchange {
+                module: module.to_owned(),
+                question: MockBytes(question.to_vec()),
+                answer: MockBytes(answer.to_vec()),
+            });
+        }
+    }
+
+    pub fn result(&self, data: &[u8]) {
+        if let Ok(mut rec) = self.0.lock() {
+            rec.result = Some(MockBytes(data.to_vec()));
+        }
+    }
+
+    pub fn finish(&self) -> Result<Recording> {
+        let Ok(rec) = self.0.lock() else {
+            anyhow::bail!("can't take lock")
+        };
+        Ok(rec.clone())
+    }
+}
+
+/// Answers requests with recorded responses
+pub struct Replayer {
+    host: Mutex<Vec<Option<HostExchange>>>,
+    modules: Mutex<Vec<Option<ModuleExchange>>>,
+    result: Mutex<Option<Vec<u8>>>,
+}
+
+impl Replayer {
+    pub fn new(recording: &Recording) -> Self {
+        Self {
+            host: Mutex::new(recording.host.iter().cloned().map(Some).collect()),
+            modules: Mutex::new(recording.modules.iter().cloned().map(Some).collect()),
+            result: Mutex::new(None),
+        }
+    }
+
+    pub fn host(&self, request: &[u8]) -> Result<HostResponse> {
+        let Ok(mut host) = self.host.lock() else {
+            anyhow::bail!("can't take lock")
+        };
+
+        host.iter_mut()
+            .find(|x| x.as_ref().is_some_and(|x| x.request.0 == request))
+            .and_then(Option::take)
+            .map(|x| x.response)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "replay diverged: no recorded response for host request {}",
+                    hex::encode(request)
+                )
+            })
+    }
+
+    pub fn module(&self, module: &str, question: &[u8]) -> Result<Vec<u8>> {
+        let Ok(mut modules) = self.modules.lock() else {
+            anyhow::bail!("can't take lock")
+        };
+
+        modules
+            .iter_mut()
+            .find(|x| {
+                x.as_ref()
+                    .is_some_and(|x| x.module == module && x.question.0 == question)
+            })
+            .and_then(Option::take)
+            .map(|x| x.answer.0)
+            .ok_or_else(|| {
+                anyhow::anyhow!("replay diverged: no recorded answer from {module} module")
+            })
+    }
+
+    pub fn set_result(&self, data: &[u8]) {
+        if let Ok(mut result) = self.result.lock() {
+            *result = Some(data.to_vec());
+        }
+    }
+
+    pub fn result(&self) -> Option<Vec<u8>> {
+        self.result.lock().ok().and_then(|x| x.clone())
+    }
+
+    /// Number of recorded host and module exchanges that were not requested during replay
+    pub fn unused(&self) -> (usize, usize) {
+        fn count<T>(v: &Mutex<Vec<Option<T>>>) -> usize {
+            v.lock()
+                .map(|v| v.iter().filter(|x| x.is_some()).count())
+                .unwrap_or(0)
+        }
+        (count(&self.host), count(&self.modules))
+    }
+}
+
+/// Where external interactions go
+#[derive(Clone, Default)]
+pub enum Tape {
+    #[default]
+    None,
+    Record(Arc<Recorder>),
+    Replay(Arc<Replayer>),
+}
+
+impl std::fmt::Debug for Tape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tape::None => f.write_str("None"),
+            Tape::Record(_) => f.write_str("Record"),
+            Tape::Replay(_) => f.write_str("Replay"),
+        }
+    }
+}
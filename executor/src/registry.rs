@@ -0,0 +1,608 @@
+//! Resolution of runner ids to archives: runners are looked up in search paths and,
+//! if absent, fetched from a mirror and installed into the first search path
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::{Context, Result};
+use genvm_common::*;
+use sha2::Digest;
+
+use crate::{config, errors::VMError, public_abi, runner, ustar};
+
+/// Where missing runners are downloaded from, it has the same layout as a search path:
+/// `<name>/<hash>.tar` and optionally `<name>/<hash>.tar.sig`
+enum Mirror {
+    Dir(PathBuf),
+    Http {
+        base: String,
+        client: reqwest::Client,
+    },
+}
+
+impl Mirror {
+    fn parse(mirror: &str, conf: &config::Runners) -> Result<Self> {
+        if mirror.starts_with("http://") || mirror.starts_with("https://") {
+            let client = reqwest::Client::builder()
+                .connect_timeout(std::time::Duration::from_millis(
+                    conf.mirror_connect_timeout_ms,
+                ))
+                .timeout(std::time::Duration::from_millis(conf.mirror_timeout_ms))
+                .build()
+                .with_context(|| "creating mirror client")?;
+
+            Ok(Self::Http {
+                base: mirror.trim_end_matches('/').to_owned(),
+                client,
+            })
+        } else {
+            Ok(Self::Dir(PathBuf::from(
+                mirror.strip_prefix("file://").unwrap_or(mirror),
+            )))
+        }
+    }
+
+    /// `Ok(None)` if mirror doesn't have the file
+    async fn get(&self, relative: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Dir(base) => {
+                let path = base.join(relative);
+                match tokio::task::spawn_blocking(move || std::fs::read(path)).await? {
+                    Ok(data) => Ok(Some(data)),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Self::Http { base, client } => {
+                let url = format!("{base}/{relative}");
+                let resp = client
+                    .get(&url)
+                    .send()
+                    .await
+                    .with_context(|| format!("fetching {url}"))?;
+                if resp.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                let resp = resp.error_for_status()?;
+                let data = resp
+                    .bytes()
+                    .await
+                    .with_context(|| format!("fetching {url}"))?;
+                Ok(Some(data.to_vec()))
+            }
+        }
+    }
+}
+
+/// Runners which archives were already verified by this process
+static VERIFIED_RUNNERS: LazyLock<Mutex<HashSet<symbol_table::GlobalSymbol>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+fn invalid_runner(what: &str, cause: anyhow::Error) -> anyhow::Error {
+    VMError(
+        format!("{} {what}", public_abi::VmError::InvalidRunner.value()),
+        Some(cause),
+    )
+    .into()
+}
+
+/// Nix flavour of base32, it is what runner build uses for hashes in ids
+fn nix32(digest: &[u8]) -> String {
+    const CHARS: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+    let len = (digest.len() * 8 - 1) / 5 + 1;
+
+    (0..len)
+        .rev()
+        .map(|n| {
+            let b = n * 5;
+            let (i, j) = (b / 8, b % 8);
+            let hi = digest
+                .get(i + 1)
+                .and_then(|x| x.checked_shl((8 - j) as u32))
+                .unwrap_or(0);
+            let c = (digest[i] >> j) | hi;
+            CHARS[(c & 0x1f) as usize] as char
+        })
+        .collect()
+}
+
+/// Id of a runner archive: nix32 of its sha256, same as `flat` fixed output hash of the build
+pub fn content_hash(contents: &[u8]) -> String {
+    nix32(sha2::Sha256::digest(contents).as_slice())
+}
+
+/// Test and latest runners are not content addressed, so they are neither checked nor fetched
+pub fn is_pinned(runner_hash: &str) -> bool {
+    runner_hash != "test" && runner_hash != "latest"
+}
+
+fn archive_name(runner_id: &str, runner_hash: &str) -> String {
+    format!("{runner_id}/{runner_hash}.tar")
+}
+
+#[derive(serde::Serialize)]
+pub struct InstalledRunner {
+    pub name: String,
+    pub hash: String,
+    pub path: PathBuf,
+}
+
+pub struct Registry {
+    /// searched in order, fetched runners are installed into the first one
+    search_paths: Vec<PathBuf>,
+    mirror: Option<Mirror>,
+    publisher_keys: Vec<ed25519_dalek::VerifyingKey>,
+}
+
+impl Registry {
+    pub fn new(conf: &config::Runners) -> Result<Self> {
+        let search_paths = if conf.search_paths.is_empty() {
+            vec![runner::path()?]
+        } else {
+            conf.search_paths.iter().map(PathBuf::from).collect()
+        };
+
+        if !search_paths.iter().any(|p| p.exists()) && conf.mirror.is_none() {
+            anyhow::bail!("none of runner paths {search_paths:#?} exists");
+        }
+
+        let publisher_keys = conf
+            .publisher_keys
+            .iter()
+            .map(|key| -> Result<ed25519_dalek::VerifyingKey> {
+                let raw: [u8; ed25519_dalek::PUBLIC_KEY_LENGTH] = hex::decode(key)?
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("invalid key length"))?;
+                Ok(ed25519_dalek::VerifyingKey::from_bytes(&raw)?)
+            })
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| "parsing runner publisher keys")?;
+
+        Ok(Self {
+            search_paths,
+            mirror: conf
+                .mirror
+                .as_deref()
+                .map(|mirror| Mirror::parse(mirror, conf))
+                .transpose()?,
+            publisher_keys,
+        })
+    }
+
+    pub fn install_dir(&self) -> &Path {
+        &self.search_paths[0]
+    }
+
+    pub fn search_paths(&self) -> &[PathBuf] {
+        &self.search_paths
+    }
+
+    /// All `<name>/<hash>.tar` archives in search paths, runners that are present in multiple
+    /// of them are listed once, with the path that is used
+    pub fn list(&self) -> Result<Vec<InstalledRunner>> {
+        let mut result: Vec<InstalledRunner> = Vec::new();
+
+        for base in &self.search_paths {
+            let Ok(dir) = std::fs::read_dir(base) else {
+                continue;
+            };
+            for runner_dir in dir {
+                let runner_dir = runner_dir?;
+                if !runner_dir.file_type()?.is_dir() {
+                    continue;
+                }
+                let name = runner_dir.file_name().to_string_lossy().into_owned();
+
+                for arch in std::fs::read_dir(runner_dir.path())? {
+                    let path = arch?.path();
+                    if path.extension() != Some(std::ffi::OsStr::new("tar")) {
+                        continue;
+                    }
+                    let Some(hash) = path.file_stem() else {
+                        continue;
+                    };
+                    let hash = hash.to_string_lossy().into_owned();
+
+                    if result.iter().any(|r| r.name == name && r.hash == hash) {
+                        continue;
+                    }
+                    result.push(InstalledRunner {
+                        name: name.clone(),
+                        hash,
+                        path,
+                    });
+                }
+            }
+        }
+
+        result.sort_by(|a, b| (&a.name, &a.hash).cmp(&(&b.name, &b.hash)));
+
+        Ok(result)
+    }
+
+    /// Hash of the latest version of a runner from `latest.json` of the first search path that has it
+    pub fn latest(&self, runner_id: &str) -> Option<String> {
+        self.search_paths.iter().find_map(|base| {
+            let latest_registry = std::fs::read_to_string(base.join("latest.json")).ok()?;
+            let mut latest_registry: std::collections::BTreeMap<String, String> =
+                serde_json::from_str(&latest_registry).ok()?;
+
+            latest_registry.remove(runner_id)
+        })
+    }
+
+    fn locate(&self, relative: &str) -> Option<PathBuf> {
+        self.search_paths
+            .iter()
+            .map(|base| base.join(relative))
+            .find(|path| path.exists())
+    }
+
+    /// Makes sure that archive of runner `id` is present in one of search paths, fetching it if needed
+    pub async fn ensure(&self, id: symbol_table::GlobalSymbol) -> Result<()> {
+        if runner::is_transaction_local(id.as_str()) {
+            return Ok(());
+        }
+
+        let (runner_id, runner_hash) = runner::verify_runner(id.as_str())?;
+        let relative = archive_name(runner_id, runner_hash);
+
+        if self.locate(&relative).is_some() {
+            return Ok(());
+        }
+
+        let Some(mirror) = &self.mirror else {
+            return Ok(());
+        };
+        if !is_pinned(runner_hash) {
+            return Ok(());
+        }
+
+        log_info!(runner = id; "fetching runner from mirror");
+
+        let Some(contents) = mirror.get(&relative).await? else {
+            anyhow::bail!("runner {id} is absent in mirror");
+        };
+
+        let signature = if self.publisher_keys.is_empty() {
+            None
+        } else {
+            mirror.get(&format!("{relative}.sig")).await?
+        };
+
+        self.check(id, runner_hash, &relative, &contents, signature.as_deref())?;
+
+        let path = self.install_dir().join(&relative);
+        if let Some(signature) = &signature {
+            let mut sig_path = path.as_os_str().to_owned();
+            sig_path.push(".sig");
+            install_atomically(Path::new(&sig_path), signature)?;
+        }
+        install_atomically(&path, &contents)?;
+
+        VERIFIED_RUNNERS.lock().unwrap().insert(id);
+
+        log_info!(runner = id, path:? = path; "runner installed");
+
+        Ok(())
+    }
+
+    /// Loads archive of runner `id`, it must be already [`Self::ensure`]d.
+    /// Archive is mapped, so its files are charged when they are accessed
+    pub fn load(&self, id: symbol_table::GlobalSymbol) -> Result<ustar::Archive> {
+        let (runner_id, runner_hash) =
+            runner::verify_runner(id.as_str()).with_context(|| format!("verifying {id}"))?;
+        let relative = archive_name(runner_id, runner_hash);
+
+        let path = self
+            .locate(&relative)
+            .ok_or_else(|| anyhow::anyhow!("runner {id} not found in {:?}", self.search_paths))?;
+
+        let contents = crate::mmap::load_file(&path, None)?;
+
+        if is_pinned(runner_hash) && !VERIFIED_RUNNERS.lock().unwrap().contains(&id) {
+            let mut sig_path = path.as_os_str().to_owned();
+            sig_path.push(".sig");
+
+            let signature = if self.publisher_keys.is_empty() {
+                None
+            } else {
+                Some(
+                    std::fs::read(&sig_path)
+                        .with_context(|| format!("reading {sig_path:?}"))
+                        .map_err(|e| invalid_runner("unsigned", e))?,
+                )
+            };
+
+            self.check(
+                id,
+                runner_hash,
+                &relative,
+                contents.as_ref(),
+                signature.as_deref(),
+            )?;
+
+            log_trace!(runner = id; "runner verified");
+            VERIFIED_RUNNERS.lock().unwrap().insert(id);
+        }
+
+        ustar::Archive::from_ustar(ustar::SharedBytes::new(contents))
+            .with_context(|| format!("path {path:?}"))
+    }
+
+    /// Checks that `contents` hash to the id and, if publishers are configured,
+    /// that `signature` is made by one of them
+    fn check(
+        &self,
+        id: symbol_table::GlobalSymbol,
+        runner_hash: &str,
+        relative: &str,
+        contents: &[u8],
+        signature: Option<&[u8]>,
+    ) -> Result<()> {
+        let actual_hash = content_hash(contents);
+        if actual_hash != runner_hash {
+            log_error!(runner = id, actual = actual_hash; "runner hash mismatch");
+            return Err(invalid_runner(
+                "hash_mismatch",
+                anyhow::anyhow!("{relative} has hash {actual_hash}"),
+            ));
+        }
+
+        if self.publisher_keys.is_empty() {
+            return Ok(());
+        }
+
+        let Some(signature) = signature else {
+            return Err(invalid_runner(
+                "unsigned",
+                anyhow::anyhow!("{relative} has no signature"),
+            ));
+        };
+        let signature = ed25519_dalek::Signature::from_slice(signature)
+            .map_err(|e| invalid_runner("bad_signature", e.into()))?;
+
+        if !self
+            .publisher_keys
+            .iter()
+            .any(|key| key.verify_strict(contents, &signature).is_ok())
+        {
+            log_error!(runner = id; "runner is not signed by any of the publishers");
+            return Err(invalid_runner(
+                "bad_signature",
+                anyhow::anyhow!("no publisher key matches signature of {relative}"),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a temporary file next to `path` and renames it, so that concurrent readers
+/// never observe a partially written archive
+fn install_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("no parent of {path:?}"))?;
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {dir:?}"))?;
+
+    let mut suffix = [0; 8];
+    getrandom::fill(&mut suffix)?;
+
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(format!(".{}.tmp", hex::encode(suffix)));
+    let tmp_path = dir.join(tmp_name);
+
+    let res = (|| -> std::io::Result<()> {
+        use std::io::Write;
+
+        let mut file = std::fs::File::create_new(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    })();
+
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+
+    res.with_context(|| format!("installing {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(name: &str, mirror: &Path) -> Registry {
+        let install = std::env::temp_dir().join(format!("genvm-test-registry-{name}"));
+        let _ = std::fs::remove_dir_all(&install);
+        std::fs::create_dir_all(&install).unwrap();
+
+        Registry::new(&config::Runners {
+            search_paths: vec![install.to_str().unwrap().to_owned()],
+            mirror: Some(format!("file://{}", mirror.to_str().unwrap())),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// Registry without mirror which has a single `py` runner with `contents` installed
+    /// under `hash`, returns it with the runner id
+    fn local_registry(
+        name: &str,
+        contents: &[u8],
+        hash: &str,
+        publisher_keys: &[ed25519_dalek::VerifyingKey],
+        signature: Option<&[u8]>,
+    ) -> (Registry, symbol_table::GlobalSymbol) {
+        let install = std::env::temp_dir().join(format!("genvm-test-registry-{name}"));
+        let _ = std::fs::remove_dir_all(&install);
+        std::fs::create_dir_all(install.join("py")).unwrap();
+
+        std::fs::write(install.join(format!("py/{hash}.tar")), contents).unwrap();
+        if let Some(signature) = signature {
+            std::fs::write(install.join(format!("py/{hash}.tar.sig")), signature).unwrap();
+        }
+
+        let reg = Registry::new(&config::Runners {
+            search_paths: vec![install.to_str().unwrap().to_owned()],
+            publisher_keys: publisher_keys
+                .iter()
+                .map(|key| hex::encode(key.as_bytes()))
+                .collect(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        (reg, symbol_table::GlobalSymbol::from(format!("py:{hash}")))
+    }
+
+    fn assert_invalid_runner<T>(res: Result<T>, reason: &str) {
+        let Err(err) = res else {
+            panic!("runner must be refused as {reason}");
+        };
+        assert!(
+            err.downcast_ref::<VMError>()
+                .is_some_and(|e| e.0.ends_with(reason)),
+            "{err:#}"
+        );
+    }
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[test]
+    fn fetch_from_mirror() {
+        let mirror = std::env::temp_dir().join("genvm-test-registry-mirror");
+        let _ = std::fs::remove_dir_all(&mirror);
+        std::fs::create_dir_all(mirror.join("py")).unwrap();
+
+        let contents = b"runner contents";
+        let hash = content_hash(contents);
+        std::fs::write(mirror.join(format!("py/{hash}.tar")), contents).unwrap();
+        std::fs::write(mirror.join("py/BAD.tar"), contents).unwrap();
+
+        let reg = registry("fetch", &mirror);
+
+        let id = symbol_table::GlobalSymbol::from(format!("py:{hash}"));
+        block_on(reg.ensure(id)).unwrap();
+        assert_eq!(
+            std::fs::read(reg.install_dir().join(format!("py/{hash}.tar"))).unwrap(),
+            contents
+        );
+
+        let err = block_on(reg.ensure(symbol_table::GlobalSymbol::from("py:BAD"))).unwrap_err();
+        assert!(err
+            .downcast_ref::<VMError>()
+            .is_some_and(|e| e.0.ends_with("hash_mismatch")));
+        assert!(!reg.install_dir().join("py/BAD.tar").exists());
+
+        assert!(block_on(reg.ensure(symbol_table::GlobalSymbol::from("py:ABSENT"))).is_err());
+    }
+
+    #[test]
+    fn hash_matches_build() {
+        use base64::Engine;
+
+        // `softfloat` of `runners/hashes.nix`, its id is `softfloat:<nix32 of this hash>`
+        let build_hash = base64::engine::general_purpose::STANDARD
+            .decode("lkSLHic0pVxCyuVcarKj80FKSxYhYq6oY1+mnJryZZ0=")
+            .unwrap();
+        assert_eq!(
+            nix32(&build_hash),
+            "17b5yad9r9jzcflawqi12r5llhgklfr6lp75r915r99l4wg8ni4n"
+        );
+
+        assert_eq!(
+            content_hash(b""),
+            nix32(
+                &hex::decode("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn load_checks_hash() {
+        let contents = b"tampered runner contents";
+        let hash = content_hash(b"original runner contents");
+
+        let (reg, id) = local_registry("load-hash", contents, &hash, &[], None);
+
+        assert_invalid_runner(reg.load(id), "hash_mismatch");
+    }
+
+    #[test]
+    fn load_requires_signature() {
+        let contents = b"unsigned runner contents";
+        let hash = content_hash(contents);
+        let key = ed25519_dalek::SigningKey::from_bytes(&[1; 32]);
+
+        let (reg, id) = local_registry(
+            "load-unsigned",
+            contents,
+            &hash,
+            &[key.verifying_key()],
+            None,
+        );
+
+        assert_invalid_runner(reg.load(id), "unsigned");
+    }
+
+    #[test]
+    fn signatures_are_checked_against_publishers() {
+        use ed25519_dalek::Signer;
+
+        let contents = b"signed runner contents";
+        let hash = content_hash(contents);
+        let publisher = ed25519_dalek::SigningKey::from_bytes(&[1; 32]);
+        let stranger = ed25519_dalek::SigningKey::from_bytes(&[2; 32]);
+
+        let bad_signature = stranger.sign(contents).to_bytes();
+        let (reg, id) = local_registry(
+            "load-bad-signature",
+            contents,
+            &hash,
+            &[publisher.verifying_key()],
+            Some(&bad_signature),
+        );
+        assert_invalid_runner(reg.load(id), "bad_signature");
+
+        let relative = archive_name("py", &hash);
+        assert_invalid_runner(
+            reg.check(id, &hash, &relative, contents, Some(b"not a signature")),
+            "bad_signature",
+        );
+
+        let signature = publisher.sign(contents).to_bytes();
+        reg.check(id, &hash, &relative, contents, Some(&signature))
+            .unwrap();
+
+        // any of configured publishers may sign
+        let reg = Registry::new(&config::Runners {
+            search_paths: reg
+                .search_paths
+                .iter()
+                .map(|p| p.to_str().unwrap().to_owned())
+                .collect(),
+            publisher_keys: [&stranger, &publisher]
+                .iter()
+                .map(|key| hex::encode(key.verifying_key().as_bytes()))
+                .collect(),
+            ..Default::default()
+        })
+        .unwrap();
+        reg.check(id, &hash, &relative, contents, Some(&signature))
+            .unwrap();
+    }
+}
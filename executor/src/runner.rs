@@ -1,9 +1,10 @@
 use anyhow::{Context, Result};
 use core::str;
+use genvm_common::version::Version;
 use genvm_common::{log_trace, log_warn};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::{str::FromStr, sync::Arc};
+use std::{collections::BTreeMap, str::FromStr, sync::Arc};
 use symbol_table::GlobalSymbol;
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
@@ -13,6 +14,20 @@ pub enum WasmMode {
     Nondet,
 }
 
+/// Contracts and special runners are not content addressed, so they can't be reused across
+/// transactions
+pub fn is_transaction_local(id: &str) -> bool {
+    id.starts_with("on_chain:") || id.starts_with('<')
+}
+
+/// First GenVM version in which files of lazily indexed archives are charged to memory limiter
+/// when accessed, older versions charge the whole archive each time it is used after its first load
+pub const PER_FILE_CHARGING_SINCE: Version = Version::new(0, 1, 7);
+
+pub fn charges_per_file(genvm_version: Version) -> bool {
+    genvm_version >= PER_FILE_CHARGING_SINCE
+}
+
 pub fn get_id_of_contract(address: calldata::Address) -> GlobalSymbol {
     let mut contract_id = String::from("on_chain:0x");
     contract_id.push_str(&hex::encode(address.raw()));
@@ -44,6 +59,65 @@ where
     d.deserialize_str(GlobalSymbolDeserializeVisitor)
 }
 
+/// Half-open range of versions, `since` is inclusive and `before` is exclusive
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VersionRange {
+    #[serde(default)]
+    pub since: Option<Version>,
+    #[serde(default)]
+    pub before: Option<Version>,
+}
+
+impl VersionRange {
+    pub fn contains(&self, version: Version) -> bool {
+        self.since.is_none_or(|since| since <= version)
+            && self.before.is_none_or(|before| version < before)
+    }
+}
+
+/// Condition of [`InitAction::When`]. All facts it depends on must be the same on all nodes
+#[derive(Clone, Debug, Deserialize)]
+pub enum Condition {
+    Mode(WasmMode),
+    /// GenVM version that is active at the transaction timestamp
+    GenvmVersion(VersionRange),
+    /// contents of `version` file of the contract
+    ContractVersion(VersionRange),
+    EntryKind(public_abi::EntryKind),
+    /// environment variable was set by previous `AddEnv`
+    HasEnv(String),
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+    /// `"det"` or `"nondet"`, which is the same as `{"Mode": ...}`
+    #[serde(untagged)]
+    ModeShort(WasmMode),
+}
+
+pub struct ConditionCtx<'a> {
+    pub mode: WasmMode,
+    pub genvm_version: Version,
+    pub contract_version: Version,
+    pub entry_kind: public_abi::EntryKind,
+    pub env: &'a BTreeMap<String, String>,
+}
+
+impl Condition {
+    pub fn holds(&self, ctx: &ConditionCtx) -> bool {
+        match self {
+            Condition::Mode(mode) | Condition::ModeShort(mode) => *mode == ctx.mode,
+            Condition::GenvmVersion(range) => range.contains(ctx.genvm_version),
+            Condition::ContractVersion(range) => range.contains(ctx.contract_version),
+            Condition::EntryKind(kind) => *kind == ctx.entry_kind,
+            Condition::HasEnv(name) => ctx.env.contains_key(name),
+            Condition::All(conds) => conds.iter().all(|c| c.holds(ctx)),
+            Condition::Any(conds) => conds.iter().any(|c| c.holds(ctx)),
+            Condition::Not(cond) => !cond.holds(ctx),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub enum InitAction {
     MapFile {
@@ -60,7 +134,7 @@ pub enum InitAction {
     StartWasm(Arc<str>),
 
     When {
-        cond: WasmMode,
+        cond: Condition,
         action: Box<InitAction>,
     },
     Seq(Vec<InitAction>),
@@ -72,7 +146,7 @@ pub enum InitAction {
     },
 }
 
-use crate::{calldata, errors::VMError, memlimiter, public_abi, ustar::*};
+use crate::{calldata, config, errors::VMError, memlimiter, public_abi, ustar::*};
 
 pub struct ZipCache {
     id: symbol_table::GlobalSymbol,
@@ -96,7 +170,7 @@ impl ZipCache {
     }
 
     pub fn get_version(&self) -> Result<genvm_common::version::Version> {
-        let contents = match self.get_file("version") {
+        let contents = match self.get_file("version", None) {
             Ok(contents) => contents,
             Err(e) => {
                 log_warn!(error:ah = e, runner = self.id; "failed to read version file for runner, using default");
@@ -116,7 +190,7 @@ impl ZipCache {
 
     pub fn get_actions(&mut self) -> Result<Arc<InitAction>> {
         if self.actions.is_none() {
-            let contents = self.get_file("runner.json")?;
+            let contents = self.get_file("runner.json", None)?;
 
             let as_init: InitAction = serde_json::from_str(str::from_utf8(contents.as_ref())?)?;
 
@@ -129,57 +203,110 @@ impl ZipCache {
         }
     }
 
-    pub fn get_file(&self, name: &str) -> Result<SharedBytes> {
+    /// Files of lazily indexed archives are charged to `limiter` on each access,
+    /// which is `None` if GenVM version doesn't [`charges_per_file`]
+    pub fn get_file(
+        &self,
+        name: &str,
+        limiter: Option<&memlimiter::Limiter>,
+    ) -> Result<SharedBytes> {
         let contents = self
             .files
-            .data
             .get(name)
-            .ok_or_else(|| anyhow::anyhow!("no file {}", name))
+            .and_then(|x| x.ok_or_else(|| anyhow::anyhow!("no file {}", name)))
             .with_context(|| format!("reading runner {}", self.id))?;
-        Ok(contents.clone())
+        self.charge(&contents, limiter)?;
+        Ok(contents)
+    }
+
+    /// All files in directory `dir` (which ends with `/`), charged as [`Self::get_file`]
+    pub fn get_dir(
+        &self,
+        dir: &str,
+        limiter: Option<&memlimiter::Limiter>,
+    ) -> Result<Vec<(String, SharedBytes)>> {
+        let files = self
+            .files
+            .with_prefix(dir)
+            .with_context(|| format!("reading runner {}", self.id))?;
+        for (_, contents) in &files {
+            self.charge(contents, limiter)?;
+        }
+        Ok(files)
+    }
+
+    fn charge(&self, contents: &SharedBytes, limiter: Option<&memlimiter::Limiter>) -> Result<()> {
+        match limiter {
+            Some(limiter) if self.files.is_lazy() => {
+                if !limiter.consume(contents.len() as u32) {
+                    return Err(VMError::oom(None).into());
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
     }
 }
 
 pub struct RunnerReaderCache {
     cache: std::collections::HashMap<symbol_table::GlobalSymbol, ZipCache>,
-    path: Arc<std::path::Path>,
+    /// archives used by the current transaction, archives kept from previous ones are charged
+    /// as if they were loaded anew, so that reused supervisor charges the same as a fresh one
+    used: std::collections::HashSet<symbol_table::GlobalSymbol>,
+    registry: Arc<crate::registry::Registry>,
 }
 
 impl RunnerReaderCache {
-    pub fn new() -> Result<Self> {
-        let runners_path: Arc<std::path::Path> = Arc::from(std::path::Path::new(&path()?));
-        if !runners_path.exists() {
-            anyhow::bail!("path {:#?} doesn't exist", &runners_path);
-        }
-
+    pub fn new(conf: &config::Runners) -> Result<Self> {
         Ok(Self {
             cache: std::collections::HashMap::new(),
-            path: runners_path,
+            used: std::collections::HashSet::new(),
+            registry: Arc::new(crate::registry::Registry::new(conf)?),
         })
     }
 
-    pub fn path(&self) -> &Arc<std::path::Path> {
-        &self.path
+    pub fn registry(&self) -> &Arc<crate::registry::Registry> {
+        &self.registry
     }
 
+    pub fn contains(&self, name: symbol_table::GlobalSymbol) -> bool {
+        self.cache.contains_key(&name)
+    }
+
+    /// With `per_file` charging lazily indexed archives are charged on access instead,
+    /// see [`charges_per_file`]
     pub fn get_or_create(
         &mut self,
         name: symbol_table::GlobalSymbol,
         arch_provider: impl FnOnce() -> Result<Archive>,
         limiter: &memlimiter::Limiter,
+        per_file: bool,
     ) -> Result<&mut ZipCache> {
-        match self.cache.entry(name) {
+        let arch = match self.cache.entry(name) {
             std::collections::hash_map::Entry::Occupied(occupied_entry) => {
-                if !limiter.consume(occupied_entry.get().files.total_size) {
-                    return Err(VMError::oom(None).into());
-                }
-                Ok(occupied_entry.into_mut())
+                occupied_entry.into_mut()
             }
             std::collections::hash_map::Entry::Vacant(vacant_entry) => {
-                let to_insert = ZipCache::new(name, arch_provider()?);
-                Ok(vacant_entry.insert(to_insert))
+                vacant_entry.insert(ZipCache::new(name, arch_provider()?))
             }
+        };
+
+        // first use in a transaction is free, only repeated uses are charged
+        if !self.used.insert(name)
+            && !(per_file && arch.files.is_lazy())
+            && !limiter.consume(arch.files.total_size)
+        {
+            return Err(VMError::oom(None).into());
         }
+
+        Ok(arch)
+    }
+
+    /// Drops all archives that are not runners and forgets which archives were used
+    pub fn retain_runners(&mut self) {
+        self.cache
+            .retain(|id, _| !is_transaction_local(id.as_str()));
+        self.used.clear();
     }
 
     pub fn get_unsafe(&mut self, key: symbol_table::GlobalSymbol) -> &mut ZipCache {
@@ -217,3 +344,38 @@ pub fn path() -> Result<std::path::PathBuf> {
     runners_path.push("runners");
     Ok(runners_path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions() {
+        let env = BTreeMap::from([("PATCHED".to_owned(), "1".to_owned())]);
+        let ctx = ConditionCtx {
+            mode: WasmMode::Nondet,
+            genvm_version: Version::new(0, 2, 3),
+            contract_version: Version::new(0, 1, 5),
+            entry_kind: public_abi::EntryKind::Sandbox,
+            env: &env,
+        };
+
+        let cond = |json: serde_json::Value| -> Condition { serde_json::from_value(json).unwrap() };
+
+        assert!(!cond(serde_json::json!("det")).holds(&ctx));
+        assert!(cond(serde_json::json!({"Mode": "nondet"})).holds(&ctx));
+        assert!(cond(serde_json::json!({"GenvmVersion": {"since": "v0.2.0"}})).holds(&ctx));
+        assert!(!cond(serde_json::json!({"GenvmVersion": {"before": "v0.2.3"}})).holds(&ctx));
+        assert!(cond(serde_json::json!({
+            "All": [
+                {"ContractVersion": {"since": "v0.1.0", "before": "v0.1.6"}},
+                {"EntryKind": "Sandbox"},
+                {"HasEnv": "PATCHED"},
+                {"Not": {"Any": [{"HasEnv": "OTHER"}, "det"]}},
+            ]
+        }))
+        .holds(&ctx));
+
+        assert!(serde_json::from_value::<Condition>(serde_json::json!("sometimes")).is_err());
+    }
+}
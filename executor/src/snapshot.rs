@@ -0,0 +1,426 @@
+//! Snapshots of runner modules after `_initialize`, in the spirit of wizer
+//!
+//! Snapshot contains exported memories and mutable globals of an instance, as well as fuel that
+//! `_initialize` consumed, so that restoring a snapshot charges exactly the same amount of gas.
+//! Only modules that export all of their memories and mutable globals and don't import them can
+//! be snapshotted. Tables are expected to be left intact by `_initialize`
+//!
+//! Binary format (all numbers are little endian):
+//! `MAGIC, fuel: u64, globals: u32, (name, kind: u8, bits: u64)*, memories: u32, (name, size: u64, chunks: u32, (offset: u64, data)*)*, sha3-256`
+//! where `name` and `data` are `len: u32` followed by bytes and the trailing hash covers everything before it.
+//! Path of a snapshot includes a digest of all state that previous runner actions made observable
+
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+use sha3::Digest;
+use wasmtime::{AsContextMut, Extern, Instance, Mutability, Val};
+
+const MAGIC: &[u8; 8] = b"GVSNAP2\0";
+
+/// Memory is stored in chunks of this size, chunks that consist of zeroes are omitted
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+enum GlobalKind {
+    I32 = 0,
+    I64 = 1,
+    F32 = 2,
+    F64 = 3,
+}
+
+struct MemoryImage {
+    name: String,
+    size: u64,
+    chunks: Vec<(u64, Vec<u8>)>,
+}
+
+pub struct Snapshot {
+    /// fuel consumed by initialization, zero for non-deterministic mode
+    pub fuel: u64,
+    globals: Vec<(String, GlobalKind, u64)>,
+    memories: Vec<MemoryImage>,
+}
+
+/// Checks that all state of the module is reachable through its exports
+pub fn is_capturable(wasm: &[u8]) -> Result<bool> {
+    use wasmparser::{ExternalKind, Payload, TypeRef};
+
+    let mut imported_globals = 0;
+    let mut defined_memories = 0;
+    let mut mutable_globals = Vec::new();
+    let mut exported_memories = std::collections::BTreeSet::new();
+    let mut exported_globals = std::collections::BTreeSet::new();
+
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        match payload? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    match import?.ty {
+                        TypeRef::Memory(_) => return Ok(false),
+                        TypeRef::Global(ty) if ty.mutable => return Ok(false),
+                        TypeRef::Global(_) => imported_globals += 1,
+                        _ => {}
+                    }
+                }
+            }
+            Payload::MemorySection(reader) => {
+                defined_memories += reader.count();
+            }
+            Payload::GlobalSection(reader) => {
+                for (i, global) in reader.into_iter().enumerate() {
+                    if global?.ty.mutable {
+                        mutable_globals.push(imported_globals + i as u32);
+                    }
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export?;
+                    match export.kind {
+                        ExternalKind::Memory => {
+                            exported_memories.insert(export.index);
+                        }
+                        ExternalKind::Global => {
+                            exported_globals.insert(export.index);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // memories are never imported at this point, so defined ones start from zero
+    let memories_ok = (0..defined_memories).all(|i| exported_memories.contains(&i));
+    let globals_ok = mutable_globals.iter().all(|i| exported_globals.contains(i));
+
+    Ok(memories_ok && globals_ok)
+}
+
+impl Snapshot {
+    pub fn capture(mut store: impl AsContextMut, instance: &Instance, fuel: u64) -> Result<Self> {
+        let exports: Vec<(String, Extern)> = instance
+            .exports(&mut store)
+            .map(|e| (e.name().to_owned(), e.into_extern()))
+            .collect();
+
+        let mut globals = Vec::new();
+        let mut memories = Vec::new();
+
+        for (name, ext) in exports {
+            match ext {
+                Extern::Global(global) => {
+                    if global.ty(&store).mutability() != Mutability::Var {
+                        continue;
+                    }
+                    let (kind, bits) = match global.get(&mut store) {
+                        Val::I32(v) => (GlobalKind::I32, v as u32 as u64),
+                        Val::I64(v) => (GlobalKind::I64, v as u64),
+                        Val::F32(v) => (GlobalKind::F32, v as u64),
+                        Val::F64(v) => (GlobalKind::F64, v),
+                        v => anyhow::bail!(
+                            "global {name} of type {:?} can't be captured",
+                            v.ty(&store)
+                        ),
+                    };
+                    globals.push((name, kind, bits));
+                }
+                Extern::Memory(memory) => {
+                    let data = memory.data(&store);
+                    let chunks = data
+                        .chunks(CHUNK_SIZE)
+                        .enumerate()
+                        .filter(|(_, chunk)| chunk.iter().any(|x| *x != 0))
+                        .map(|(i, chunk)| ((i * CHUNK_SIZE) as u64, chunk.to_vec()))
+                        .collect();
+                    memories.push(MemoryImage {
+                        name,
+                        size: data.len() as u64,
+                        chunks,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            fuel,
+            globals,
+            memories,
+        })
+    }
+
+    /// Applies snapshot to a freshly created instance of the same module
+    pub fn restore(&self, mut store: impl AsContextMut, instance: &Instance) -> Result<()> {
+        for (name, kind, bits) in &self.globals {
+            let global = instance
+                .get_global(&mut store, name)
+                .with_context(|| format!("global {name} is absent"))?;
+            let val = match kind {
+                GlobalKind::I32 => Val::I32(*bits as u32 as i32),
+                GlobalKind::I64 => Val::I64(*bits as i64),
+                GlobalKind::F32 => Val::F32(*bits as u32),
+                GlobalKind::F64 => Val::F64(*bits),
+            };
+            global.set(&mut store, val)?;
+        }
+
+        for image in &self.memories {
+            let memory = instance
+                .get_memory(&mut store, &image.name)
+                .with_context(|| format!("memory {} is absent", image.name))?;
+
+            let current = memory.data_size(&store) as u64;
+            if current < image.size {
+                let page_size = memory.page_size(&store);
+                memory.grow(&mut store, (image.size - current).div_ceil(page_size))?;
+            }
+
+            let data = memory.data_mut(&mut store);
+            data.fill(0);
+            for (offset, chunk) in &image.chunks {
+                let offset = *offset as usize;
+                data.get_mut(offset..offset + chunk.len())
+                    .context("snapshot chunk is out of bounds")?
+                    .copy_from_slice(chunk);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self> {
+        let contents = std::fs::read(path)?;
+
+        if contents.len() < 32 {
+            anyhow::bail!("snapshot is too short");
+        }
+        let (data, hash) = contents.split_at(contents.len() - 32);
+        if sha3::Sha3_256::digest(data).as_slice() != hash {
+            anyhow::bail!("snapshot hash mismatch");
+        }
+
+        let mut file = data;
+
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            anyhow::bail!("invalid snapshot magic");
+        }
+
+        let fuel = read_u64(&mut file)?;
+
+        let mut globals = Vec::new();
+        for _ in 0..read_u32(&mut file)? {
+            let name = read_name(&mut file)?;
+            let kind = match read_exact::<1>(&mut file)?[0] {
+                0 => GlobalKind::I32,
+                1 => GlobalKind::I64,
+                2 => GlobalKind::F32,
+                3 => GlobalKind::F64,
+                x => anyhow::bail!("invalid global kind {x}"),
+            };
+            globals.push((name, kind, read_u64(&mut file)?));
+        }
+
+        let mut memories = Vec::new();
+        for _ in 0..read_u32(&mut file)? {
+            let name = read_name(&mut file)?;
+            let size = read_u64(&mut file)?;
+            let mut chunks = Vec::new();
+            for _ in 0..read_u32(&mut file)? {
+                let offset = read_u64(&mut file)?;
+                chunks.push((offset, read_slice(&mut file)?));
+            }
+            memories.push(MemoryImage { name, size, chunks });
+        }
+
+        if !file.is_empty() {
+            anyhow::bail!("trailing data in snapshot");
+        }
+
+        Ok(Self {
+            fuel,
+            globals,
+            memories,
+        })
+    }
+
+    /// Writes snapshot atomically, so that concurrent processes never observe partial files
+    pub fn save(&self, path: &std::path::Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+
+        {
+            let mut file = Vec::new();
+
+            file.write_all(MAGIC)?;
+            file.write_all(&self.fuel.to_le_bytes())?;
+
+            file.write_all(&(self.globals.len() as u32).to_le_bytes())?;
+            for (name, kind, bits) in &self.globals {
+                write_slice(&mut file, name.as_bytes())?;
+                file.write_all(&[*kind as u8])?;
+                file.write_all(&bits.to_le_bytes())?;
+            }
+
+            file.write_all(&(self.memories.len() as u32).to_le_bytes())?;
+            for image in &self.memories {
+                write_slice(&mut file, image.name.as_bytes())?;
+                file.write_all(&image.size.to_le_bytes())?;
+                file.write_all(&(image.chunks.len() as u32).to_le_bytes())?;
+                for (offset, chunk) in &image.chunks {
+                    file.write_all(&offset.to_le_bytes())?;
+                    write_slice(&mut file, chunk)?;
+                }
+            }
+
+            let hash = sha3::Sha3_256::digest(&file);
+            file.extend_from_slice(hash.as_slice());
+
+            std::fs::write(&tmp_path, &file)?;
+        }
+
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+}
+
+fn read_exact<const N: usize>(input: &mut impl Read) -> Result<[u8; N]> {
+    let mut res = [0; N];
+    input.read_exact(&mut res)?;
+    Ok(res)
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32> {
+    Ok(u32::from_le_bytes(read_exact(input)?))
+}
+
+fn read_u64(input: &mut impl Read) -> Result<u64> {
+    Ok(u64::from_le_bytes(read_exact(input)?))
+}
+
+fn read_slice(input: &mut impl Read) -> Result<Vec<u8>> {
+    let len = read_u32(input)?;
+    let mut res = vec![0; len as usize];
+    input.read_exact(&mut res)?;
+    Ok(res)
+}
+
+fn read_name(input: &mut impl Read) -> Result<String> {
+    Ok(String::from_utf8(read_slice(input)?)?)
+}
+
+fn write_slice(output: &mut impl Write, data: &[u8]) -> Result<()> {
+    output.write_all(&(data.len() as u32).to_le_bytes())?;
+    output.write_all(data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &[u8] = &[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    const MEMORY: &[u8] = &[0x05, 0x03, 0x01, 0x00, 0x01];
+    const EXPORT_MEMORY: &[u8] = &[0x07, 0x07, 0x01, 0x03, b'm', b'e', b'm', 0x02, 0x00];
+
+    #[test]
+    fn capturable_requires_exports() {
+        assert!(!is_capturable(&[HEADER, MEMORY].concat()).unwrap());
+        assert!(is_capturable(&[HEADER, MEMORY, EXPORT_MEMORY].concat()).unwrap());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let snap = Snapshot {
+            fuel: 42,
+            globals: Vec::from([("g".to_owned(), GlobalKind::I64, u64::MAX)]),
+            memories: Vec::from([MemoryImage {
+                name: "mem".into(),
+                size: 2 * CHUNK_SIZE as u64,
+                chunks: Vec::from([(CHUNK_SIZE as u64, Vec::from([1, 2, 3]))]),
+            }]),
+        };
+
+        let path = std::env::temp_dir().join("genvm-test-snapshot.det");
+        snap.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+
+        assert_eq!(loaded.fuel, 42);
+        assert_eq!(loaded.globals.len(), 1);
+        assert_eq!(loaded.globals[0].2, u64::MAX);
+        assert_eq!(loaded.memories[0].size, snap.memories[0].size);
+        assert_eq!(loaded.memories[0].chunks, snap.memories[0].chunks);
+
+        let mut contents = std::fs::read(&path).unwrap();
+        contents[MAGIC.len()] ^= 1;
+        std::fs::write(&path, contents).unwrap();
+        assert!(Snapshot::load(&path).is_err());
+    }
+
+    /// `_initialize` stores 42 at address 100 and sets exported global `g` to 7
+    const INIT_MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type () -> ()
+        0x03, 0x02, 0x01, 0x00, // function
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory
+        0x06, 0x06, 0x01, 0x7f, 0x01, 0x41, 0x00, 0x0b, // mut global i32
+        0x07, 0x1c, 0x03, // exports
+        0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, //
+        0x01, b'g', 0x03, 0x00, //
+        0x0b, b'_', b'i', b'n', b'i', b't', b'i', b'a', b'l', b'i', b'z', b'e', 0x00, 0x00, //
+        0x0a, 0x10, 0x01, 0x0e, 0x00, // code
+        0x41, 0xe4, 0x00, 0x41, 0x2a, 0x36, 0x02, 0x00, // i32.store 100 42
+        0x41, 0x07, 0x24, 0x00, 0x0b, // global.set 0 7
+    ];
+
+    fn state(store: &mut wasmtime::Store<()>, instance: &Instance) -> (Vec<u8>, Val) {
+        let memory = instance.get_memory(&mut *store, "memory").unwrap();
+        let global = instance.get_global(&mut *store, "g").unwrap();
+        (memory.data(&*store).to_vec(), global.get(&mut *store))
+    }
+
+    #[test]
+    fn restore_matches_initialize() {
+        assert!(is_capturable(INIT_MODULE).unwrap());
+
+        let engine = wasmtime::Engine::default();
+        let module = wasmtime::Module::new(&engine, INIT_MODULE).unwrap();
+        let should_quit = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let mut store = wasmtime::Store::new(&engine, (), should_quit);
+
+        let initialized = Instance::new(&mut store, &module, &[]).unwrap();
+        initialized
+            .get_typed_func::<(), ()>(&mut store, "_initialize")
+            .unwrap()
+            .call(&mut store, ())
+            .unwrap();
+
+        let path = std::env::temp_dir().join("genvm-test-snapshot-restore.det");
+        Snapshot::capture(&mut store, &initialized, 0)
+            .unwrap()
+            .save(&path)
+            .unwrap();
+
+        let restored = Instance::new(&mut store, &module, &[]).unwrap();
+        Snapshot::load(&path)
+            .unwrap()
+            .restore(&mut store, &restored)
+            .unwrap();
+
+        let (expected_memory, expected_global) = state(&mut store, &initialized);
+        let (memory, global) = state(&mut store, &restored);
+
+        assert_eq!(expected_memory[100..104], 42_i32.to_le_bytes());
+        assert!(memory == expected_memory);
+        assert_eq!(global.unwrap_i32(), expected_global.unwrap_i32());
+    }
+}
@@ -1,5 +1,7 @@
 use std::{collections::BTreeMap, sync::Arc};
 
+use anyhow::Context;
+
 #[derive(Clone)]
 pub struct SharedBytes {
     bytes: Arc<dyn AsRef<[u8]> + Sync + Send>,
@@ -89,10 +91,7 @@ impl SharedBytes {
     }
 }
 
-pub struct Archive {
-    pub data: BTreeMap<String, SharedBytes>,
-    pub total_size: u32,
-}
+const BLOCK_SIZE: usize = 512;
 
 fn map_try_insert<K, V>(map: &mut BTreeMap<K, V>, key: K, value: V) -> anyhow::Result<&mut V>
 where
@@ -105,37 +104,138 @@ where
     }
 }
 
+/// Part of a header field before the first zero byte
 fn trim_zeroes(x: &[u8]) -> &[u8] {
-    let mut idx = x.len() - 1;
-    while idx > 0 && x[idx - 1] == 0 {
-        idx -= 1;
+    match x.iter().position(|c| *c == 0) {
+        Some(idx) => &x[..idx],
+        None => x,
+    }
+}
+
+fn parse_octal(field: &[u8]) -> anyhow::Result<usize> {
+    if field.first().is_some_and(|c| c & 0x80 != 0) {
+        anyhow::bail!("base-256 numbers are not supported")
     }
 
-    &x[0..idx]
+    let mut res = 0_usize;
+    for c in trim_zeroes(field).trim_ascii().iter().cloned() {
+        if !(b'0'..=b'7').contains(&c) {
+            anyhow::bail!("invalid octal ascii {}", c)
+        }
+        res = res
+            .checked_mul(8)
+            .and_then(|r| r.checked_add((c - b'0') as usize))
+            .ok_or_else(|| anyhow::anyhow!("octal number overflow"))?;
+    }
+    Ok(res)
 }
 
-impl Archive {
-    pub fn from_ustar(original_data: SharedBytes) -> anyhow::Result<Self> {
-        const BLOCK_SIZE: usize = 512;
-        const _RECORD_SIZE: usize = BLOCK_SIZE * 20;
+fn round_up_to_block(x: usize) -> usize {
+    x.div_ceil(BLOCK_SIZE) * BLOCK_SIZE
+}
+
+/// Header checksum is a sum of all its bytes with the checksum field itself treated as spaces.
+/// Some old implementations summed signed bytes, so both are accepted
+fn verify_checksum(header: &[u8]) -> anyhow::Result<()> {
+    let expected = parse_octal(&header[148..156])?;
+
+    let mut unsigned = 0_usize;
+    let mut signed = 0_isize;
+    for (i, c) in header.iter().cloned().enumerate() {
+        let c = if (148..156).contains(&i) { b' ' } else { c };
+        unsigned += c as usize;
+        signed += c as i8 as isize;
+    }
 
-        if original_data.len() < BLOCK_SIZE * 2 {
+    if expected != unsigned && expected as isize != signed {
+        anyhow::bail!("checksum mismatch expected={expected} actual={unsigned}")
+    }
+
+    Ok(())
+}
+
+/// `path` and `size` from PAX extended header records `"<len> <key>=<value>\n"`
+#[derive(Default)]
+struct PaxOverrides {
+    path: Option<String>,
+    size: Option<usize>,
+}
+
+impl PaxOverrides {
+    fn parse(mut data: &[u8]) -> anyhow::Result<Self> {
+        let mut res = Self::default();
+
+        while !data.is_empty() {
+            let space = data
+                .iter()
+                .position(|c| *c == b' ')
+                .ok_or_else(|| anyhow::anyhow!("pax record without length"))?;
+            let len: usize = std::str::from_utf8(&data[..space])?.parse()?;
+            if len <= space || len > data.len() || data[len - 1] != b'\n' {
+                anyhow::bail!("invalid pax record length {len}")
+            }
+
+            let record = std::str::from_utf8(&data[space + 1..len - 1])?;
+            let (key, value) = record
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("pax record without value"))?;
+
+            match key {
+                "path" => res.path = Some(value.to_owned()),
+                "size" => res.size = Some(value.parse()?),
+                _ => {}
+            }
+
+            data = &data[len..];
+        }
+
+        Ok(res)
+    }
+}
+
+/// File from a tar archive
+pub struct Entry {
+    pub name: String,
+    pub contents: SharedBytes,
+}
+
+/// Streaming reader of ustar archives that also understands PAX extended headers and GNU long names.
+/// Directories are skipped, links and other special files are forbidden
+pub struct Entries {
+    data: SharedBytes,
+    offset: usize,
+    done: bool,
+}
+
+impl Entries {
+    pub fn new(data: SharedBytes) -> anyhow::Result<Self> {
+        if data.len() < BLOCK_SIZE * 2 {
             anyhow::bail!("archive is too short for tar")
         }
 
-        if original_data.len() % BLOCK_SIZE != 0 {
+        if data.len() % BLOCK_SIZE != 0 {
             anyhow::bail!("tar len % 512 != 0")
         }
 
-        let mut res = BTreeMap::new();
+        Ok(Self {
+            data,
+            offset: 0,
+            done: false,
+        })
+    }
+
+    fn next_entry(&mut self) -> anyhow::Result<Option<Entry>> {
+        let mut pax = PaxOverrides::default();
+        let mut long_name = None;
 
-        let mut begin = 0;
-        while begin + 2 * BLOCK_SIZE <= original_data.len() {
-            let data = original_data.slice(begin, original_data.len());
-            let header = data.slice(0, BLOCK_SIZE);
+        while self.offset + 2 * BLOCK_SIZE <= self.data.len() {
+            let begin = self.offset;
+            let header = self.data.slice(begin, begin + BLOCK_SIZE);
+            let header = header.as_ref();
 
-            if data
-                .slice(0, BLOCK_SIZE * 2)
+            if self
+                .data
+                .slice(begin, begin + BLOCK_SIZE * 2)
                 .as_ref()
                 .iter()
                 .all(|x| *x == 0)
@@ -143,9 +243,9 @@ impl Archive {
                 break;
             }
 
-            let header_signature = &header.as_ref()[257..265];
-
-            if header_signature != b"ustar\x0000" {
+            let header_signature = &header[257..265];
+            let is_posix = header_signature == b"ustar\x0000";
+            if !is_posix && header_signature != b"ustar  \x00" {
                 anyhow::bail!(
                     "invalid ustar header={:?}; offset={}",
                     header_signature,
@@ -153,49 +253,208 @@ impl Archive {
                 )
             }
 
-            let file_size_octal = trim_zeroes(&header.as_ref()[124..136]);
+            verify_checksum(header).with_context(|| format!("offset={begin}"))?;
 
-            let link_indicator = header.as_ref()[156];
-            if ![b'0', b'\x00', b'5'].contains(&link_indicator) {
-                anyhow::bail!("links are forbidden")
-            }
+            let header_size = parse_octal(&header[124..136])?;
+            let type_flag = header[156];
 
-            let path_and_name = trim_zeroes(&header.as_ref()[0..100]);
-            let path_and_name_prefix = trim_zeroes(&header.as_ref()[345..345 + 155]);
+            let size = match type_flag {
+                b'0' | b'\x00' | b'7' | b'5' => pax.size.take().unwrap_or(header_size),
+                _ => header_size,
+            };
 
-            begin += BLOCK_SIZE;
+            let contents_begin = begin + BLOCK_SIZE;
+            if size > self.data.len() - contents_begin {
+                anyhow::bail!("entry at offset={begin} with size={size} exceeds archive")
+            }
+            let contents = self.data.slice(contents_begin, contents_begin + size);
 
-            let mut name_vec = Vec::from(path_and_name_prefix);
-            name_vec.extend_from_slice(path_and_name);
+            self.offset = contents_begin + round_up_to_block(size);
 
-            let name = String::from_utf8(name_vec)?;
+            match type_flag {
+                b'x' => {
+                    pax = PaxOverrides::parse(contents.as_ref())
+                        .with_context(|| format!("pax header at offset={begin}"))?;
+                    continue;
+                }
+                // global headers can't carry anything meaningful for a single file
+                b'g' => continue,
+                b'L' => {
+                    long_name = Some(String::from_utf8(trim_zeroes(contents.as_ref()).to_vec())?);
+                    continue;
+                }
+                b'0' | b'\x00' | b'7' | b'5' => {}
+                b'1' | b'2' | b'K' => anyhow::bail!("links are forbidden"),
+                c => anyhow::bail!("unsupported entry type {:?}", c as char),
+            }
+
+            let name = match pax.path.take().or(long_name.take()) {
+                Some(name) => name,
+                None => {
+                    let path_and_name = trim_zeroes(&header[0..100]);
+                    let prefix = if is_posix {
+                        trim_zeroes(&header[345..345 + 155])
+                    } else {
+                        &[]
+                    };
+
+                    let mut name_vec = Vec::from(prefix);
+                    if !prefix.is_empty() {
+                        name_vec.push(b'/');
+                    }
+                    name_vec.extend_from_slice(path_and_name);
+
+                    String::from_utf8(name_vec)?
+                }
+            };
 
-            if name.ends_with("/") {
+            if type_flag == b'5' || name.ends_with("/") {
                 continue;
             }
 
-            let mut file_size = 0_usize;
-            for c in file_size_octal.iter().cloned() {
-                if !(b'0'..=b'7').contains(&c) {
-                    anyhow::bail!("invalid octal ascii {}", c)
+            return Ok(Some(Entry { name, contents }));
+        }
+
+        if pax.path.is_some() || pax.size.is_some() || long_name.is_some() {
+            anyhow::bail!("extended header is not followed by an entry")
+        }
+
+        Ok(None)
+    }
+}
+
+impl Iterator for Entries {
+    type Item = anyhow::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let res = self.next_entry().transpose();
+        if !matches!(res, Some(Ok(_))) {
+            self.done = true;
+        }
+        res
+    }
+}
+
+struct Index {
+    files: BTreeMap<String, SharedBytes>,
+    /// entries that are not indexed yet
+    pending: Option<Entries>,
+    /// indexing error is remembered, so that files that precede it are still available
+    error: Option<String>,
+}
+
+impl Index {
+    /// Indexes pending entries until `stop` returns true for one of them
+    fn advance(&mut self, stop: impl Fn(&str) -> bool) -> anyhow::Result<()> {
+        if let Some(e) = &self.error {
+            anyhow::bail!("{e}")
+        }
+
+        while let Some(pending) = &mut self.pending {
+            let res = match pending.next() {
+                None => {
+                    self.pending = None;
+                    break;
+                }
+                Some(res) => res.and_then(|entry| {
+                    let found = stop(&entry.name);
+                    map_try_insert(&mut self.files, entry.name, entry.contents)?;
+                    Ok(found)
+                }),
+            };
+
+            match res {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => {
+                    let e = format!("{e:#}");
+                    self.error = Some(e.clone());
+                    self.pending = None;
+                    anyhow::bail!("{e}")
                 }
-                file_size = file_size * 8 + (c - b'0') as usize;
             }
+        }
 
-            begin += file_size;
-            begin += (BLOCK_SIZE - (begin % BLOCK_SIZE)) % BLOCK_SIZE;
+        Ok(())
+    }
+}
 
-            let file_contents = data.slice(BLOCK_SIZE, BLOCK_SIZE + file_size);
+pub struct Archive {
+    index: std::sync::Mutex<Index>,
+    lazy: bool,
+    pub total_size: u32,
+}
 
-            map_try_insert(&mut res, name, file_contents)?;
+impl Archive {
+    fn indexed(data: BTreeMap<String, SharedBytes>, total_size: u32) -> Self {
+        Self {
+            index: std::sync::Mutex::new(Index {
+                files: data,
+                pending: None,
+                error: None,
+            }),
+            lazy: false,
+            total_size,
         }
+    }
+
+    /// Archive is indexed lazily: only as far as it is needed to find requested files
+    pub fn from_ustar(original_data: SharedBytes) -> anyhow::Result<Self> {
+        let total_size = original_data.len() as u32;
+        let entries = Entries::new(original_data)?;
 
         Ok(Self {
-            data: res,
-            total_size: original_data.len() as u32,
+            index: std::sync::Mutex::new(Index {
+                files: BTreeMap::new(),
+                pending: Some(entries),
+                error: None,
+            }),
+            lazy: true,
+            total_size,
         })
     }
 
+    /// Files of lazily indexed archives are backed by a mapped file, so they are charged when accessed
+    /// rather than as a whole
+    pub fn is_lazy(&self) -> bool {
+        self.lazy
+    }
+
+    pub fn get(&self, name: &str) -> anyhow::Result<Option<SharedBytes>> {
+        let mut index = self.index.lock().unwrap();
+        if let Some(contents) = index.files.get(name) {
+            return Ok(Some(contents.clone()));
+        }
+
+        index.advance(|entry| entry == name)?;
+        Ok(index.files.get(name).cloned())
+    }
+
+    /// All files which names start with `prefix`, in order
+    pub fn with_prefix(&self, prefix: &str) -> anyhow::Result<Vec<(String, SharedBytes)>> {
+        let mut index = self.index.lock().unwrap();
+        index.advance(|_| false)?;
+
+        Ok(index
+            .files
+            .range(prefix.to_owned()..)
+            .take_while(|(name, _)| name.starts_with(prefix))
+            .map(|(name, contents)| (name.clone(), contents.clone()))
+            .collect())
+    }
+
+    /// All files, indexes the whole archive
+    pub fn files(&self) -> anyhow::Result<BTreeMap<String, SharedBytes>> {
+        let mut index = self.index.lock().unwrap();
+        index.advance(|_| false)?;
+
+        Ok(index.files.clone())
+    }
+
     pub fn from_zip<R: std::io::Read + std::io::Seek>(
         zip: &mut zip::ZipArchive<R>,
         bytes: SharedBytes,
@@ -230,10 +489,7 @@ impl Archive {
             )?;
         }
 
-        Ok(Self {
-            data: res,
-            total_size: bytes.len() as u32,
-        })
+        Ok(Self::indexed(res, bytes.len() as u32))
     }
 
     pub fn from_file_and_runner(
@@ -243,13 +499,98 @@ impl Archive {
     ) -> Self {
         let total_size = file.len() as u32;
 
-        Self {
-            data: BTreeMap::from_iter([
+        Self::indexed(
+            BTreeMap::from_iter([
                 ("runner.json".into(), runner_comment),
                 ("version".into(), version),
                 ("file".into(), file),
             ]),
             total_size,
-        }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSIX: &[u8; 8] = b"ustar\x0000";
+    const GNU: &[u8; 8] = b"ustar  \x00";
+
+    fn push(tar: &mut Vec<u8>, name: &str, type_flag: u8, magic: &[u8; 8], contents: &[u8]) {
+        let mut header = [0_u8; BLOCK_SIZE];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", contents.len()).as_bytes());
+        header[156] = type_flag;
+        header[257..265].copy_from_slice(magic);
+
+        header[148..156].fill(b' ');
+        let checksum: usize = header.iter().map(|c| *c as usize).sum();
+        header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+
+        tar.extend_from_slice(&header);
+        tar.extend_from_slice(contents);
+        tar.resize(round_up_to_block(tar.len()), 0);
+    }
+
+    fn finish(mut tar: Vec<u8>) -> Archive {
+        tar.resize(tar.len() + 2 * BLOCK_SIZE, 0);
+        Archive::from_ustar(SharedBytes::new(tar)).unwrap()
+    }
+
+    #[test]
+    fn extensions() {
+        let long = format!("py/{}/mod.py", "x".repeat(120));
+
+        let mut tar = Vec::new();
+        push(&mut tar, "dir/", b'5', POSIX, b"");
+        push(&mut tar, "dir/short", b'0', POSIX, b"short");
+
+        let pax = format!("path={long}\n");
+        let pax = format!("{} {pax}", pax.len() + 4);
+        push(&mut tar, "PaxHeader", b'x', POSIX, pax.as_bytes());
+        push(&mut tar, "truncated", b'0', POSIX, b"pax");
+
+        push(
+            &mut tar,
+            "././@LongLink",
+            b'L',
+            GNU,
+            format!("{long}2\0").as_bytes(),
+        );
+        push(&mut tar, "truncated", b'0', GNU, b"gnu");
+
+        let arch = finish(tar);
+
+        assert_eq!(arch.get("dir/short").unwrap().unwrap().as_ref(), b"short");
+        assert_eq!(arch.get(&long).unwrap().unwrap().as_ref(), b"pax");
+        assert_eq!(
+            arch.get(&format!("{long}2")).unwrap().unwrap().as_ref(),
+            b"gnu"
+        );
+        assert!(arch.get("truncated").unwrap().is_none());
+        assert_eq!(arch.with_prefix("dir/").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn lazy_errors() {
+        let mut tar = Vec::new();
+        push(&mut tar, "first", b'0', POSIX, b"1");
+        let corrupted = tar.len();
+        push(&mut tar, "second", b'0', POSIX, b"2");
+        tar[corrupted] = b'S';
+        push(&mut tar, "link", b'2', POSIX, b"");
+
+        let arch = finish(tar);
+
+        assert!(arch.get("first").unwrap().is_some());
+        assert!(arch.get("second").is_err());
+        assert!(arch.get("first").unwrap().is_some());
+        assert!(arch.files().is_err());
+
+        let mut tar = Vec::new();
+        push(&mut tar, "link", b'2', POSIX, b"");
+        assert!(finish(tar).files().is_err());
     }
 }
@@ -2,7 +2,7 @@ use core::str;
 use std::{
     borrow::Cow,
     collections::{BTreeMap, BTreeSet, HashMap},
-    sync::atomic::AtomicU32,
+    sync::atomic::{AtomicU32, AtomicU64},
 };
 
 use itertools::Itertools;
@@ -16,8 +16,10 @@ use crate::{
     host::LockedSlotsSet,
     memlimiter, public_abi,
     runner::{self, InitAction, WasmMode},
+    snapshot,
     ustar::{Archive, SharedBytes},
     wasi,
+    wasi::genlayer_sdk::VmKind,
 };
 use anyhow::{Context, Result};
 use genvm_common::*;
@@ -81,6 +83,20 @@ impl RunOk {
         Self::Return([0].into())
     }
 
+    /// `{ kind, payload }`, where payload is returned calldata bytes or error message
+    pub fn to_calldata(&self) -> calldata::Value {
+        let (kind, payload) = match self {
+            RunOk::Return(data) => ("return", calldata::Value::Bytes(data.clone())),
+            RunOk::UserError(message) => ("user_error", calldata::Value::Str(message.clone())),
+            RunOk::VMError(message, _) => ("vm_error", calldata::Value::Str(message.clone())),
+        };
+
+        calldata::Value::Map(BTreeMap::from([
+            ("kind".to_owned(), calldata::Value::Str(kind.into())),
+            ("payload".to_owned(), payload),
+        ]))
+    }
+
     pub fn as_bytes_iter(&self) -> impl Iterator<Item = u8> + '_ {
         use crate::public_abi::ResultCode;
         match self {
@@ -129,6 +145,71 @@ impl std::fmt::Debug for RunOk {
 pub struct WasmContext {
     genlayer_ctx: Arc<Mutex<wasi::Context>>,
     limits: memlimiter::Limiter,
+    /// checked on each epoch tick, see [`EpochTicker`]
+    deadlines: Vec<Deadline>,
+}
+
+/// Point in time after which execution is interrupted with [`public_abi::VmError::Timeout`]
+#[derive(Clone, Debug)]
+pub struct Deadline {
+    pub at: std::time::Instant,
+    /// budget that is exceeded, such as `total` or `nondet call 0`
+    pub what: String,
+}
+
+impl Deadline {
+    /// Timeouts too large to be represented are the same as absent ones
+    pub fn after(timeout_ms: Option<u64>, what: impl Into<String>) -> Option<Self> {
+        let at =
+            std::time::Instant::now().checked_add(std::time::Duration::from_millis(timeout_ms?))?;
+        Some(Self {
+            at,
+            what: what.into(),
+        })
+    }
+
+    pub fn check(&self) -> Result<(), VMError> {
+        if std::time::Instant::now() >= self.at {
+            Err(VMError::timeout(&self.what))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// How often engines epoch is incremented, it is the precision of all timeouts
+const EPOCH_TICK: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// Increments epoch of engines from a background thread until dropped
+struct EpochTicker {
+    stop: Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl EpochTicker {
+    fn start(engines: &Engines) -> Result<Self> {
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let det = engines.det.clone();
+        let non_det = engines.non_det.clone();
+        let stop_copy = stop.clone();
+        std::thread::Builder::new()
+            .name("genvm-epoch".into())
+            .spawn(move || {
+                while !stop_copy.load(std::sync::atomic::Ordering::Relaxed) {
+                    std::thread::sleep(EPOCH_TICK);
+                    det.increment_epoch();
+                    non_det.increment_epoch();
+                }
+            })?;
+
+        Ok(Self { stop })
+    }
+}
+
+impl Drop for EpochTicker {
+    fn drop(&mut self) {
+        self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
 }
 
 impl wasmtime::ResourceLimiter for memlimiter::Limiter {
@@ -195,6 +276,7 @@ impl WasmContext {
         limiter: memlimiter::Limiter,
     ) -> anyhow::Result<WasmContext> {
         Ok(WasmContext {
+            deadlines: shared_data.deadline.iter().cloned().collect(),
             genlayer_ctx: Arc::new(Mutex::new(wasi::Context::new(data, shared_data)?)),
             limits: limiter,
         })
@@ -222,10 +304,27 @@ pub struct SharedData {
 
     pub limiter_det: memlimiter::Limiter,
     pub limiter_non_det: memlimiter::Limiter,
+    pub memory_limits: config::MemoryLimits,
+    /// limiters of all VMs spawned during the transaction, for reporting
+    pub vm_limiters: Mutex<Vec<(VmKind, memlimiter::Limiter)>>,
     pub locked_slots: LockedSlotsSet,
+
+    /// gas is shared across all deterministic VMs, non-deterministic ones are not metered
+    pub gas_limit: u64,
+    pub gas_remaining: AtomicU64,
+
+    pub timeouts: config::Timeouts,
+    /// of the whole transaction
+    pub deadline: Option<Deadline>,
+
+    pub call_limits: config::CallLimits,
+
+    /// host can be used without locking the supervisor
+    pub host: Arc<crate::Host>,
 }
 
 impl SharedData {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         modules: Modules,
         cancellation: Arc<genvm_common::cancellation::Token>,
@@ -233,7 +332,13 @@ impl SharedData {
         cookie: String,
         allow_latest: bool,
         limiter_det: memlimiter::Limiter,
+        memory_limits: config::MemoryLimits,
         locked_slots: LockedSlotsSet,
+        gas_limit: u64,
+        timeouts: config::Timeouts,
+        deadline: Option<Deadline>,
+        call_limits: config::CallLimits,
+        host: Arc<crate::Host>,
     ) -> Self {
         Self {
             nondet_call_no: 0.into(),
@@ -244,10 +349,53 @@ impl SharedData {
             cookie,
             allow_latest,
             limiter_det,
-            limiter_non_det: memlimiter::Limiter::new("non-det"),
+            limiter_non_det: memlimiter::Limiter::new(
+                "non-det",
+                memory_limits.non_det.unwrap_or(u32::MAX),
+            ),
+            memory_limits,
+            vm_limiters: Mutex::new(Vec::new()),
             locked_slots,
+            gas_limit,
+            gas_remaining: AtomicU64::new(gas_limit),
+            timeouts,
+            deadline,
+            call_limits,
+            host,
+        }
+    }
+
+    pub fn gas_used(&self) -> u64 {
+        self.gas_limit - self.gas_remaining.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    pub fn check_deadline(&self) -> Result<(), VMError> {
+        match &self.deadline {
+            Some(deadline) => deadline.check(),
+            None => Ok(()),
         }
     }
+
+    pub fn memory_report(&self) -> Vec<VmMemoryReport> {
+        self.vm_limiters
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(kind, limiter)| VmMemoryReport {
+                kind: *kind,
+                limit: limiter.limit(),
+                high_water: limiter.high_water_mark(),
+            })
+            .collect()
+    }
+}
+
+/// Memory usage of a single VM, in order of spawning
+#[derive(Serialize, Debug, Clone)]
+pub struct VmMemoryReport {
+    pub kind: VmKind,
+    pub limit: u32,
+    pub high_water: u32,
 }
 
 pub struct PrecompiledModule {
@@ -260,21 +408,23 @@ pub struct Modules {
     pub llm: Arc<crate::modules::Module>,
 }
 
-#[derive(Serialize)]
-struct SupervisorStats {
-    precompile_hits: usize,
-    cache_hits: usize,
-    compiled_modules: usize,
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SupervisorStats {
+    pub precompile_hits: usize,
+    pub cache_hits: usize,
+    pub compiled_modules: usize,
+    pub snapshot_hits: usize,
 }
 
 pub struct Supervisor {
-    pub host: crate::Host,
     pub shared_data: Arc<SharedData>,
 
     engines: Engines,
     cached_modules: HashMap<symbol_table::GlobalSymbol, Arc<PrecompiledModule>>,
     runner_cache: runner::RunnerReaderCache,
     cache_dir: Option<std::path::PathBuf>,
+    snapshots: bool,
+    _epoch_ticker: EpochTicker,
 
     stats: SupervisorStats,
 }
@@ -289,38 +439,82 @@ struct ApplyActionCtx {
     env: BTreeMap<String, String>,
     visited: BTreeSet<symbol_table::GlobalSymbol>,
     contract_id: symbol_table::GlobalSymbol,
+    genvm_version: genvm_common::version::Version,
+    contract_version: genvm_common::version::Version,
+    entry_kind: public_abi::EntryKind,
+    /// digest of mapped files, arguments and linked modules, which `_initialize` can observe
+    applied: sha3::Sha3_256,
 }
 
-fn try_get_latest(runner_id: &str, base_path: &std::path::Path) -> Option<String> {
-    let mut path = std::path::PathBuf::from(base_path);
-    path.push("latest.json");
-
-    let latest_registry = std::fs::read_to_string(&path).ok()?;
-    let mut latest_registry: BTreeMap<String, String> =
-        serde_json::from_str(&latest_registry).ok()?;
-
-    latest_registry.remove(runner_id)
-}
-
-fn make_new_runner_arch_from_tar(
-    id: symbol_table::GlobalSymbol,
-    base_path: &std::path::Path,
-    limiter: &memlimiter::Limiter,
-) -> Result<Archive> {
-    let (runner_id, runner_hash) =
-        runner::verify_runner(id.as_str()).with_context(|| format!("verifying {id}"))?;
+impl ApplyActionCtx {
+    /// Limiter to which files are charged on access, see [`runner::charges_per_file`]
+    fn file_limiter<'a>(
+        &self,
+        limiter: &'a memlimiter::Limiter,
+    ) -> Option<&'a memlimiter::Limiter> {
+        runner::charges_per_file(self.genvm_version).then_some(limiter)
+    }
 
-    let mut path = std::path::PathBuf::from(base_path);
-    path.push(runner_id);
+    fn record(&mut self, tag: &str, parts: &[&[u8]]) {
+        use sha3::Digest;
 
-    let mut fname = runner_hash.to_owned();
-    fname.push_str(".tar");
-    path.push(fname);
+        self.applied.update(tag.as_bytes());
+        for part in parts {
+            self.applied.update((part.len() as u64).to_le_bytes());
+            self.applied.update(part);
+        }
+    }
 
-    let contents = crate::mmap::load_file(&path, Some(limiter))?;
+    /// Files of content addressed runners are identified by their path, other ones by their hash
+    fn record_file(
+        &mut self,
+        tag: &str,
+        name: &str,
+        runner_id: symbol_table::GlobalSymbol,
+        path_in_arch: &str,
+        contents: &SharedBytes,
+    ) {
+        use sha3::Digest;
+
+        let pinned = !runner::is_transaction_local(runner_id.as_str())
+            && runner::verify_runner(runner_id.as_str())
+                .is_ok_and(|(_, hash)| crate::registry::is_pinned(hash));
+        if pinned {
+            self.record(
+                tag,
+                &[
+                    name.as_bytes(),
+                    runner_id.as_str().as_bytes(),
+                    path_in_arch.as_bytes(),
+                ],
+            );
+        } else {
+            let hash = sha3::Sha3_256::digest(contents.as_ref());
+            self.record(tag, &[name.as_bytes(), hash.as_slice()]);
+        }
+    }
 
-    crate::ustar::Archive::from_ustar(SharedBytes::new(contents))
-        .with_context(|| format!("path {path:?}"))
+    /// Identifies everything that previous actions made observable, so that a snapshot
+    /// is never restored into a different context
+    fn state_digest(&self, is_det: bool) -> String {
+        use sha3::Digest;
+
+        let mut hasher = self.applied.clone();
+        for (name, val) in &self.env {
+            hasher.update((name.len() as u64).to_le_bytes());
+            hasher.update(name.as_bytes());
+            hasher.update((val.len() as u64).to_le_bytes());
+            hasher.update(val.as_bytes());
+        }
+        hasher.update([is_det as u8, self.entry_kind.value()]);
+        hasher.update(self.genvm_version.to_string().as_bytes());
+        hasher.update(self.contract_version.to_string().as_bytes());
+
+        base32::encode(
+            base32::Alphabet::Rfc4648 { padding: false },
+            hasher.finalize().as_slice(),
+        )
+    }
 }
 
 impl VM {
@@ -328,6 +522,28 @@ impl VM {
         self.config_copy.is_deterministic
     }
 
+    /// Adds a budget in addition to the one of the whole transaction
+    pub fn add_deadline(&mut self, deadline: Deadline) {
+        self.store.data_mut().deadlines.push(deadline);
+    }
+
+    /// Limiter of this VM, it also charges the pool of its mode
+    pub fn limiter(&self) -> memlimiter::Limiter {
+        self.store.data().limits.clone()
+    }
+
+    fn call_stack(&self) -> Vec<errors::StackAddress> {
+        let lock = self.store.data().genlayer_ctx.lock().unwrap();
+        let message_data = &lock.genlayer_sdk.data.message_data;
+
+        message_data
+            .stack
+            .iter()
+            .chain(std::iter::once(&message_data.contract_address))
+            .map(|addr| errors::StackAddress(addr.raw()))
+            .collect()
+    }
+
     #[allow(clippy::manual_try_fold)]
     pub async fn run(&mut self, instance: &wasmtime::Instance) -> anyhow::Result<FullRunOk> {
         if let Ok(lck) = self.store.data().genlayer_ctx.lock() {
@@ -346,7 +562,10 @@ impl VM {
             Ok(()) => Ok((RunOk::empty_return(), None)),
             Err(e) => {
                 if self.config_copy.needs_error_fingerprint {
-                    errors::unwrap_vm_errors_fingerprint(e).map(|(a, b)| (a, Some(b)))
+                    errors::unwrap_vm_errors_fingerprint(e).map(|(a, mut b)| {
+                        b.call_stack = self.call_stack();
+                        (a, Some(b))
+                    })
                 } else {
                     errors::unwrap_vm_errors(e).map(|a| (a, None))
                 }
@@ -397,6 +616,7 @@ impl Engines {
             .wasm_feature(WasmFeatures::MULTI_VALUE, true);
 
         base_conf.consume_fuel(false);
+        base_conf.epoch_interruption(true);
         //base_conf.wasm_threads(false);
         //base_conf.wasm_reference_types(false);
         base_conf.wasm_simd(false);
@@ -410,7 +630,8 @@ impl Engines {
         det_conf
             .wasm_floats_enabled(false)
             .cranelift_nan_canonicalization(true)
-            .wasm_backtrace(true);
+            .wasm_backtrace(true)
+            .consume_fuel(true);
 
         let mut non_det_conf = base_conf.clone();
         non_det_conf.wasm_floats_enabled(true).wasm_backtrace(false);
@@ -460,12 +681,7 @@ impl WasmFileDesc {
 }
 
 impl Supervisor {
-    #[allow(clippy::unnecessary_literal_unwrap)]
-    pub fn new(
-        config: &config::Config,
-        mut host: crate::Host,
-        shared_data: Arc<SharedData>,
-    ) -> Result<Self> {
+    pub fn new(config: &config::Config, shared_data: Arc<SharedData>) -> Result<Self> {
         let my_cache_dir = caching::get_cache_dir(&config.cache_dir).ok();
 
         let engines = Engines::create(|base_conf| {
@@ -493,31 +709,31 @@ impl Supervisor {
                 }
             }
             Ok(())
-        });
-        let engines = match engines {
-            Ok(engines) => engines,
-            Err(e) => {
-                let err = Err(e);
-                host.consume_result(&err)?;
-                return Err(err.unwrap_err());
-            }
-        };
+        })?;
+        let epoch_ticker = EpochTicker::start(&engines)?;
         Ok(Self {
             engines,
+            _epoch_ticker: epoch_ticker,
             cached_modules: HashMap::new(),
-            runner_cache: runner::RunnerReaderCache::new()?,
-            host,
+            runner_cache: runner::RunnerReaderCache::new(&config.runners)?,
             shared_data,
             cache_dir: my_cache_dir,
+            snapshots: config.snapshots,
 
-            stats: SupervisorStats {
-                cache_hits: 0,
-                precompile_hits: 0,
-                compiled_modules: 0,
-            },
+            stats: SupervisorStats::default(),
         })
     }
 
+    /// Prepares supervisor for the next transaction: engines, compiled modules and runner archives
+    /// are kept, everything that belongs to the previous transaction is dropped
+    pub fn reset(&mut self, shared_data: Arc<SharedData>) {
+        self.shared_data = shared_data;
+        self.runner_cache.retain_runners();
+        self.cached_modules
+            .retain(|uid, _| !runner::is_transaction_local(uid.as_str()));
+        self.stats = SupervisorStats::default();
+    }
+
     pub fn cache_module(&mut self, data: &WasmFileDesc) -> Result<Arc<PrecompiledModule>> {
         let entry = self.cached_modules.entry(data.wasm_uid);
         match entry {
@@ -555,26 +771,36 @@ impl Supervisor {
                     })
                 };
 
-                let get_from_precompiled = || -> Result<PrecompiledModule> {
+                // `Ok(None)` means that there is nothing precompiled for this module
+                let get_from_precompiled = || -> Result<Option<PrecompiledModule>> {
                     if data.is_special() {
-                        anyhow::bail!("special runners are not supported");
+                        return Ok(None);
                     }
-                    let (id, hash) = runner::verify_runner(data.runner_id.as_str())?;
+                    let Ok((id, hash)) = runner::verify_runner(data.runner_id.as_str()) else {
+                        return Ok(None);
+                    };
+                    let Some(mut runner_dir) = self.cache_dir.clone() else {
+                        return Ok(None);
+                    };
+
+                    runner_dir.push(caching::PRECOMPILE_DIR_NAME);
+                    runner_dir.push(id);
+                    runner_dir.push(hash);
+
+                    let Some(manifest) = caching::PrecompileManifest::load(&runner_dir)? else {
+                        return Ok(None);
+                    };
 
                     let path_in_arch = data.path_in_arch.as_ref();
-                    let mut result_zip_path = self
-                        .cache_dir
-                        .clone()
-                        .ok_or_else(|| anyhow::anyhow!("cache is absent"))?;
+                    manifest.verify(&self.engines, path_in_arch, data.contents.as_ref())?;
 
-                    result_zip_path.push(caching::PRECOMPILE_DIR_NAME);
-                    result_zip_path.push(id);
-                    result_zip_path.push(hash);
-                    result_zip_path.push(caching::path_in_zip_to_hash(path_in_arch));
+                    let result_zip_path =
+                        runner_dir.join(caching::path_in_zip_to_hash(path_in_arch));
 
                     let process_single = |suff: &str, engine: &Engine| -> Result<Module> {
                         let path = result_zip_path.with_extension(suff);
                         unsafe { Module::deserialize_file(engine, &path) }
+                            .with_context(|| format!("deserializing {path:?}"))
                     };
 
                     let det = process_single(
@@ -588,14 +814,25 @@ impl Supervisor {
 
                     log_debug!(cache_method = "precompiled", runner = data.runner_id.as_str(); "using cached");
 
-                    Ok(PrecompiledModule { det, non_det })
+                    Ok(Some(PrecompiledModule { det, non_det }))
                 };
 
-                let ret = get_from_precompiled().inspect(|_| { self.stats.precompile_hits += 1; }).or_else(|e| {
-                    log_trace!(error:ah = &e, runner = data.runner_id.as_str(); "could not use precompiled");
-                    self.stats.compiled_modules += 1;
-                    compile_here()
-                })?;
+                let ret = match get_from_precompiled() {
+                    Ok(Some(ret)) => {
+                        self.stats.precompile_hits += 1;
+                        ret
+                    }
+                    Ok(None) => {
+                        log_trace!(runner = data.runner_id.as_str(); "no precompiled module");
+                        self.stats.compiled_modules += 1;
+                        compile_here()?
+                    }
+                    Err(e) => {
+                        log_warn!(error:ah = &e, runner = data.runner_id.as_str(), path = debug_path; "precompiled module is invalid, run `genvm precompile` again");
+                        self.stats.compiled_modules += 1;
+                        compile_here()?
+                    }
+                };
 
                 Ok(entry.insert(Arc::new(ret)).clone())
             }
@@ -605,15 +842,29 @@ impl Supervisor {
     pub async fn spawn(&mut self, data: crate::wasi::genlayer_sdk::SingleVMData) -> Result<VM> {
         let config_copy = data.conf;
 
-        let (engine, limiter) = if data.conf.is_deterministic {
-            (&self.engines.det, self.shared_data.limiter_det.clone())
+        let (engine, pool, id) = if data.conf.is_deterministic {
+            (&self.engines.det, &self.shared_data.limiter_det, "det-vm")
         } else {
             (
                 &self.engines.non_det,
-                self.shared_data.limiter_non_det.clone(),
+                &self.shared_data.limiter_non_det,
+                "non-det-vm",
             )
         };
 
+        let limits = &self.shared_data.memory_limits;
+        let own_limit = match data.kind {
+            VmKind::Main | VmKind::Nondet => None,
+            VmKind::Sandbox => limits.sandbox,
+            VmKind::Nested => limits.nested,
+        };
+        let limiter = pool.child(id, own_limit.unwrap_or(u32::MAX));
+        self.shared_data
+            .vm_limiters
+            .lock()
+            .unwrap()
+            .push((data.kind, limiter.clone()));
+
         let mut store = Store::new(
             engine,
             WasmContext::new(data, self.shared_data.clone(), limiter)?,
@@ -622,6 +873,44 @@ impl Supervisor {
 
         store.limiter(|ctx| &mut ctx.limits);
 
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(|ctx| {
+            for deadline in &ctx.data().deadlines {
+                deadline.check()?;
+            }
+            Ok(wasmtime::UpdateDeadline::Continue(1))
+        });
+
+        if config_copy.is_deterministic {
+            // fuel is kept in the store only while wasm is running,
+            // so that nested VMs spawned from host functions continue from the same counter
+            store.set_fuel(
+                self.shared_data
+                    .gas_remaining
+                    .load(std::sync::atomic::Ordering::SeqCst),
+            )?;
+
+            let shared_data = self.shared_data.clone();
+            store.call_hook(move |mut ctx, hook| {
+                use wasmtime::CallHook;
+                match hook {
+                    CallHook::CallingHost | CallHook::ReturningFromWasm => {
+                        shared_data
+                            .gas_remaining
+                            .store(ctx.get_fuel()?, std::sync::atomic::Ordering::SeqCst);
+                    }
+                    CallHook::CallingWasm | CallHook::ReturningFromHost => {
+                        ctx.set_fuel(
+                            shared_data
+                                .gas_remaining
+                                .load(std::sync::atomic::Ordering::SeqCst),
+                        )?;
+                    }
+                }
+                Ok(())
+            });
+        }
+
         let linker_shared = Arc::new(tokio::sync::Mutex::new(Linker::new(engine)));
 
         {
@@ -658,7 +947,6 @@ impl Supervisor {
         &mut self,
         ctx: &ApplyActionCtx,
         id: symbol_table::GlobalSymbol,
-        path: &std::path::Path,
     ) -> Result<symbol_table::GlobalSymbol> {
         if id.as_str() == "<contract>" {
             Ok(ctx.contract_id)
@@ -671,7 +959,7 @@ impl Supervisor {
                     anyhow::bail!("test/latest runner not allowed")
                 }
 
-                if let Some(borrowed) = try_get_latest(runner_id, path) {
+                if let Some(borrowed) = self.runner_cache.registry().latest(runner_id) {
                     let mut new_id = runner_id.to_owned();
                     new_id.push(':');
                     new_id.push_str(&borrowed);
@@ -684,6 +972,119 @@ impl Supervisor {
         }
     }
 
+    fn snapshot_path(
+        &self,
+        desc: &WasmFileDesc,
+        is_det: bool,
+        state: &str,
+    ) -> Option<std::path::PathBuf> {
+        if !self.snapshots
+            || desc.is_special()
+            || runner::is_transaction_local(desc.runner_id.as_str())
+        {
+            return None;
+        }
+
+        let (id, hash) = runner::verify_runner(desc.runner_id.as_str()).ok()?;
+        if hash == "test" || hash == "latest" {
+            return None;
+        }
+
+        let mut path = self.cache_dir.clone()?;
+        path.push(caching::SNAPSHOT_DIR_NAME);
+        path.push(id);
+        path.push(hash);
+        path.push(format!(
+            "{}-{state}",
+            caching::path_in_zip_to_hash(&desc.path_in_arch)
+        ));
+
+        Some(path.with_extension(if is_det {
+            caching::DET_NON_DET_PRECOMPILED_SUFFIX.det
+        } else {
+            caching::DET_NON_DET_PRECOMPILED_SUFFIX.non_det
+        }))
+    }
+
+    /// Calls `_initialize` or restores its result from a snapshot taken in the same `state`
+    async fn initialize(
+        &mut self,
+        vm: &mut VM,
+        desc: &WasmFileDesc,
+        state: &str,
+        instance: &wasmtime::Instance,
+        func: wasmtime::TypedFunc<(), ()>,
+    ) -> Result<()> {
+        let snapshot_path = self.snapshot_path(desc, vm.is_det(), state);
+
+        if let Some(path) = &snapshot_path {
+            match snapshot::Snapshot::load(path) {
+                Ok(snap) => {
+                    log_debug!(runner = desc.runner_id.as_str(), path = desc.debug_path(); "restoring snapshot");
+
+                    // restoring must cost exactly as much as initialization, otherwise gas differs
+                    // between nodes with and without snapshots
+                    let charged = self.shared_data.gas_remaining.fetch_update(
+                        std::sync::atomic::Ordering::SeqCst,
+                        std::sync::atomic::Ordering::SeqCst,
+                        |gas| gas.checked_sub(snap.fuel),
+                    );
+                    if charged.is_err() {
+                        // initialization would have trapped after spending all remaining gas
+                        self.shared_data
+                            .gas_remaining
+                            .store(0, std::sync::atomic::Ordering::SeqCst);
+                        return Err(
+                            VMError(public_abi::VmError::OutOfGas.value().into(), None).into()
+                        );
+                    }
+
+                    snap.restore(&mut vm.store, instance)?;
+                    self.stats.snapshot_hits += 1;
+                    return Ok(());
+                }
+                Err(e) => {
+                    log_trace!(error:ah = &e, runner = desc.runner_id.as_str(); "could not use snapshot");
+                }
+            }
+        }
+
+        log_info!(runner = desc.runner_id.as_str(), path = desc.debug_path(); "calling _initialize");
+
+        let gas_before = self
+            .shared_data
+            .gas_remaining
+            .load(std::sync::atomic::Ordering::SeqCst);
+        func.call_async(&mut vm.store, ()).await?;
+
+        let Some(path) = snapshot_path else {
+            return Ok(());
+        };
+
+        let fuel = if vm.is_det() {
+            gas_before
+                - self
+                    .shared_data
+                    .gas_remaining
+                    .load(std::sync::atomic::Ordering::SeqCst)
+        } else {
+            0
+        };
+
+        let res = snapshot::is_capturable(desc.contents.as_ref()).and_then(|capturable| {
+            if !capturable {
+                log_debug!(runner = desc.runner_id.as_str(), path = desc.debug_path(); "module state is not exported, snapshot is not possible");
+                return Ok(());
+            }
+            snapshot::Snapshot::capture(&mut vm.store, instance, fuel)?.save(&path)
+        });
+        if let Err(e) = res {
+            log_warn!(error:ah = &e, runner = desc.runner_id.as_str(); "saving snapshot failed");
+        }
+
+        Ok(())
+    }
+
     async fn apply_action_recursive(
         &mut self,
         vm: &mut VM,
@@ -693,26 +1094,19 @@ impl Supervisor {
     ) -> Result<Option<wasmtime::Instance>> {
         match action {
             InitAction::MapFile { to, file } => {
-                let limiter = if vm.is_det() {
-                    &self.shared_data.limiter_det
-                } else {
-                    &self.shared_data.limiter_non_det
-                };
+                let limiter = &vm.limiter();
 
                 if file.ends_with("/") {
-                    let arch = self.runner_cache.get_unsafe(current);
-
-                    let file_name_str = String::from(&file[..]);
+                    let files = self
+                        .runner_cache
+                        .get_unsafe(current)
+                        .get_dir(file, ctx.file_limiter(limiter))?;
 
-                    for (name, file_contents) in arch.files.data.range(file_name_str..) {
+                    for (name, file_contents) in files {
                         if name.ends_with("/") {
                             continue;
                         }
 
-                        if !name.starts_with(&file[..]) {
-                            break;
-                        }
-
                         let mut name_in_fs = String::from(&to[..]);
                         if !name_in_fs.ends_with("/") {
                             name_in_fs.push('/');
@@ -726,11 +1120,13 @@ impl Supervisor {
                             return Err(VMError::oom(None).into());
                         }
 
+                        ctx.record_file("map", &name_in_fs, current, &name, &file_contents);
+
                         vm.store
                             .data_mut()
                             .genlayer_ctx_mut()
                             .preview1
-                            .map_file(&name_in_fs, file_contents.clone())?;
+                            .map_file(&name_in_fs, file_contents)?;
                     }
                 } else {
                     if !limiter.consume(
@@ -739,11 +1135,17 @@ impl Supervisor {
                         return Err(VMError::oom(None).into());
                     }
 
+                    let contents = self
+                        .runner_cache
+                        .get_unsafe(current)
+                        .get_file(file, ctx.file_limiter(limiter))?;
+                    ctx.record_file("map", to, current, file, &contents);
+
                     vm.store
                         .data_mut()
                         .genlayer_ctx_mut()
                         .preview1
-                        .map_file(to, self.runner_cache.get_unsafe(current).get_file(file)?)?;
+                        .map_file(to, contents)?;
                 }
                 Ok(None)
             }
@@ -757,6 +1159,10 @@ impl Supervisor {
                 Ok(None)
             }
             InitAction::SetArgs(args) => {
+                ctx.record(
+                    "args",
+                    &args.iter().map(|x| x.as_bytes()).collect::<Vec<_>>(),
+                );
                 vm.store
                     .data_mut()
                     .genlayer_ctx_mut()
@@ -765,10 +1171,13 @@ impl Supervisor {
                 Ok(None)
             }
             InitAction::LinkWasm(path) => {
-                let contents = self.runner_cache.get_unsafe(current).get_file(path)?;
+                let contents = self
+                    .runner_cache
+                    .get_unsafe(current)
+                    .get_file(path, ctx.file_limiter(&vm.limiter()))?;
+                let desc = WasmFileDesc::new(contents, current, path.clone());
 
-                let module =
-                    self.link_wasm_into(vm, &WasmFileDesc::new(contents, current, path.clone()))?;
+                let module = self.link_wasm_into(vm, &desc)?;
                 let instance = {
                     let mut linker = vm.linker.lock().await;
                     let instance = linker.instantiate_async(&mut vm.store, &module).await?;
@@ -787,10 +1196,11 @@ impl Supervisor {
                 match instance.get_typed_func::<(), ()>(&mut vm.store, "_initialize") {
                     Err(_) => {}
                     Ok(func) => {
-                        log_info!(runner = self.runner_cache.get_unsafe(current).runner_id().as_str(), path = path; "calling _initialize");
-                        func.call_async(&mut vm.store, ()).await?;
+                        let state = ctx.state_digest(vm.is_det());
+                        self.initialize(vm, &desc, &state, &instance, func).await?;
                     }
                 }
+                ctx.record_file("link", path, current, path, &desc.contents);
                 Ok(None)
             }
             InitAction::StartWasm(path) => {
@@ -804,7 +1214,10 @@ impl Supervisor {
                     .genlayer_ctx_mut()
                     .preview1
                     .set_env(&env)?;
-                let contents = self.runner_cache.get_unsafe(current).get_file(path)?;
+                let contents = self
+                    .runner_cache
+                    .get_unsafe(current)
+                    .get_file(path, ctx.file_limiter(&vm.limiter()))?;
                 let module =
                     self.link_wasm_into(vm, &WasmFileDesc::new(contents, current, path.clone()))?;
 
@@ -814,7 +1227,18 @@ impl Supervisor {
                 ))
             }
             InitAction::When { cond, action } => {
-                if (*cond == WasmMode::Det) != vm.is_det() {
+                let cond_ctx = runner::ConditionCtx {
+                    mode: if vm.is_det() {
+                        WasmMode::Det
+                    } else {
+                        WasmMode::Nondet
+                    },
+                    genvm_version: ctx.genvm_version,
+                    contract_version: ctx.contract_version,
+                    entry_kind: ctx.entry_kind,
+                    env: &ctx.env,
+                };
+                if !cond.holds(&cond_ctx) {
                     return Ok(None);
                 }
                 Box::pin(self.apply_action_recursive(vm, ctx, action, current)).await
@@ -830,20 +1254,19 @@ impl Supervisor {
                 Ok(None)
             }
             InitAction::With { runner: id, action } => {
-                let path = self.runner_cache.path().clone();
+                let id = self.unfold_test_id_if_any(ctx, *id)?;
 
-                let id = self.unfold_test_id_if_any(ctx, *id, &path)?;
-
-                let limiter = if vm.is_det() {
-                    &self.shared_data.limiter_det
-                } else {
-                    &self.shared_data.limiter_non_det
-                };
+                let limiter = &vm.limiter();
 
+                let registry = self.runner_cache.registry().clone();
+                if !self.runner_cache.contains(id) {
+                    registry.ensure(id).await?;
+                }
                 let _ = self.runner_cache.get_or_create(
                     id,
-                    || make_new_runner_arch_from_tar(id, &path, limiter),
+                    || registry.load(id),
                     limiter,
+                    runner::charges_per_file(ctx.genvm_version),
                 )?;
 
                 Box::pin(self.apply_action_recursive(vm, ctx, action, id))
@@ -851,28 +1274,26 @@ impl Supervisor {
                     .with_context(|| format!("With {id}"))
             }
             InitAction::Depends(id) => {
-                let path = self.runner_cache.path().clone();
-
-                let id = self.unfold_test_id_if_any(ctx, *id, &path)?;
+                let id = self.unfold_test_id_if_any(ctx, *id)?;
 
                 if !ctx.visited.insert(id) {
                     return Ok(None);
                 }
 
-                let limiter = if vm.is_det() {
-                    &self.shared_data.limiter_det
-                } else {
-                    &self.shared_data.limiter_non_det
-                };
+                let limiter = &vm.limiter();
 
-                let path = self.runner_cache.path().clone();
+                let registry = self.runner_cache.registry().clone();
+                if !self.runner_cache.contains(id) {
+                    registry
+                        .ensure(id)
+                        .await
+                        .with_context(|| format!("fetching {id}"))?;
+                }
                 let new_arch = self.runner_cache.get_or_create(
                     id,
-                    || {
-                        make_new_runner_arch_from_tar(id, &path, limiter)
-                            .with_context(|| format!("loading {id}"))
-                    },
+                    || registry.load(id).with_context(|| format!("loading {id}")),
                     limiter,
+                    runner::charges_per_file(ctx.genvm_version),
                 )?;
                 let new_action = new_arch
                     .get_actions()
@@ -884,7 +1305,8 @@ impl Supervisor {
         }
     }
 
-    fn code_to_archive(code: SharedBytes) -> Result<Archive> {
+    /// Contract code can be a zip, a single wasm or a text with runner comment
+    pub fn code_to_archive(code: SharedBytes) -> Result<Archive> {
         if let Ok(mut as_zip) = zip::ZipArchive::new(std::io::Cursor::new(code.clone())) {
             return Archive::from_zip(&mut as_zip, code);
         }
@@ -959,35 +1381,35 @@ impl Supervisor {
     }
 
     pub async fn apply_contract_actions(&mut self, vm: &mut VM) -> Result<wasmtime::Instance> {
-        let (contract_address, datetime) = {
+        let (contract_address, datetime, entry_kind) = {
             let lock = vm.store.data().genlayer_ctx.lock().unwrap();
             (
                 lock.genlayer_sdk.data.message_data.contract_address,
                 lock.genlayer_sdk.data.message_data.datetime,
+                lock.genlayer_sdk.data.message_data.entry_kind,
             )
         };
 
         let contract_id = runner::get_id_of_contract(contract_address);
 
-        let limiter = if vm.is_det() {
-            &self.shared_data.limiter_det
+        let limiter = &vm.limiter();
+
+        let code = if self.runner_cache.contains(contract_id) {
+            None
         } else {
-            &self.shared_data.limiter_non_det
+            Some(
+                self.shared_data
+                    .host
+                    .get_code(vm.config_copy.state_mode, contract_address, limiter)
+                    .await?,
+            )
         };
 
         let provide_arch = || {
-            let code = self
-                .host
-                .get_code(vm.config_copy.state_mode, contract_address, limiter)?;
+            let code = code.ok_or_else(|| anyhow::anyhow!("contract code was not read"))?;
             Self::code_to_archive(SharedBytes::new(code))
         };
 
-        let cur_arch = self
-            .runner_cache
-            .get_or_create(contract_id, provide_arch, limiter)?;
-
-        let version = cur_arch.get_version()?;
-
         //self.shared_data.
         let transaction_ts = datetime.timestamp() as u64;
         let max_version_index =
@@ -997,7 +1419,18 @@ impl Supervisor {
                 Err(index) => index - 1,
             };
 
-        if version > crate::version_timestamps::DATA[max_version_index].1 {
+        let genvm_version = crate::version_timestamps::DATA[max_version_index].1;
+
+        let cur_arch = self.runner_cache.get_or_create(
+            contract_id,
+            provide_arch,
+            limiter,
+            runner::charges_per_file(genvm_version),
+        )?;
+
+        let version = cur_arch.get_version()?;
+
+        if version > genvm_version {
             return Err(VMError(public_abi::VmError::VersionTooBig.value().into(), None).into());
         }
 
@@ -1014,6 +1447,10 @@ impl Supervisor {
             env: BTreeMap::new(),
             visited: BTreeSet::new(),
             contract_id,
+            genvm_version,
+            contract_version: version,
+            entry_kind,
+            applied: sha3::Sha3_256::default(),
         };
         match self
             .apply_action_recursive(vm, &mut ctx, &actions, contract_id)
@@ -1026,12 +1463,17 @@ impl Supervisor {
         }
     }
 
+    pub fn stats(&self) -> &SupervisorStats {
+        &self.stats
+    }
+
     pub fn log_stats(&self) {
         log_debug!(
             all_wasm_modules:serde = self.cached_modules.keys().map(|x| x.as_str()).collect_vec(),
             stats:serde = self.stats,
-            det_max_memory = u32::MAX - self.shared_data.limiter_det.get_least_remaining_memory(),
-            non_det_max_memory = u32::MAX - self.shared_data.limiter_non_det.get_least_remaining_memory();
+            gas_used = self.shared_data.gas_used(),
+            det_max_memory = self.shared_data.limiter_det.high_water_mark(),
+            non_det_max_memory = self.shared_data.limiter_non_det.high_water_mark();
             "supervisor stats"
         );
     }
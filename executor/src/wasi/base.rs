@@ -2,11 +2,17 @@ use serde::Serialize;
 
 use crate::public_abi;
 
+/// Permissions of a single VM
+///
+/// All deterministic VMs, including called contracts, consume gas of the whole transaction,
+/// gas spent by a mutating call is not refunded if its writes are discarded
 #[derive(Clone, Copy, Serialize)]
 pub struct Config {
     pub needs_error_fingerprint: bool,
     pub is_deterministic: bool,
     pub can_read_storage: bool,
+    /// read storage of other contracts without calling them
+    pub can_read_others: bool,
     pub can_write_storage: bool,
     pub can_spawn_nondet: bool,
     pub can_send_messages: bool,
@@ -18,4 +24,30 @@ impl Config {
     pub fn is_main(&self) -> bool {
         self.state_mode == public_abi::StorageType::Default
     }
+
+    /// Only VM that can write its own storage in the default state can make mutating calls
+    pub fn can_call_mutating(&self) -> bool {
+        self.is_deterministic && self.can_call_others && self.can_write_storage && self.is_main()
+    }
+
+    /// Permissions of a contract called from VM with this config, they never exceed the caller ones.
+    /// Callee can write its storage only if call is mutating, in which case it runs in the default state.
+    /// Mutating callee can't send messages, as they can't be taken back if its writes are discarded
+    pub fn for_called_contract(&self, state_mode: public_abi::StorageType, write: bool) -> Self {
+        Self {
+            needs_error_fingerprint: true,
+            is_deterministic: true,
+            can_read_storage: self.can_read_storage,
+            can_read_others: self.can_read_others,
+            can_write_storage: write && self.can_write_storage,
+            can_spawn_nondet: self.can_spawn_nondet,
+            can_call_others: self.can_call_others,
+            can_send_messages: !write && self.can_send_messages,
+            state_mode: if write {
+                public_abi::StorageType::Default
+            } else {
+                state_mode
+            },
+        }
+    }
 }
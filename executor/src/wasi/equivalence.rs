@@ -0,0 +1,218 @@
+//! Built-in comparisons of leader and validator results of `RunNondet`,
+//! with them validator runs leader function instead of its own one
+
+use crate::calldata;
+use crate::vm::RunOk;
+
+use super::gl_call::Equivalence;
+
+/// Upper bound of validator runs for [`Equivalence::Majority`]
+pub const MAX_RUNS: u32 = 16;
+
+impl Equivalence {
+    /// How many times validator runs leader function
+    pub fn runs(&self) -> u32 {
+        match self {
+            Self::Majority { runs, .. } => *runs,
+            _ => 1,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Self::Majority { runs, quorum } => *runs <= MAX_RUNS && 0 < *quorum && quorum <= runs,
+            _ => true,
+        }
+    }
+
+    /// Same form as it is received in `RunNondet`
+    pub fn to_calldata(&self) -> calldata::Value {
+        use calldata::Value;
+
+        let (kind, fields) = match self {
+            Self::Exact => return Value::Str("Exact".into()),
+            Self::Tolerance { abs, rel_ppm } => (
+                "Tolerance",
+                vec![
+                    ("abs", Value::Number(abs.clone())),
+                    ("rel_ppm", Value::Number((*rel_ppm).into())),
+                ],
+            ),
+            Self::Fields { fields } => (
+                "Fields",
+                vec![(
+                    "fields",
+                    Value::Array(fields.iter().map(|f| Value::Str(f.clone())).collect()),
+                )],
+            ),
+            Self::Majority { runs, quorum } => (
+                "Majority",
+                vec![
+                    ("runs", Value::Number((*runs).into())),
+                    ("quorum", Value::Number((*quorum).into())),
+                ],
+            ),
+        };
+
+        Value::Map(std::collections::BTreeMap::from([(
+            kind.to_owned(),
+            Value::Map(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect()),
+        )]))
+    }
+
+    /// `mine` contains exactly [`Self::runs`] results
+    pub fn agrees(&self, leader: &RunOk, mine: &[RunOk]) -> bool {
+        match self {
+            Self::Exact => mine.iter().all(|mine| same(leader, mine)),
+            Self::Tolerance { abs, rel_ppm } => mine
+                .iter()
+                .all(|mine| within_tolerance(leader, mine, abs, *rel_ppm)),
+            Self::Fields { fields } => mine.iter().all(|mine| same_fields(leader, mine, fields)),
+            Self::Majority { quorum, .. } => {
+                mine.iter().filter(|mine| same(leader, mine)).count() >= *quorum as usize
+            }
+        }
+    }
+}
+
+fn same(leader: &RunOk, mine: &RunOk) -> bool {
+    leader.as_bytes_iter().eq(mine.as_bytes_iter())
+}
+
+fn decode_return(res: &RunOk) -> Option<calldata::Value> {
+    match res {
+        RunOk::Return(data) => calldata::decode(data).ok(),
+        _ => None,
+    }
+}
+
+/// Non-numeric results must be the same
+fn within_tolerance(leader: &RunOk, mine: &RunOk, abs: &num_bigint::BigInt, rel_ppm: u32) -> bool {
+    let (Some(calldata::Value::Number(l)), Some(calldata::Value::Number(m))) =
+        (decode_return(leader), decode_return(mine))
+    else {
+        return same(leader, mine);
+    };
+
+    let diff = (&l - &m).magnitude().clone();
+    let allowed = abs.magnitude() + l.magnitude() * rel_ppm / 1_000_000u32;
+
+    diff <= allowed
+}
+
+fn lookup<'a>(mut value: &'a calldata::Value, path: &str) -> Option<&'a calldata::Value> {
+    for key in path.split('.') {
+        match value {
+            calldata::Value::Map(map) => value = map.get(key)?,
+            _ => return None,
+        }
+    }
+
+    Some(value)
+}
+
+/// Results that are not maps must be the same
+fn same_fields(leader: &RunOk, mine: &RunOk, fields: &[String]) -> bool {
+    let (Some(l @ calldata::Value::Map(_)), Some(m @ calldata::Value::Map(_))) =
+        (decode_return(leader), decode_return(mine))
+    else {
+        return same(leader, mine);
+    };
+
+    fields
+        .iter()
+        .all(|path| lookup(&l, path) == lookup(&m, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn ret(value: calldata::Value) -> RunOk {
+        RunOk::Return(calldata::encode(&value))
+    }
+
+    #[test]
+    fn tolerance() {
+        let eq = Equivalence::Tolerance {
+            abs: 1.into(),
+            rel_ppm: 10_000,
+        };
+
+        let leader = ret(calldata::Value::Number(1000.into()));
+        assert!(eq.agrees(&leader, &[ret(calldata::Value::Number(1011.into()))]));
+        assert!(!eq.agrees(&leader, &[ret(calldata::Value::Number(988.into()))]));
+        assert!(!eq.agrees(&leader, &[RunOk::UserError("1000".into())]));
+    }
+
+    #[test]
+    fn fields() {
+        let eq = Equivalence::Fields {
+            fields: vec!["a.b".into()],
+        };
+
+        let make = |b: i32, c: i32| {
+            let inner = BTreeMap::from([("b".to_owned(), calldata::Value::Number(b.into()))]);
+            ret(calldata::Value::Map(BTreeMap::from([
+                ("a".to_owned(), calldata::Value::Map(inner)),
+                ("c".to_owned(), calldata::Value::Number(c.into())),
+            ])))
+        };
+
+        assert!(eq.agrees(&make(1, 2), &[make(1, 3)]));
+        assert!(!eq.agrees(&make(1, 2), &[make(2, 2)]));
+    }
+
+    #[test]
+    fn majority() {
+        let eq = Equivalence::Majority { runs: 3, quorum: 2 };
+        assert!(eq.is_valid());
+
+        let leader = RunOk::UserError("x".into());
+        let other = RunOk::UserError("y".into());
+        assert!(eq.agrees(
+            &leader,
+            &[
+                RunOk::UserError("x".into()),
+                other,
+                RunOk::UserError("x".into())
+            ]
+        ));
+        assert!(!eq.agrees(
+            &leader,
+            &[
+                RunOk::UserError("x".into()),
+                RunOk::UserError("y".into()),
+                RunOk::UserError("z".into())
+            ]
+        ));
+
+        assert!(!Equivalence::Majority { runs: 2, quorum: 3 }.is_valid());
+    }
+
+    #[test]
+    fn to_calldata_keeps_numbers() {
+        let abs = num_bigint::BigInt::from(u64::MAX) * 1000;
+        let eq = Equivalence::Tolerance {
+            abs: abs.clone(),
+            rel_ppm: 10,
+        };
+
+        assert_eq!(
+            eq.to_calldata(),
+            calldata::Value::Map(BTreeMap::from([(
+                "Tolerance".to_owned(),
+                calldata::Value::Map(BTreeMap::from([
+                    ("abs".to_owned(), calldata::Value::Number(abs)),
+                    ("rel_ppm".to_owned(), calldata::Value::Number(10.into())),
+                ])),
+            )]))
+        );
+        assert_eq!(
+            Equivalence::Exact.to_calldata(),
+            calldata::Value::Str("Exact".into())
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Event ABI: schemas that contracts declare in `#get-schema` under `events`,
+//! validation of emitted blobs against them and derivation of topics.
+//! SDK sends the schema of an event along with it, field types are only informational, for decoders
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use sha3::{Digest, Keccak256};
+
+use crate::calldata;
+use crate::public_abi;
+
+#[derive(Deserialize, Debug)]
+pub struct EventSchema {
+    /// names of indexed fields, order is irrelevant
+    pub indexed: Vec<String>,
+    /// whether fields that are not indexed are allowed
+    #[serde(default)]
+    pub blob: bool,
+}
+
+impl EventSchema {
+    fn sorted_indexed(&self) -> Vec<&str> {
+        let mut indexed: Vec<&str> = self.indexed.iter().map(String::as_str).collect();
+        indexed.sort();
+        indexed
+    }
+
+    /// `Name(a,b)` with indexed fields sorted, topic 0 is hash of it
+    pub fn signature(&self, name: &str) -> String {
+        format!("{name}({})", self.sorted_indexed().join(","))
+    }
+
+    /// Checks `blob` and returns all topics, starting with the signature one
+    pub fn topics(
+        &self,
+        name: &str,
+        blob: &BTreeMap<String, calldata::Value>,
+    ) -> Result<Vec<[u8; 32]>, String> {
+        let indexed = self.sorted_indexed();
+
+        if indexed.len() + 1 > public_abi::EVENT_MAX_TOPICS as usize {
+            return Err(format!("too many indexed fields: {}", indexed.len()));
+        }
+
+        if !self.blob {
+            if let Some(field) = blob.keys().find(|k| !indexed.contains(&k.as_str())) {
+                return Err(format!("unexpected field `{field}`"));
+            }
+        }
+
+        let mut topics = Vec::with_capacity(indexed.len() + 1);
+        topics.push(keccak(self.signature(name).as_bytes()));
+
+        for field in indexed {
+            let Some(value) = blob.get(field) else {
+                return Err(format!("missing indexed field `{field}`"));
+            };
+            topics.push(value_topic(value));
+        }
+
+        Ok(topics)
+    }
+}
+
+fn keccak(data: &[u8]) -> [u8; 32] {
+    let mut ret = [0; 32];
+    ret.copy_from_slice(Keccak256::digest(data).as_slice());
+    ret
+}
+
+/// Encoded value padded with zeroes, or hash of encoding if it doesn't fit
+fn value_topic(value: &calldata::Value) -> [u8; 32] {
+    let encoded = calldata::encode(value);
+    if encoded.len() > 32 {
+        return keccak(&encoded);
+    }
+
+    let mut topic = [0; 32];
+    topic[..encoded.len()].copy_from_slice(&encoded);
+    topic
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(json: serde_json::Value) -> EventSchema {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn topics() {
+        let ev = schema(serde_json::json!({
+            "indexed": ["to", "from"],
+            "params": {"from": "address", "to": "address"},
+            "blob": true,
+        }));
+
+        assert_eq!(ev.signature("Transfer"), "Transfer(from,to)");
+
+        let mut blob = BTreeMap::new();
+        blob.insert(
+            "from".to_owned(),
+            calldata::Value::Address(calldata::Address::zero()),
+        );
+        blob.insert("to".to_owned(), calldata::Value::Str("x".repeat(40)));
+        blob.insert("amount".to_owned(), calldata::Value::Number(10.into()));
+
+        let topics = ev.topics("Transfer", &blob).unwrap();
+        assert_eq!(topics.len(), 3);
+        assert_eq!(topics[0], keccak(b"Transfer(from,to)"));
+        assert_eq!(
+            topics[2],
+            keccak(&calldata::encode(&calldata::Value::Str("x".repeat(40))))
+        );
+
+        blob.remove("to");
+        assert!(ev.topics("Transfer", &blob).is_err());
+    }
+
+    #[test]
+    fn strict_blob() {
+        let ev = schema(serde_json::json!({"indexed": ["id"]}));
+
+        let mut blob = BTreeMap::new();
+        blob.insert("id".to_owned(), calldata::Value::Number(1.into()));
+
+        let topics = ev.topics("Created", &blob).unwrap();
+        let mut expected = [0; 32];
+        let encoded = calldata::encode(&calldata::Value::Number(1.into()));
+        expected[..encoded.len()].copy_from_slice(&encoded);
+        assert_eq!(topics[1], expected);
+
+        blob.insert("extra".to_owned(), calldata::Value::Null);
+        assert!(ev.topics("Created", &blob).is_err());
+    }
+}
@@ -6,7 +6,7 @@ use genvm_common::*;
 use genvm_modules_interfaces::GenericValue;
 use wiggle::GuestError;
 
-use crate::host::SlotID;
+use crate::host::{LockedSlotsSet, SlotID};
 use crate::{
     calldata,
     errors::*,
@@ -83,11 +83,24 @@ impl TransformedMessage {
     }
 }
 
+/// Why VM was spawned, determines which memory budget applies to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VmKind {
+    Main,
+    Nondet,
+    Sandbox,
+    Nested,
+}
+
 pub struct SingleVMData {
+    pub kind: VmKind,
     pub conf: base::Config,
     pub message_data: TransformedMessage,
     pub supervisor: Arc<tokio::sync::Mutex<crate::vm::Supervisor>>,
     pub version: genvm_common::version::Version,
+    /// slots that can't be written, if absent locked slots of the entry contract are used
+    pub locked_slots: Option<Arc<LockedSlotsSet>>,
 }
 
 pub struct Context {
@@ -309,6 +322,22 @@ where
     }
 }
 
+/// Result of `CallContract` with `structured` flag: `{ kind, payload, fingerprint, address }`,
+/// where `payload` is calldata bytes for `return` and message for `user_error` and `vm_error`
+fn call_result_envelope(
+    address: calldata::Address,
+    (res, fingerprint): vm::FullRunOk,
+) -> anyhow::Result<calldata::Value> {
+    let calldata::Value::Map(mut envelope) = res.to_calldata() else {
+        unreachable!()
+    };
+
+    envelope.insert("fingerprint".to_owned(), calldata::to_value(&fingerprint)?);
+    envelope.insert("address".to_owned(), calldata::Value::Address(address));
+
+    Ok(calldata::Value::Map(envelope))
+}
+
 const NO_FILE: u32 = u32::MAX;
 
 #[inline]
@@ -391,11 +420,11 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 });
                 let data_str = serde_json::to_string(&data_json).unwrap();
 
-                let supervisor = self.context.data.supervisor.clone();
-                let mut supervisor = supervisor.lock().await;
-                let res = supervisor
+                self.context
+                    .shared_data
                     .host
                     .eth_send(address, &calldata, &data_str)
+                    .await
                     .map_err(generated::types::Error::trap)?;
 
                 self.context.messages_decremented += value;
@@ -409,11 +438,12 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                     return Err(generated::types::Errno::Forbidden.into());
                 }
 
-                let supervisor = self.context.data.supervisor.clone();
-                let mut supervisor = supervisor.lock().await;
-                let res = supervisor
+                let res = self
+                    .context
+                    .shared_data
                     .host
                     .eth_call(address, &calldata)
+                    .await
                     .map_err(generated::types::Error::trap)?;
                 Ok(generated::types::Fd::from(self.vfs.place_content(
                     FileContentsUnevaluated::from_contents(SharedBytes::new(res), 0),
@@ -423,7 +453,12 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 address,
                 calldata,
                 mut state,
+                structured,
+                write,
             } => {
+                if structured || write {
+                    self.check_version(genvm_common::version::Version::new(0, 1, 6))?;
+                }
                 if !self.context.data.conf.is_deterministic {
                     return Err(generated::types::Errno::Forbidden.into());
                 }
@@ -431,7 +466,20 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                     return Err(generated::types::Errno::Forbidden.into());
                 }
 
-                if state == public_abi::StorageType::Default {
+                let host = self.context.shared_data.host.clone();
+
+                if write {
+                    if !self.context.data.conf.can_call_mutating() {
+                        return Err(generated::types::Errno::Forbidden.into());
+                    }
+                    if state != public_abi::StorageType::Default {
+                        return Err(generated::types::Errno::Inval.into());
+                    }
+                    if !host.supports_nested_writes() {
+                        log_warn!("host protocol doesn't support mutating calls");
+                        return Err(generated::types::Errno::Forbidden.into());
+                    }
+                } else if state == public_abi::StorageType::Default {
                     state = public_abi::StorageType::LatestNonFinal;
                 }
 
@@ -448,22 +496,40 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                     .fork(public_abi::EntryKind::Main, calldata_encoded);
                 my_data.stack.push(my_data.contract_address);
 
-                let calldata_encoded = calldata::encode(&calldata);
+                let limits = self.context.shared_data.call_limits;
+                if my_data.stack.len() > limits.max_depth as usize {
+                    log_warn!(depth = my_data.stack.len(), max = limits.max_depth; "call depth exceeded");
+                    return Err(generated::types::Errno::CallDepth.into());
+                }
+                if limits.cycles == crate::config::CyclePolicy::Forbid
+                    && my_data.stack.contains(&address)
+                {
+                    log_warn!(address:? = address, stack:? = my_data.stack; "call cycle forbidden");
+                    return Err(generated::types::Errno::CallCycle.into());
+                }
+
+                // callee of a mutating call sees its caller as the sender
+                let (sender_address, locked_slots) = if write {
+                    let locked_slots = host
+                        .get_locked_slots_for_sender(
+                            address,
+                            my_data.contract_address,
+                            &self.context.shared_data.limiter_det,
+                        )
+                        .await
+                        .map_err(generated::types::Error::trap)?;
+
+                    (my_data.contract_address, Some(Arc::new(locked_slots)))
+                } else {
+                    (my_data.sender_address, None)
+                };
 
                 let vm_data = SingleVMData {
-                    conf: base::Config {
-                        needs_error_fingerprint: true,
-                        is_deterministic: true,
-                        can_read_storage: my_conf.can_read_storage,
-                        can_write_storage: false,
-                        can_spawn_nondet: my_conf.can_spawn_nondet,
-                        can_call_others: my_conf.can_call_others,
-                        can_send_messages: my_conf.can_send_messages,
-                        state_mode: state,
-                    },
+                    kind: VmKind::Nested,
+                    conf: my_conf.for_called_contract(state, write),
                     message_data: TransformedMessage {
                         contract_address: address,
-                        sender_address: my_data.sender_address,
+                        sender_address,
                         origin_address: my_data.origin_address,
                         value: num_bigint::BigInt::ZERO,
                         is_init: false,
@@ -476,50 +542,168 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                     },
                     supervisor: supervisor.clone(),
                     version: genvm_common::version::Version::ZERO,
+                    locked_slots,
                 };
 
-                let res = self
+                if write {
+                    host.begin_nested_writes()
+                        .map_err(generated::types::Error::trap)?;
+                }
+
+                let res = match self
                     .context
-                    .spawn_and_run(&supervisor, vm_data)
+                    .spawn_and_run_full(&supervisor, vm_data, None)
                     .await
+                {
+                    Err(e) if structured => {
+                        errors::unwrap_vm_errors_fingerprint(e).map(|(a, b)| (a, Some(b)))
+                    }
+                    res => res,
+                };
+
+                if write {
+                    if let Ok((RunOk::Return(_), _)) = &res {
+                        host.commit_nested_writes()
+                    } else {
+                        host.discard_nested_writes()
+                    }
                     .map_err(generated::types::Error::trap)?;
+                }
+
+                let res = res.map_err(generated::types::Error::trap)?;
+
+                self.context
+                    .shared_data
+                    .check_deadline()
+                    .map_err(|e| generated::types::Error::trap(e.into()))?;
+
+                if !structured {
+                    return self.set_vm_run_result(res.0).map(|x| x.0);
+                }
+
+                let envelope =
+                    call_result_envelope(address, res).map_err(generated::types::Error::trap)?;
 
-                self.set_vm_run_result(res).map(|x| x.0)
+                Ok(generated::types::Fd::from(self.vfs.place_content(
+                    FileContentsUnevaluated::from_contents(
+                        SharedBytes::new(calldata::encode(&envelope)),
+                        0,
+                    ),
+                )))
             }
-            gl_call::Message::EmitEvent { topics, blob } => {
-                self.check_version(genvm_common::version::Version::new(0, 1, 5))?;
+            gl_call::Message::StorageRead {
+                address,
+                slot,
+                index,
+                len,
+                mut state,
+            } => {
+                self.check_version(genvm_common::version::Version::new(0, 1, 6))?;
 
                 if !self.context.data.conf.is_deterministic {
-                    log_warn!("forbidden emit event in deterministic mode");
-
+                    return Err(generated::types::Errno::Forbidden.into());
+                }
+                if !self.context.data.conf.can_read_storage
+                    || !self.context.data.conf.can_read_others
+                {
                     return Err(generated::types::Errno::Forbidden.into());
                 }
 
-                if topics.len() > public_abi::EVENT_MAX_TOPICS as usize {
-                    log_warn!(cnt = topics.len(), max = public_abi::EVENT_MAX_TOPICS; "too many topics");
+                let Ok(slot) = <[u8; 32]>::try_from(slot.0) else {
                     return Err(generated::types::Errno::Inval.into());
+                };
+                if index.checked_add(len).is_none() {
+                    return Err(generated::types::Errno::Inval.into());
+                }
+
+                // as in view calls, `default` state of other contracts means the latest non-final one
+                if address == self.context.data.message_data.contract_address {
+                    state = self.context.data.conf.state_mode;
+                } else if state == public_abi::StorageType::Default {
+                    state = public_abi::StorageType::LatestNonFinal;
                 }
 
-                let mut real_topics = [[0; 32]; public_abi::EVENT_MAX_TOPICS as usize];
+                if !self.context.shared_data.limiter_det.consume(len) {
+                    return Err(generated::types::Error::trap(VMError::oom(None).into()));
+                }
 
-                for (i, gl_call::Bytes(t)) in topics.iter().enumerate() {
-                    if t.len() != 32 {
-                        log_warn!(len = t.len(); "invalid topic length");
+                let mut res = vec![0; len as usize];
+                self.context
+                    .shared_data
+                    .host
+                    .storage_read(state, address, SlotID(slot), index, &mut res)
+                    .await
+                    .map_err(generated::types::Error::trap)?;
 
-                        return Err(generated::types::Errno::Inval.into());
-                    }
+                Ok(generated::types::Fd::from(self.vfs.place_content(
+                    FileContentsUnevaluated::from_contents(SharedBytes::new(res), 0),
+                )))
+            }
+            gl_call::Message::EmitEvent {
+                topics,
+                blob,
+                name,
+                schema,
+            } => {
+                self.check_version(genvm_common::version::Version::new(0, 1, 5))?;
+
+                if !self.context.data.conf.is_deterministic {
+                    log_warn!("forbidden emit event in deterministic mode");
 
-                    real_topics[i].copy_from_slice(t);
+                    return Err(generated::types::Errno::Forbidden.into());
+                }
+                if !self.context.data.conf.can_send_messages {
+                    return Err(generated::types::Errno::Forbidden.into());
                 }
 
-                let blob_data = calldata::encode(&calldata::Value::Map(blob));
+                let real_topics = match &name {
+                    None => {
+                        if topics.len() > public_abi::EVENT_MAX_TOPICS as usize {
+                            log_warn!(cnt = topics.len(), max = public_abi::EVENT_MAX_TOPICS; "too many topics");
+                            return Err(generated::types::Errno::Inval.into());
+                        }
 
-                let supervisor = self.context.data.supervisor.clone();
+                        let mut real_topics = Vec::with_capacity(topics.len());
+
+                        for gl_call::Bytes(t) in &topics {
+                            let Ok(t) = <[u8; 32]>::try_from(t.as_slice()) else {
+                                log_warn!(len = t.len(); "invalid topic length");
+
+                                return Err(generated::types::Errno::Inval.into());
+                            };
 
-                let mut supervisor = supervisor.lock().await;
-                supervisor
+                            real_topics.push(t);
+                        }
+
+                        real_topics
+                    }
+                    Some(name) => {
+                        self.check_version(genvm_common::version::Version::new(0, 1, 6))?;
+
+                        if !topics.is_empty() {
+                            log_warn!(name = name; "topics of declared event are computed from its schema");
+                            return Err(generated::types::Errno::Inval.into());
+                        }
+
+                        let Some(schema) = &schema else {
+                            log_warn!(name = name; "declared event without schema");
+                            return Err(generated::types::Errno::Inval.into());
+                        };
+
+                        schema.topics(name, &blob).map_err(|reason| {
+                            log_warn!(name = name, reason = reason; "event doesn't match schema");
+                            generated::types::Error::from(generated::types::Errno::Inval)
+                        })?
+                    }
+                };
+
+                let blob_data = calldata::encode(&calldata::Value::Map(blob));
+
+                self.context
+                    .shared_data
                     .host
-                    .post_event(&real_topics[..topics.len()], &blob_data)
+                    .post_event(&real_topics, &blob_data, name.as_deref())
+                    .await
                     .map_err(generated::types::Error::trap)?;
 
                 return Ok(file_fd_none());
@@ -556,11 +740,11 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 });
                 let data_str = serde_json::to_string(&data_json).unwrap();
 
-                let supervisor = self.context.data.supervisor.clone();
-                let mut supervisor = supervisor.lock().await;
-                let res = supervisor
+                self.context
+                    .shared_data
                     .host
                     .post_message(&address, &calldata_encoded, &data_str)
+                    .await
                     .map_err(generated::types::Error::trap)?;
 
                 self.context.messages_decremented += value;
@@ -601,11 +785,11 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 });
                 let data_str = serde_json::to_string(&data_json).unwrap();
 
-                let supervisor = self.context.data.supervisor.clone();
-                let mut supervisor = supervisor.lock().await;
-                let res = supervisor
+                self.context
+                    .shared_data
                     .host
                     .deploy_contract(&calldata_encoded, &code, &data_str)
+                    .await
                     .map_err(generated::types::Error::trap)?;
 
                 self.context.messages_decremented += value;
@@ -658,13 +842,12 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 }
 
                 // Get remaining fuel from host
-                let supervisor = self.context.data.supervisor.clone();
-                let mut sup = supervisor.lock().await;
-                let remaining_fuel_as_gen = sup
+                let shared_data = self.context.shared_data.clone();
+                let remaining_fuel_as_gen = shared_data
                     .host
                     .remaining_fuel_as_gen()
+                    .await
                     .map_err(generated::types::Error::trap)?;
-                std::mem::drop(sup);
 
                 let llm = self.context.shared_data.modules.llm.clone();
                 let task = tokio::spawn(taskify(async move {
@@ -680,11 +863,11 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                     use genvm_modules_interfaces::llm::PromptAnswer;
 
                     if let Ok(PromptAnswer { consumed_gen, .. }) = &result {
-                        let mut sup = supervisor.lock().await;
-                        sup.host
+                        shared_data
+                            .host
                             .consume_fuel(*consumed_gen)
+                            .await
                             .map_err(generated::types::Error::trap)?;
-                        std::mem::drop(sup);
                     }
 
                     Ok(result.map(|r| r.data))
@@ -706,13 +889,12 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 );
 
                 // Get remaining fuel from host
-                let supervisor = self.context.data.supervisor.clone();
-                let mut sup = supervisor.lock().await;
-                let remaining_fuel_as_gen = sup
+                let shared_data = self.context.shared_data.clone();
+                let remaining_fuel_as_gen = shared_data
                     .host
                     .remaining_fuel_as_gen()
+                    .await
                     .map_err(generated::types::Error::trap)?;
-                std::mem::drop(sup);
 
                 let llm = self.context.shared_data.modules.llm.clone();
                 let task = tokio::spawn(taskify(async move {
@@ -727,9 +909,10 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                     use genvm_modules_interfaces::llm::{PromptAnswer, PromptAnswerData};
 
                     if let Ok(PromptAnswer { consumed_gen, .. }) = &answer {
-                        let mut sup = supervisor.lock().await;
-                        sup.host
+                        shared_data
+                            .host
                             .consume_fuel(*consumed_gen)
+                            .await
                             .map_err(generated::types::Error::trap)?;
                     }
 
@@ -768,7 +951,11 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
             gl_call::Message::RunNondet {
                 data_leader,
                 data_validator,
-            } => self.run_nondet(data_leader, data_validator).await,
+                equivalence,
+            } => {
+                self.run_nondet(data_leader, data_validator, equivalence)
+                    .await
+            }
             gl_call::Message::Sandbox {
                 data,
                 allow_write_ops,
@@ -804,10 +991,8 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
         let mut vec = Vec::with_capacity(mem_size);
         unsafe { vec.set_len(mem_size) };
 
-        let supervisor = self.context.data.supervisor.clone();
-        let mut supervisor = supervisor.lock().await;
-
-        supervisor
+        self.context
+            .shared_data
             .host
             .storage_read(
                 self.context.data.conf.state_mode,
@@ -816,6 +1001,7 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 index,
                 &mut vec,
             )
+            .await
             .map_err(generated::types::Error::trap)?;
 
         mem.copy_from_slice(&vec, buf)?;
@@ -845,18 +1031,26 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
 
         let slot = SlotID::read_from_mem(mem, slot)?;
 
-        if self.context.shared_data.locked_slots.contains(slot) {
+        let locked = match &self.context.data.locked_slots {
+            Some(locked_slots) => locked_slots.contains(slot),
+            None => self.context.shared_data.locked_slots.contains(slot),
+        };
+        if locked {
             return Err(generated::types::Errno::Forbidden.into());
         }
 
         let buf: Vec<u8> = read_owned_vec(mem, buf)?;
 
-        let supervisor = self.context.data.supervisor.clone();
-        let mut supervisor = supervisor.lock().await;
-
-        supervisor
+        self.context
+            .shared_data
             .host
-            .storage_write(slot, index, &buf)
+            .storage_write(
+                self.context.data.message_data.contract_address,
+                slot,
+                index,
+                &buf,
+            )
+            .await
             .map_err(generated::types::Error::trap)
     }
 
@@ -921,11 +1115,11 @@ impl Context {
             return Ok(*res);
         }
 
-        let supervisor = self.data.supervisor.clone();
-        let mut supervisor = supervisor.lock().await;
-        let res = supervisor
+        let res = self
+            .shared_data
             .host
             .get_balance(address)
+            .await
             .map_err(generated::types::Error::trap)?;
 
         let _ = self.shared_data.balances.insert(address, res);
@@ -947,7 +1141,20 @@ impl Context {
         &mut self,
         supervisor: &Arc<tokio::sync::Mutex<crate::vm::Supervisor>>,
         essential_data: SingleVMData,
+        deadline: Option<vm::Deadline>,
     ) -> anyhow::Result<vm::RunOk> {
+        self.spawn_and_run_full(supervisor, essential_data, deadline)
+            .await
+            .map(|x| x.0)
+    }
+
+    /// Same as [`Self::spawn_and_run`], but keeps fingerprint if VM produces one
+    async fn spawn_and_run_full(
+        &mut self,
+        supervisor: &Arc<tokio::sync::Mutex<crate::vm::Supervisor>>,
+        essential_data: SingleVMData,
+        deadline: Option<vm::Deadline>,
+    ) -> anyhow::Result<vm::FullRunOk> {
         let limiter = if essential_data.conf.is_deterministic {
             self.shared_data.limiter_det.clone()
         } else {
@@ -958,6 +1165,9 @@ impl Context {
             let mut supervisor = supervisor.lock().await;
 
             let mut vm = supervisor.spawn(essential_data).await?;
+            if let Some(deadline) = deadline {
+                vm.add_deadline(deadline);
+            }
             let instance = supervisor.apply_contract_actions(&mut vm).await?;
 
             (vm, instance, limiter.save())
@@ -966,7 +1176,7 @@ impl Context {
 
         limiter.restore(limiter_save);
 
-        result.map(|x| x.0)
+        result
     }
 }
 
@@ -975,11 +1185,21 @@ impl ContextVFS<'_> {
         &mut self,
         data_leader: Vec<u8>,
         data_validator: Vec<u8>,
+        equivalence: Option<gl_call::Equivalence>,
     ) -> Result<generated::types::Fd, generated::types::Error> {
         if !self.context.data.conf.can_spawn_nondet {
             return Err(generated::types::Errno::Forbidden.into());
         }
 
+        if let Some(equivalence) = &equivalence {
+            self.check_version(genvm_common::version::Version::new(0, 1, 6))?;
+
+            if !equivalence.is_valid() {
+                log_warn!(equivalence:? = equivalence; "invalid equivalence");
+                return Err(generated::types::Errno::Inval.into());
+            }
+        }
+
         // relaxed reason: only deterministic VM can run it
         let call_no = self
             .context
@@ -987,12 +1207,13 @@ impl ContextVFS<'_> {
             .nondet_call_no
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
-        let leaders_res = {
-            let supervisor = self.context.data.supervisor.clone();
-            let mut supervisor = supervisor.lock().await;
-            supervisor.host.get_leader_result(call_no)
-        }
-        .map_err(generated::types::Error::trap)?;
+        let leaders_res = self
+            .context
+            .shared_data
+            .host
+            .get_leader_result(call_no)
+            .await
+            .map_err(generated::types::Error::trap)?;
 
         let leaders_res = match (leaders_res, self.context.shared_data.is_sync) {
             (leaders_res, false) => leaders_res,
@@ -1004,84 +1225,126 @@ impl ContextVFS<'_> {
             }
         };
 
-        let message_data = match &leaders_res {
-            None => self.context.data.message_data.fork_leader(
-                public_abi::EntryKind::ConsensusStage,
-                data_leader,
-                None,
-            ),
-            Some(leaders_res) => {
-                let dup = match leaders_res {
-                    RunOk::Return(items) => RunOk::Return(items.clone()),
-                    RunOk::UserError(msg) => RunOk::UserError(msg.clone()),
-                    RunOk::VMError(msg, _) => RunOk::VMError(msg.clone(), None),
-                };
-                self.context.data.message_data.fork_leader(
-                    public_abi::EntryKind::ConsensusStage,
-                    data_validator,
-                    Some(dup),
-                )
-            }
+        let runs = match (&leaders_res, &equivalence) {
+            (Some(_), Some(equivalence)) => equivalence.runs(),
+            _ => 1,
         };
 
         let supervisor = self.context.data.supervisor.clone();
 
-        let vm_data = SingleVMData {
-            conf: base::Config {
-                needs_error_fingerprint: false,
-                is_deterministic: false,
-                can_read_storage: false,
-                can_write_storage: false,
-                can_spawn_nondet: false,
-                can_call_others: false,
-                can_send_messages: false,
-                state_mode: public_abi::StorageType::Default,
-            },
-            message_data,
-            version: self.context.data.version,
-            supervisor: supervisor.clone(),
-        };
+        // budget is shared by all runs of the validator
+        let deadline = vm::Deadline::after(
+            self.context.shared_data.timeouts.nondet_ms,
+            format!("nondet call {call_no}"),
+        );
+
+        let mut my_results = Vec::with_capacity(runs as usize);
+
+        for _ in 0..runs {
+            let message_data = match (&leaders_res, &equivalence) {
+                (Some(leaders_res), None) => {
+                    let dup = match leaders_res {
+                        RunOk::Return(items) => RunOk::Return(items.clone()),
+                        RunOk::UserError(msg) => RunOk::UserError(msg.clone()),
+                        RunOk::VMError(msg, _) => RunOk::VMError(msg.clone(), None),
+                    };
+                    self.context.data.message_data.fork_leader(
+                        public_abi::EntryKind::ConsensusStage,
+                        data_validator.clone(),
+                        Some(dup),
+                    )
+                }
+                _ => self.context.data.message_data.fork_leader(
+                    public_abi::EntryKind::ConsensusStage,
+                    data_leader.clone(),
+                    None,
+                ),
+            };
+
+            let vm_data = SingleVMData {
+                kind: VmKind::Nondet,
+                conf: base::Config {
+                    needs_error_fingerprint: false,
+                    is_deterministic: false,
+                    can_read_storage: false,
+                    can_read_others: false,
+                    can_write_storage: false,
+                    can_spawn_nondet: false,
+                    can_call_others: false,
+                    can_send_messages: false,
+                    state_mode: public_abi::StorageType::Default,
+                },
+                message_data,
+                version: self.context.data.version,
+                locked_slots: self.context.data.locked_slots.clone(),
+                supervisor: supervisor.clone(),
+            };
+
+            let my_res = self
+                .context
+                .spawn_and_run(&supervisor, vm_data, deadline.clone())
+                .await;
+            let my_res = match my_res {
+                Ok(res) => Ok(res),
+                Err(e) => errors::unwrap_vm_errors(e),
+            }
+            .map_err(generated::types::Error::trap)?;
 
-        let my_res = self.context.spawn_and_run(&supervisor, vm_data).await;
-        let my_res = match my_res {
-            Ok(res) => Ok(res),
-            Err(e) => errors::unwrap_vm_errors(e),
+            // nondet VM result must not be used if whole transaction is out of time
+            self.context
+                .shared_data
+                .check_deadline()
+                .map_err(|e| generated::types::Error::trap(e.into()))?;
+
+            my_results.push(my_res);
         }
-        .map_err(generated::types::Error::trap)?;
 
         let ret_res = match leaders_res {
             None => {
-                let mut supervisor = supervisor.lock().await;
-                supervisor
+                let my_res = my_results.pop().unwrap();
+                self.context
+                    .shared_data
                     .host
                     .post_nondet_result(call_no, &my_res)
+                    .await
                     .map_err(generated::types::Error::trap)?;
                 Ok(my_res)
             }
-            Some(leaders_res) => match my_res {
-                RunOk::Return(v) if v == [16] => Ok(leaders_res),
-                RunOk::Return(v) if v == [8] => Err(VMError(
-                    format!(
-                        "{} call {}",
-                        public_abi::VmError::ValidatorDisagrees.value(),
-                        call_no
-                    ),
-                    None,
-                )
-                .into()),
-                _ => {
-                    log_warn!(validator_result:? = my_res, leaders_result:? = leaders_res; "validator reported unexpected result");
+            Some(leaders_res) => {
+                let agrees = match &equivalence {
+                    Some(equivalence) => equivalence.agrees(&leaders_res, &my_results),
+                    None => match &my_results[0] {
+                        RunOk::Return(v) if v == &[16] => true,
+                        RunOk::Return(v) if v == &[8] => false,
+                        my_res => {
+                            log_warn!(validator_result:? = my_res, leaders_result:? = leaders_res; "validator reported unexpected result");
+                            false
+                        }
+                    },
+                };
+
+                if agrees {
+                    Ok(leaders_res)
+                } else {
+                    let report = DisagreementReport {
+                        call_no,
+                        equivalence: equivalence.as_ref().map(gl_call::Equivalence::to_calldata),
+                        leaders_result: leaders_res.to_calldata(),
+                        validator_results: my_results.iter().map(RunOk::to_calldata).collect(),
+                    };
+                    log_info!(report:serde = report; "validator disagrees");
+
                     Err(VMError(
                         format!(
                             "{} call {}",
                             public_abi::VmError::ValidatorDisagrees.value(),
                             call_no
                         ),
-                        None,
+                        Some(report.into()),
                     )
                     .into())
                 }
-            },
+            }
         };
         let ret_res = ret_res.map_err(generated::types::Error::trap)?;
         self.set_vm_run_result(ret_res).map(|x| x.0)
@@ -1103,10 +1366,12 @@ impl ContextVFS<'_> {
         let zelf_conf = &self.context.data.conf;
 
         let vm_data = SingleVMData {
+            kind: VmKind::Sandbox,
             conf: base::Config {
                 needs_error_fingerprint: false,
                 is_deterministic: zelf_conf.is_deterministic,
                 can_read_storage: false,
+                can_read_others: false,
                 can_write_storage: zelf_conf.can_write_storage & allow_write_ops,
                 can_spawn_nondet: false,
                 can_call_others: false,
@@ -1116,15 +1381,21 @@ impl ContextVFS<'_> {
             message_data,
             supervisor: supervisor.clone(),
             version: genvm_common::version::Version::ZERO,
+            locked_slots: self.context.data.locked_slots.clone(),
         };
 
-        let my_res = self.context.spawn_and_run(&supervisor, vm_data).await;
+        let my_res = self.context.spawn_and_run(&supervisor, vm_data, None).await;
         let my_res = match my_res {
             Ok(res) => Ok(res),
             Err(e) => errors::unwrap_vm_errors(e),
         }
         .map_err(generated::types::Error::trap)?;
 
+        self.context
+            .shared_data
+            .check_deadline()
+            .map_err(|e| generated::types::Error::trap(e.into()))?;
+
         let data: Box<[u8]> = my_res.as_bytes_iter().collect();
         Ok(generated::types::Fd::from(self.vfs.place_content(
             FileContentsUnevaluated::from_contents(SharedBytes::new(data), 0),
@@ -45,6 +45,25 @@ where
     deserializer.deserialize_any(Visitor)
 }
 
+/// Built-in comparison of leader and validator results of `RunNondet`
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub enum Equivalence {
+    /// results must be the same
+    Exact,
+    /// returned numbers may differ by `abs` plus `rel_ppm` millionths of the leader's one
+    Tolerance {
+        #[serde(default)]
+        abs: num_bigint::BigInt,
+        #[serde(default)]
+        rel_ppm: u32,
+    },
+    /// returned maps must have equal values at dot-separated `fields`, other entries are ignored
+    Fields { fields: Vec<String> },
+    /// leader function is run `runs` times, at least `quorum` results must be the same as the leader's one
+    Majority { runs: u32, quorum: u32 },
+}
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -65,6 +84,21 @@ pub enum Message {
         calldata: calldata::Value,
         #[serde(deserialize_with = "storage_type_from_bigint")]
         state: public_abi::StorageType,
+        /// return result envelope instead of trapping on callee `VMError`
+        #[serde(default)]
+        structured: bool,
+        /// callee may write its storage, writes are kept only if it returns
+        #[serde(default)]
+        write: bool,
+    },
+    /// read-only access to storage of any contract
+    StorageRead {
+        address: calldata::Address,
+        slot: Bytes,
+        index: u32,
+        len: u32,
+        #[serde(deserialize_with = "storage_type_from_bigint")]
+        state: public_abi::StorageType,
     },
     PostMessage {
         address: calldata::Address,
@@ -86,6 +120,9 @@ pub enum Message {
         data_leader: Vec<u8>,
         #[serde(with = "serde_bytes")]
         data_validator: Vec<u8>,
+        /// if present, validator runs `data_leader` and compares results itself
+        #[serde(default)]
+        equivalence: Option<Equivalence>,
     },
 
     Sandbox {
@@ -104,8 +141,14 @@ pub enum Message {
     Return(calldata::Value),
 
     EmitEvent {
+        #[serde(default)]
         topics: Vec<Bytes>,
         blob: BTreeMap<String, calldata::Value>,
+        /// event declared in contract schema, topics are then computed by GenVM from `schema`
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        schema: Option<super::event_abi::EventSchema>,
     },
 }
 
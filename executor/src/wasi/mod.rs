@@ -4,6 +4,8 @@ use crate::{calldata, vm};
 
 pub mod base;
 mod common;
+mod equivalence;
+pub mod event_abi;
 pub mod genlayer_sdk;
 mod gl_call;
 pub mod preview1;
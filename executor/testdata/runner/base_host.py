@@ -66,7 +66,7 @@ class IHost(metaclass=abc.ABCMeta):
 
 	@abc.abstractmethod
 	async def consume_result(
-		self, type: ResultCode, data: collections.abc.Buffer, /
+		self, type: ResultCode, data: collections.abc.Buffer, gas_used: int, /
 	) -> None: ...
 	@abc.abstractmethod
 	def has_result(self) -> bool: ...
@@ -101,6 +101,8 @@ class IHost(metaclass=abc.ABCMeta):
 	async def remaining_fuel_as_gen(self, /) -> int: ...
 	@abc.abstractmethod
 	async def post_event(self, topics: list[bytes], blob: bytes, /) -> None: ...
+	@abc.abstractmethod
+	async def get_gas_limit(self, /) -> int: ...
 
 
 def save_code_callback[T](
@@ -192,9 +194,26 @@ async def host_loop(handler: IHost):
 					await send_all(bytes([e.error_code]))
 				else:
 					await send_all(bytes([Errors.OK]))
+			case Methods.STORAGE_WRITE_BATCH:
+				count = await recv_int()
+				writes = []
+				for i in range(count):
+					slot = await read_exact(SLOT_ID_SIZE)
+					index = await recv_int()
+					got = await read_slice()
+					writes.append((slot, index, got))
+				try:
+					for slot, index, got in writes:
+						await handler.storage_write(slot, index, got)
+				except HostException as e:
+					await send_all(bytes([e.error_code]))
+				else:
+					await send_all(bytes([Errors.OK]))
 			case Methods.CONSUME_RESULT:
 				res = await read_slice()
-				await handler.consume_result(ResultCode(res[0]), res[1:])
+				# run.py uses protocol version 2, which doesn't carry used gas
+				gas_used = 0
+				await handler.consume_result(ResultCode(res[0]), res[1:], gas_used)
 				await send_all(b'\x00')
 				return
 			case Methods.GET_LEADER_NONDET_RESULT:
@@ -312,6 +331,14 @@ async def host_loop(handler: IHost):
 					await send_all(bytes([e.error_code]))
 				else:
 					await send_all(bytes([Errors.OK]))
+			case Methods.GET_GAS_LIMIT:
+				try:
+					res = await handler.get_gas_limit()
+				except HostException as e:
+					await send_all(bytes([e.error_code]))
+				else:
+					await send_all(bytes([Errors.OK]))
+					await send_all(res.to_bytes(8, byteorder='little', signed=False))
 			case x:
 				raise Exception(f'unknown method {x}')
 
@@ -470,7 +497,7 @@ async def run_host_and_program(
 		):
 			errors.append(Exception('no result provided'))
 		else:
-			await handler.consume_result(ResultCode.VM_ERROR, b'timeout')
+			await handler.consume_result(ResultCode.VM_ERROR, b'timeout', 0)
 
 	result = RunHostAndProgramRes(
 		b''.join(stdout).decode(),
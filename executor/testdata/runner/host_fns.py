@@ -19,6 +19,11 @@ class Methods(IntEnum):
 	ETH_SEND = 11
 	GET_BALANCE = 12
 	REMAINING_FUEL_AS_GEN = 13
+	GET_GAS_LIMIT = 14
+	STORAGE_WRITE_BATCH = 15
+
+
+PROTOCOL_VERSION: typing.Final[int] = 6
 
 
 class Errors(IntEnum):
@@ -128,6 +128,9 @@ class MockHost(IHost):
 	async def remaining_fuel_as_gen(self) -> int:
 		return 2**32
 
+	async def get_gas_limit(self) -> int:
+		return 2**64 - 1
+
 	async def storage_write(
 		self,
 		slot: bytes,
@@ -138,7 +141,7 @@ class MockHost(IHost):
 		self.storage.write(self.running_address, slot, index, got)
 
 	async def consume_result(
-		self, type: ResultCode, data: collections.abc.Buffer
+		self, type: ResultCode, data: collections.abc.Buffer, gas_used: int
 	) -> None:
 		self._has_result = True
 
@@ -39,6 +39,8 @@ class VmError(StrEnum):
 	VERSION_TOO_BIG = 'version_too_big'
 	OOM = 'OOM'
 	INVALID_CONTRACT = 'invalid_contract'
+	OUT_OF_GAS = 'out_of_gas'
+	INVALID_RUNNER = 'invalid_runner'
 
 
 EVENT_MAX_TOPICS: typing.Final[int] = 4
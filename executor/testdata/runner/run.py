@@ -226,6 +226,8 @@ def run(jsonnet_rel_path):
 				'--allow-latest',
 				'--host-data',
 				'{"node_address": "0x", "tx_id": "0x"}',
+				'--host-protocol',
+				'2',
 			]
 		)
 		if config['sync']:
@@ -0,0 +1,1106 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use genvm::mock::{MockBytes, MockHost, MockState};
+use genvm::record::{Recorder, Recording, Replayer, Tape};
+use genvm::{root_offsets, AccountAddress, MessageData, PublicArgs, SlotID};
+
+/// module with a single empty `_start` function
+const EMPTY_CONTRACT: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type () -> ()
+    0x03, 0x02, 0x01, 0x00, // function
+    0x07, 0x0a, 0x01, 0x06, b'_', b's', b't', b'a', b'r', b't', 0x00, 0x00, // export
+    0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b, // code
+];
+
+/// module which `_start` never returns
+const LOOPING_CONTRACT: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type () -> ()
+    0x03, 0x02, 0x01, 0x00, // function
+    0x07, 0x0a, 0x01, 0x06, b'_', b's', b't', b'a', b'r', b't', 0x00, 0x00, // export
+    0x0a, 0x09, 0x01, 0x07, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b, // code: loop br 0 end
+];
+
+const CONTRACT: AccountAddress = AccountAddress([1; 20]);
+const CALLEE: AccountAddress = AccountAddress([3; 20]);
+
+/// offset at which [`sdk_contract`] places its data
+const DATA_OFFSET: i32 = 1024;
+
+fn push_uleb(out: &mut Vec<u8>, mut v: u32) {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn push_vec(out: &mut Vec<u8>, data: &[u8]) {
+    push_uleb(out, data.len() as u32);
+    out.extend_from_slice(data);
+}
+
+fn i32_const(out: &mut Vec<u8>, mut v: i32) {
+    out.push(0x41);
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if (v == 0 && byte & 0x40 == 0) || (v == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Module with exported memory and `_start` executing `body`, which can call imported
+/// `genlayer_sdk.storage_write` (function 0), `genlayer_sdk.gl_call` (function 1)
+/// and `wasi_snapshot_preview1.fd_read` (function 2)
+fn sdk_contract(body: &[u8], data: &[u8]) -> Vec<u8> {
+    fn section(out: &mut Vec<u8>, id: u8, content: &[u8]) {
+        out.push(id);
+        push_vec(out, content);
+    }
+
+    let mut res = Vec::from(&EMPTY_CONTRACT[..8]);
+
+    section(
+        &mut res,
+        1,
+        &[
+            0x03, // types
+            0x60, 0x00, 0x00, // () -> ()
+            0x60, 0x04, 0x7f, 0x7f, 0x7f, 0x7f, 0x01, 0x7f, // storage_write
+            0x60, 0x03, 0x7f, 0x7f, 0x7f, 0x01, 0x7f, // gl_call
+        ],
+    );
+
+    let mut imports = vec![0x03];
+    for (module, name, ty) in [
+        ("genlayer_sdk", "storage_write", 1),
+        ("genlayer_sdk", "gl_call", 2),
+        ("wasi_snapshot_preview1", "fd_read", 1),
+    ] {
+        push_vec(&mut imports, module.as_bytes());
+        push_vec(&mut imports, name.as_bytes());
+        imports.extend_from_slice(&[0x00, ty]);
+    }
+    section(&mut res, 2, &imports);
+
+    section(&mut res, 3, &[0x01, 0x00]);
+    section(&mut res, 5, &[0x01, 0x00, 0x01]);
+
+    let mut exports = vec![0x02];
+    push_vec(&mut exports, b"_start");
+    exports.extend_from_slice(&[0x00, 0x03]);
+    push_vec(&mut exports, b"memory");
+    exports.extend_from_slice(&[0x02, 0x00]);
+    section(&mut res, 7, &exports);
+
+    let mut func = vec![0x00]; // no locals
+    func.extend_from_slice(body);
+    func.push(0x0b);
+    let mut code = vec![0x01];
+    push_vec(&mut code, &func);
+    section(&mut res, 10, &code);
+
+    let mut segment = vec![0x01, 0x00];
+    i32_const(&mut segment, DATA_OFFSET);
+    segment.push(0x0b);
+    push_vec(&mut segment, data);
+    section(&mut res, 11, &segment);
+
+    res
+}
+
+/// Instructions calling `gl_call` with encoded `request` placed at [`DATA_OFFSET`],
+/// leave errno on the stack
+fn gl_call(body: &mut Vec<u8>, request: &[u8]) {
+    i32_const(body, DATA_OFFSET);
+    i32_const(body, request.len() as i32);
+    i32_const(body, 0); // returned fd
+    body.extend_from_slice(&[0x10, 0x01]);
+}
+
+fn make_config(name: &str) -> genvm::config::Config {
+    let cache_dir = std::env::temp_dir().join(format!("genvm-test-{name}"));
+
+    serde_yaml::from_value(serde_yaml::Value::Mapping(serde_yaml::Mapping::from_iter([
+        (
+            "modules".into(),
+            serde_yaml::from_str(
+                "{ llm: { address: 'ws://127.0.0.1:1' }, web: { address: 'ws://127.0.0.1:1' } }",
+            )
+            .unwrap(),
+        ),
+        ("cache_dir".into(), cache_dir.to_str().unwrap().into()),
+        ("threads".into(), 1.into()),
+        ("blocking_threads".into(), 1.into()),
+        ("log_disable".into(), "*".into()),
+    ])))
+    .unwrap()
+}
+
+fn make_state(code: &[u8]) -> MockState {
+    let mut code_data = Vec::from((code.len() as u32).to_le_bytes());
+    code_data.extend_from_slice(code);
+
+    let mut state = MockState::default();
+    state.storage.insert(
+        CONTRACT,
+        BTreeMap::from([(
+            SlotID::ZERO.indirection(root_offsets::CODE),
+            MockBytes(code_data),
+        )]),
+    );
+    state.gas_limit = 1_000_000;
+    state
+}
+
+fn make_message() -> MessageData {
+    serde_json::from_value(serde_json::json!({
+        "contract_address": "AQEBAQEBAQEBAQEBAQEBAQEBAQE=",
+        "sender_address": "AgICAgICAgICAgICAgICAgICAgI=",
+        "origin_address": "AgICAgICAgICAgICAgICAgICAgI=",
+        "chain_id": "0",
+        "value": null,
+        "is_init": false,
+    }))
+    .unwrap()
+}
+
+/// Message of a transaction at which GenVM `v0.1.6` is active
+fn make_message_v0_1_6() -> MessageData {
+    let mut message = make_message();
+    message.datetime = "2025-08-01T00:00:00Z".parse().unwrap();
+    message
+}
+
+/// Stored zip of a `v0.1.6` contract which starts `wasm`
+fn contract_v0_1_6(wasm: &[u8]) -> Vec<u8> {
+    contract_v0_1_6_with_runner(br#"{ "StartWasm": "contract.wasm" }"#, wasm)
+}
+
+/// Stored zip of a `v0.1.6` contract with given `runner.json` and `contract.wasm`
+fn contract_v0_1_6_with_runner(runner: &[u8], wasm: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, contents) in [
+        ("version", b"v0.1.6".as_ref()),
+        ("runner.json", runner),
+        ("contract.wasm", wasm),
+    ] {
+        zip.start_file(name, options).unwrap();
+        zip.write_all(contents).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+fn make_host_data() -> genvm_modules_interfaces::HostData {
+    serde_json::from_value(serde_json::json!({ "node_address": "", "tx_id": "" })).unwrap()
+}
+
+fn run(name: &str, state: MockState) -> MockState {
+    run_recorded(name, state, None)
+}
+
+fn run_recorded(name: &str, state: MockState, recorder: Option<Arc<Recorder>>) -> MockState {
+    run_configured(
+        make_config(name),
+        name,
+        state,
+        make_message(),
+        "rwscn",
+        recorder,
+    )
+}
+
+fn run_v0_1_6(name: &str, state: MockState, permissions: &str) -> MockState {
+    run_configured(
+        make_config(name),
+        name,
+        state,
+        make_message_v0_1_6(),
+        permissions,
+        None,
+    )
+}
+
+fn run_configured(
+    config: genvm::config::Config,
+    name: &str,
+    state: MockState,
+    message: MessageData,
+    permissions: &str,
+    recorder: Option<Arc<Recorder>>,
+) -> MockState {
+    assert_eq!(message.contract_address, CONTRACT);
+
+    let mock = Arc::new(Mutex::new(MockHost::new(state, message.contract_address)));
+
+    let runtime = config.base.create_rt().unwrap();
+    let (token, _canceller) = genvm_common::cancellation::make();
+
+    runtime.block_on(async {
+        let mut host = genvm::Host::new_in_process(mock.clone()).await.unwrap();
+
+        let tape = match &recorder {
+            Some(recorder) => {
+                host.record_to(recorder.clone());
+                Tape::Record(recorder.clone())
+            }
+            None => Tape::None,
+        };
+
+        let supervisor = genvm::create_supervisor(
+            &config,
+            host,
+            token,
+            make_host_data(),
+            PublicArgs {
+                cookie: name.into(),
+                allow_latest: false,
+                is_sync: true,
+                message: &message,
+                tape,
+            },
+        )
+        .await
+        .unwrap();
+
+        genvm::run_with(message.clone(), supervisor, permissions)
+            .await
+            .unwrap();
+    });
+
+    // mock host thread releases the lock after consuming result
+    let host = mock.lock().unwrap();
+    host.state.clone()
+}
+
+/// Returns `consume_result` payload of the replayed run
+fn replay(name: &str, recording: &Recording) -> Option<Vec<u8>> {
+    let config = make_config(name);
+    let replayer = Arc::new(Replayer::new(recording));
+
+    let runtime = config.base.create_rt().unwrap();
+    let (token, _canceller) = genvm_common::cancellation::make();
+
+    runtime.block_on(async {
+        let host = genvm::Host::replay(replayer.clone(), recording.host_protocol);
+
+        let supervisor = genvm::create_supervisor(
+            &config,
+            host,
+            token,
+            recording.host_data.clone(),
+            PublicArgs {
+                cookie: name.into(),
+                allow_latest: recording.allow_latest,
+                is_sync: recording.is_sync,
+                message: &recording.message,
+                tape: Tape::Replay(replayer.clone()),
+            },
+        )
+        .await
+        .unwrap();
+
+        genvm::run_with(
+            recording.message.clone(),
+            supervisor,
+            &recording.permissions,
+        )
+        .await
+        .unwrap();
+    });
+
+    replayer.result()
+}
+
+#[test]
+fn empty_contract_returns() {
+    let state = run("empty-contract", make_state(EMPTY_CONTRACT));
+
+    let result = state.result.expect("result must be consumed");
+    assert_eq!(result.code, genvm::public_abi::ResultCode::Return as u8);
+    assert!(result.gas_used <= 1_000_000);
+}
+
+#[test]
+fn invalid_contract_is_vm_error() {
+    let state = run("invalid-contract", make_state(b"not a contract"));
+
+    let result = state.result.expect("result must be consumed");
+    assert_eq!(result.code, genvm::public_abi::ResultCode::VmError as u8);
+}
+
+#[test]
+fn state_roundtrips() {
+    let mut state = make_state(EMPTY_CONTRACT);
+    state
+        .balances
+        .insert(CONTRACT, primitive_types::U256::from(42));
+
+    let path = std::env::temp_dir().join("genvm-test-mock-state.yaml");
+    state.save(&path).unwrap();
+    let loaded = MockState::load(&path).unwrap();
+
+    assert_eq!(loaded.storage, state.storage);
+    assert_eq!(loaded.balances, state.balances);
+    assert_eq!(loaded.gas_limit, state.gas_limit);
+}
+
+#[test]
+fn recording_replays() {
+    let recorder = Arc::new(Recorder::new(Recording {
+        genvm_version: genvm_common::VERSION.to_owned(),
+        message: make_message(),
+        permissions: "rwscn".into(),
+        host_data: make_host_data(),
+        host_protocol: genvm::Host::LATEST_PROTOCOL_VERSION,
+        is_sync: true,
+        allow_latest: false,
+        host: Vec::new(),
+        modules: Vec::new(),
+        result: None,
+    }));
+
+    run_recorded("record", make_state(EMPTY_CONTRACT), Some(recorder.clone()));
+    let recording = recorder.finish().unwrap();
+
+    assert!(!recording.host.is_empty());
+    assert!(recording.result.is_some());
+
+    let replayed = replay("replay", &recording);
+    assert_eq!(replayed, recording.result.map(|x| x.0));
+}
+
+#[test]
+fn supervisor_is_reused() {
+    let config = make_config("reuse");
+    let message = make_message();
+
+    let runtime = config.base.create_rt().unwrap();
+
+    let results = runtime.block_on(async {
+        let mut supervisor = None;
+        let mut results = Vec::new();
+
+        for code in [EMPTY_CONTRACT, b"not a contract", EMPTY_CONTRACT] {
+            let mock = Arc::new(Mutex::new(MockHost::new(
+                make_state(code),
+                message.contract_address,
+            )));
+            let host = genvm::Host::new_in_process(mock.clone()).await.unwrap();
+            let (token, _canceller) = genvm_common::cancellation::make();
+            let pub_args = PublicArgs {
+                cookie: "reuse".into(),
+                allow_latest: false,
+                is_sync: true,
+                message: &message,
+                tape: Tape::None,
+            };
+
+            let current = match &supervisor {
+                None => {
+                    let created =
+                        genvm::create_supervisor(&config, host, token, make_host_data(), pub_args)
+                            .await
+                            .unwrap();
+                    supervisor = Some(created.clone());
+                    created
+                }
+                Some(existing) => {
+                    genvm::reuse_supervisor(
+                        &config,
+                        existing,
+                        host,
+                        token,
+                        make_host_data(),
+                        pub_args,
+                    )
+                    .await
+                    .unwrap();
+                    existing.clone()
+                }
+            };
+
+            genvm::run_with(message.clone(), current, "rwscn")
+                .await
+                .unwrap();
+
+            let code = mock.lock().unwrap().state.result.as_ref().unwrap().code;
+            results.push(code);
+        }
+
+        results
+    });
+
+    // contract code must not leak between transactions
+    assert_eq!(
+        results,
+        [
+            genvm::public_abi::ResultCode::Return as u8,
+            genvm::public_abi::ResultCode::VmError as u8,
+            genvm::public_abi::ResultCode::Return as u8,
+        ]
+    );
+}
+
+#[test]
+fn total_timeout_interrupts_contract() {
+    let mut config = make_config("timeout");
+    config.timeouts.total_ms = Some(100);
+
+    let mut state = make_state(LOOPING_CONTRACT);
+    state.gas_limit = u64::MAX;
+
+    let state = run_configured(config, "timeout", state, make_message(), "rwscn", None);
+
+    let result = state.result.expect("result must be consumed");
+    assert_eq!(result.code, genvm::public_abi::ResultCode::VmError as u8);
+
+    let genvm::calldata::Value::Map(value) = genvm::calldata::decode(&result.data.0).unwrap()
+    else {
+        panic!("vm error must be a map");
+    };
+    assert_eq!(
+        value.get("message"),
+        Some(&genvm::calldata::Value::Str("timeout total".into()))
+    );
+}
+
+#[test]
+fn gas_limit_interrupts_contract() {
+    let mut state = make_state(LOOPING_CONTRACT);
+    state.gas_limit = 10_000;
+
+    let state = run("out-of-gas", state);
+
+    let result = state.result.expect("result must be consumed");
+    assert_eq!(result.code, genvm::public_abi::ResultCode::VmError as u8);
+    assert_eq!(result.gas_used, 10_000);
+
+    let genvm::calldata::Value::Map(value) = genvm::calldata::decode(&result.data.0).unwrap()
+    else {
+        panic!("vm error must be a map");
+    };
+    assert_eq!(
+        value.get("message"),
+        Some(&genvm::calldata::Value::Str(
+            genvm::public_abi::VmError::OutOfGas.value().into()
+        ))
+    );
+}
+
+/// `forbidden` errno of `genlayer_sdk`
+const ERRNO_FORBIDDEN: u8 = 6;
+
+/// Encoded `gl_call` request `{ kind: fields }`
+fn gl_request<const N: usize>(kind: &str, fields: [(&str, genvm::calldata::Value); N]) -> Vec<u8> {
+    genvm::calldata::encode(&genvm::calldata::Value::Map(BTreeMap::from([(
+        kind.to_owned(),
+        genvm::calldata::Value::Map(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect()),
+    )])))
+}
+
+/// Contract which stores errno of `gl_call` with `request` into slot of zeroes,
+/// then returns or traps
+fn store_errno_contract(request: &[u8], rollback: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    i32_const(&mut body, 64);
+    gl_call(&mut body, &request);
+    body.extend_from_slice(&[0x3a, 0x00, 0x00]); // i32.store8
+
+    // storage_write(slot of zeroes, 0, errno, 1)
+    for arg in [32, 0, 64, 1] {
+        i32_const(&mut body, arg);
+    }
+    body.extend_from_slice(&[0x10, 0x00, 0x1a]);
+
+    if rollback {
+        body.push(0x00); // unreachable
+    }
+
+    sdk_contract(&body, request)
+}
+
+/// Callee of a mutating call tries to post a message
+fn mutating_callee(rollback: bool) -> Vec<u8> {
+    use genvm::calldata::Value;
+
+    let request = gl_request(
+        "PostMessage",
+        [
+            (
+                "address",
+                Value::Address(genvm::calldata::Address::from(CONTRACT.0)),
+            ),
+            ("calldata", Value::Map(BTreeMap::new())),
+            ("value", Value::Number(0.into())),
+            ("on", Value::Str("finalized".into())),
+        ],
+    );
+
+    store_errno_contract(&request, rollback)
+}
+
+/// Contract which calls [`CALLEE`] in mutating mode and ignores the outcome
+fn mutating_caller() -> Vec<u8> {
+    use genvm::calldata::Value;
+
+    let request = gl_request(
+        "CallContract",
+        [
+            (
+                "address",
+                Value::Address(genvm::calldata::Address::from(CALLEE.0)),
+            ),
+            ("calldata", Value::Map(BTreeMap::new())),
+            ("state", Value::Number(0.into())),
+            ("structured", Value::Bool(true)),
+            ("write", Value::Bool(true)),
+        ],
+    );
+
+    let mut body = Vec::new();
+    gl_call(&mut body, &request);
+    body.push(0x1a); // drop
+
+    sdk_contract(&body, &request)
+}
+
+fn run_mutating_call(name: &str, rollback: bool) -> MockState {
+    let mut state = make_state(&contract_v0_1_6(&mutating_caller()));
+    let callee = make_state(&mutating_callee(rollback));
+    state
+        .storage
+        .extend(callee.storage.into_values().map(|slots| (CALLEE, slots)));
+
+    let state = run_v0_1_6(name, state, "rwscn");
+
+    let result = state.result.as_ref().expect("result must be consumed");
+    assert_eq!(result.code, genvm::public_abi::ResultCode::Return as u8);
+
+    state
+}
+
+#[test]
+fn mutating_call_commits_writes() {
+    let state = run_mutating_call("mutating-commit", false);
+
+    // message of the callee would outlive its discarded writes, so it is forbidden
+    assert!(state.messages.is_empty());
+    assert_eq!(
+        state.storage[&CALLEE].get(&SlotID::ZERO),
+        Some(&MockBytes(vec![ERRNO_FORBIDDEN]))
+    );
+}
+
+#[test]
+fn mutating_call_rolls_back_writes() {
+    let state = run_mutating_call("mutating-rollback", true);
+
+    assert!(state.messages.is_empty());
+    assert_eq!(state.storage[&CALLEE].get(&SlotID::ZERO), None);
+}
+
+/// Reads a byte of [`CALLEE`] storage with given permissions, returns errno of the read
+fn read_other_storage(name: &str, permissions: &str) -> u8 {
+    use genvm::calldata::Value;
+
+    let request = gl_request(
+        "StorageRead",
+        [
+            (
+                "address",
+                Value::Address(genvm::calldata::Address::from(CALLEE.0)),
+            ),
+            ("slot", Value::Bytes(SlotID::ZERO.0.to_vec())),
+            ("index", Value::Number(0.into())),
+            ("len", Value::Number(1.into())),
+            ("state", Value::Number(0.into())),
+        ],
+    );
+
+    let state = make_state(&contract_v0_1_6(&store_errno_contract(&request, false)));
+    let state = run_v0_1_6(name, state, permissions);
+
+    let result = state.result.as_ref().expect("result must be consumed");
+    assert_eq!(result.code, genvm::public_abi::ResultCode::Return as u8);
+
+    let MockBytes(errno) = &state.storage[&CONTRACT][&SlotID::ZERO];
+    errno[0]
+}
+
+#[test]
+fn storage_read_of_others_needs_permissions() {
+    assert_eq!(read_other_storage("read-others", "rwscno"), 0);
+    assert_eq!(
+        read_other_storage("read-others-no-o", "rwscn"),
+        ERRNO_FORBIDDEN
+    );
+    assert_eq!(
+        read_other_storage("read-others-no-r", "wscno"),
+        ERRNO_FORBIDDEN
+    );
+}
+
+/// Contract which runs a non-deterministic block, that block never returns.
+/// Blocks are told apart by `storage_write` being forbidden in them
+fn looping_nondet_contract() -> Vec<u8> {
+    use genvm::calldata::Value;
+
+    let request = gl_request(
+        "RunNondet",
+        [
+            ("data_leader", Value::Bytes(Vec::new())),
+            ("data_validator", Value::Bytes(Vec::new())),
+        ],
+    );
+
+    let mut body = Vec::new();
+    for arg in [32, 0, 64, 1] {
+        i32_const(&mut body, arg);
+    }
+    body.extend_from_slice(&[0x10, 0x00]); // call storage_write
+    body.extend_from_slice(&[0x04, 0x40, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b]); // if loop br 0 end end
+    gl_call(&mut body, &request);
+    body.push(0x1a); // drop
+
+    sdk_contract(&body, &request)
+}
+
+#[test]
+fn nondet_timeout_interrupts_block() {
+    let mut config = make_config("nondet-timeout");
+    config.timeouts.nondet_ms = Some(100);
+
+    let state = run_configured(
+        config,
+        "nondet-timeout",
+        make_state(&looping_nondet_contract()),
+        make_message(),
+        "rwscn",
+        None,
+    );
+
+    // leader reports timed out block to the host, and its error fails the transaction
+    let mut expected = vec![genvm::public_abi::ResultCode::VmError as u8];
+    expected.extend_from_slice(b"timeout nondet call 0");
+    assert_eq!(state.nondet_results.get(&0), Some(&MockBytes(expected)));
+
+    let result = state.result.expect("result must be consumed");
+    assert_eq!(result.code, genvm::public_abi::ResultCode::VmError as u8);
+
+    let genvm::calldata::Value::Map(value) = genvm::calldata::decode(&result.data.0).unwrap()
+    else {
+        panic!("vm error must be a map");
+    };
+    assert_eq!(
+        value.get("message"),
+        Some(&genvm::calldata::Value::Str("timeout nondet call 0".into()))
+    );
+}
+
+/// `call_depth` and `call_cycle` errnos of `genlayer_sdk`
+const ERRNO_CALL_DEPTH: u8 = 8;
+const ERRNO_CALL_CYCLE: u8 = 9;
+
+/// Encoded request of a view call of `address`
+fn call_request(address: AccountAddress, structured: bool) -> Vec<u8> {
+    use genvm::calldata::Value;
+
+    gl_request(
+        "CallContract",
+        [
+            (
+                "address",
+                Value::Address(genvm::calldata::Address::from(address.0)),
+            ),
+            ("calldata", Value::Map(BTreeMap::new())),
+            ("state", Value::Number(0.into())),
+            ("structured", Value::Bool(structured)),
+            ("write", Value::Bool(false)),
+        ],
+    )
+}
+
+/// Runs a contract which calls `address` and returns errno of that call
+fn call_errno(config: genvm::config::Config, name: &str, address: AccountAddress) -> u8 {
+    let contract = store_errno_contract(&call_request(address, false), false);
+    let state = run_configured(
+        config,
+        name,
+        make_state(&contract),
+        make_message(),
+        "rwscn",
+        None,
+    );
+
+    let result = state.result.as_ref().expect("result must be consumed");
+    assert_eq!(result.code, genvm::public_abi::ResultCode::Return as u8);
+
+    let MockBytes(errno) = &state.storage[&CONTRACT][&SlotID::ZERO];
+    errno[0]
+}
+
+#[test]
+fn call_depth_is_limited() {
+    let mut config = make_config("call-depth");
+    config.call_limits.max_depth = 0;
+
+    assert_eq!(call_errno(config, "call-depth", CALLEE), ERRNO_CALL_DEPTH);
+}
+
+#[test]
+fn call_cycle_is_forbidden() {
+    let mut config = make_config("call-cycle");
+    config.call_limits.cycles = genvm::config::CyclePolicy::Forbid;
+
+    assert_eq!(call_errno(config, "call-cycle", CONTRACT), ERRNO_CALL_CYCLE);
+}
+
+/// Contract which makes a structured call of [`CALLEE`]
+/// and stores the received result envelope into its slot of zeroes
+fn envelope_caller() -> Vec<u8> {
+    let request = call_request(CALLEE, true);
+
+    let mut body = Vec::new();
+    gl_call(&mut body, &request);
+    body.push(0x1a); // drop
+
+    // iovec at 16 of 4096 bytes at 2048
+    for (addr, value) in [(16, 2048), (20, 4096)] {
+        i32_const(&mut body, addr);
+        i32_const(&mut body, value);
+        body.extend_from_slice(&[0x36, 0x02, 0x00]); // i32.store
+    }
+
+    // fd_read(returned fd, iovec, 1, read length at 8)
+    i32_const(&mut body, 0);
+    body.extend_from_slice(&[0x28, 0x02, 0x00]); // i32.load
+    for arg in [16, 1, 8] {
+        i32_const(&mut body, arg);
+    }
+    body.extend_from_slice(&[0x10, 0x02, 0x1a]);
+
+    // storage_write(slot of zeroes, 0, read envelope, read length)
+    for arg in [32, 0, 2048, 8] {
+        i32_const(&mut body, arg);
+    }
+    body.extend_from_slice(&[0x28, 0x02, 0x00]); // i32.load
+    body.extend_from_slice(&[0x10, 0x00, 0x1a]);
+
+    sdk_contract(&body, &request)
+}
+
+/// Runs [`envelope_caller`] against `callee` and returns the decoded envelope
+fn call_envelope(name: &str, callee: &[u8]) -> BTreeMap<String, genvm::calldata::Value> {
+    let mut state = make_state(&contract_v0_1_6(&envelope_caller()));
+    let callee = make_state(callee);
+    state
+        .storage
+        .extend(callee.storage.into_values().map(|slots| (CALLEE, slots)));
+
+    let state = run_v0_1_6(name, state, "rwscn");
+
+    let result = state.result.as_ref().expect("result must be consumed");
+    assert_eq!(result.code, genvm::public_abi::ResultCode::Return as u8);
+
+    let MockBytes(envelope) = &state.storage[&CONTRACT][&SlotID::ZERO];
+    let genvm::calldata::Value::Map(envelope) = genvm::calldata::decode(envelope).unwrap() else {
+        panic!("envelope must be a map");
+    };
+    envelope
+}
+
+#[test]
+fn fingerprint_has_call_stack() {
+    let trapping_callee = sdk_contract(&[0x00], &[]); // unreachable
+    let envelope = call_envelope("call-stack", &trapping_callee);
+
+    let Some(genvm::calldata::Value::Map(fingerprint)) = envelope.get("fingerprint") else {
+        panic!("fingerprint must be a map");
+    };
+    assert_eq!(
+        fingerprint.get("call_stack"),
+        Some(&genvm::calldata::Value::Array(vec![
+            genvm::calldata::Value::Bytes(CONTRACT.0.to_vec()),
+            genvm::calldata::Value::Bytes(CALLEE.0.to_vec()),
+        ]))
+    );
+}
+
+/// Contract which sends a single `gl_call` with `request`, which must not return
+fn finishing_contract(request: &genvm::calldata::Value) -> Vec<u8> {
+    let request = genvm::calldata::encode(request);
+
+    let mut body = Vec::new();
+    gl_call(&mut body, &request);
+    body.push(0x1a); // drop
+
+    sdk_contract(&body, &request)
+}
+
+#[test]
+fn call_result_envelope() {
+    use genvm::calldata::Value;
+
+    let callee_address = Value::Address(genvm::calldata::Address::from(CALLEE.0));
+
+    let returned = Value::Str("ok".into());
+    let envelope = call_envelope(
+        "envelope-return",
+        &finishing_contract(&Value::Map(BTreeMap::from([(
+            "Return".to_owned(),
+            returned.clone(),
+        )]))),
+    );
+    assert_eq!(envelope.get("kind"), Some(&Value::Str("return".into())));
+    assert_eq!(
+        envelope.get("payload"),
+        Some(&Value::Bytes(genvm::calldata::encode(&returned)))
+    );
+    assert_eq!(envelope.get("address"), Some(&callee_address));
+
+    let envelope = call_envelope(
+        "envelope-user-error",
+        &finishing_contract(&Value::Map(BTreeMap::from([(
+            "Rollback".to_owned(),
+            Value::Str("nope".into()),
+        )]))),
+    );
+    assert_eq!(envelope.get("kind"), Some(&Value::Str("user_error".into())));
+    assert_eq!(envelope.get("payload"), Some(&Value::Str("nope".into())));
+    assert_eq!(envelope.get("address"), Some(&callee_address));
+
+    let envelope = call_envelope("envelope-vm-error", &sdk_contract(&[0x00], &[]));
+    assert_eq!(envelope.get("kind"), Some(&Value::Str("vm_error".into())));
+    assert_eq!(
+        envelope.get("payload"),
+        Some(&Value::Str("wasm_trap UnreachableCodeReached".into()))
+    );
+    assert!(matches!(envelope.get("fingerprint"), Some(Value::Map(_))));
+    assert_eq!(envelope.get("address"), Some(&callee_address));
+}
+
+/// Ustar archive of `files`
+fn ustar(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut tar = Vec::new();
+    for (name, contents) in files {
+        let mut header = [0_u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", contents.len()).as_bytes());
+        header[156] = b'0';
+        header[257..265].copy_from_slice(b"ustar\x0000");
+
+        header[148..156].fill(b' ');
+        let checksum: usize = header.iter().map(|c| *c as usize).sum();
+        header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+
+        tar.extend_from_slice(&header);
+        tar.extend_from_slice(contents);
+        tar.resize(tar.len().div_ceil(512) * 512, 0);
+    }
+    tar.resize(tar.len() + 2 * 512, 0);
+    tar
+}
+
+/// Installs `lib` runner archive into a search path of `config`, returns its id
+fn install_lib_runner(config: &mut genvm::config::Config, name: &str, lib: &[u8]) -> String {
+    let runners = std::env::temp_dir().join(format!("genvm-test-{name}-runners"));
+    let _ = std::fs::remove_dir_all(&runners);
+    std::fs::create_dir_all(runners.join("lib")).unwrap();
+
+    let hash = genvm::registry::content_hash(lib);
+    std::fs::write(runners.join(format!("lib/{hash}.tar")), lib).unwrap();
+    config.runners.search_paths = vec![runners.to_str().unwrap().to_owned()];
+
+    format!("lib:{hash}")
+}
+
+#[test]
+fn reused_supervisor_charges_runners_as_fresh_one() {
+    let mut config = make_config("charge-reuse");
+
+    // runner which is used twice by the contract, only the second use is charged
+    let lib = ustar(&[
+        (
+            "runner.json",
+            br#"{ "AddEnv": { "name": "LIB", "val": "1" } }"#,
+        ),
+        ("data", &[7; 4096]),
+    ]);
+    let lib_id = install_lib_runner(&mut config, "charge-reuse", &lib);
+
+    let runner_json = serde_json::json!({ "Seq": [
+        { "Depends": lib_id },
+        { "With": { "runner": lib_id, "action": { "AddEnv": { "name": "WITH", "val": "1" } } } },
+        { "StartWasm": "contract.wasm" },
+    ] });
+    let code = contract_v0_1_6_with_runner(runner_json.to_string().as_bytes(), EMPTY_CONTRACT);
+    let message = make_message_v0_1_6();
+
+    let runtime = config.base.create_rt().unwrap();
+
+    // like `genvm run`, each transaction has a fresh supervisor, then like `genvm serve` one is reused
+    let charged = runtime.block_on(async {
+        let mut charged = Vec::new();
+        let mut reused = None;
+
+        for reuse in [false, true, true] {
+            let mock = Arc::new(Mutex::new(MockHost::new(
+                make_state(&code),
+                message.contract_address,
+            )));
+            let host = genvm::Host::new_in_process(mock.clone()).await.unwrap();
+            let (token, _canceller) = genvm_common::cancellation::make();
+            let pub_args = PublicArgs {
+                cookie: "charge-reuse".into(),
+                allow_latest: false,
+                is_sync: true,
+                message: &message,
+                tape: Tape::None,
+            };
+
+            let supervisor = match &reused {
+                Some(existing) if reuse => {
+                    genvm::reuse_supervisor(
+                        &config,
+                        existing,
+                        host,
+                        token,
+                        make_host_data(),
+                        pub_args,
+                    )
+                    .await
+                    .unwrap();
+                    Arc::clone(existing)
+                }
+                _ => {
+                    let created =
+                        genvm::create_supervisor(&config, host, token, make_host_data(), pub_args)
+                            .await
+                            .unwrap();
+                    if reuse {
+                        reused = Some(created.clone());
+                    }
+                    created
+                }
+            };
+
+            genvm::run_with(message.clone(), supervisor.clone(), "rwscn")
+                .await
+                .unwrap();
+
+            let code = mock.lock().unwrap().state.result.as_ref().unwrap().code;
+            assert_eq!(code, genvm::public_abi::ResultCode::Return as u8);
+
+            charged.push(
+                supervisor
+                    .lock()
+                    .await
+                    .shared_data
+                    .limiter_det
+                    .high_water_mark(),
+            );
+        }
+
+        charged
+    });
+
+    assert!(charged[0] >= lib.len() as u32);
+    assert_eq!(charged, [charged[0]; 3]);
+}
+
+/// Module named `init` with exported memory, which `_initialize` burns gas in a loop
+fn burning_init_module() -> Vec<u8> {
+    fn section(out: &mut Vec<u8>, id: u8, content: &[u8]) {
+        out.push(id);
+        push_vec(out, content);
+    }
+
+    let mut res = Vec::from(&EMPTY_CONTRACT[..8]);
+    section(&mut res, 1, &[0x01, 0x60, 0x00, 0x00]);
+    section(&mut res, 3, &[0x01, 0x00]);
+    section(&mut res, 5, &[0x01, 0x00, 0x01]);
+
+    let mut exports = vec![0x02];
+    push_vec(&mut exports, b"memory");
+    exports.extend_from_slice(&[0x02, 0x00]);
+    push_vec(&mut exports, b"_initialize");
+    exports.extend_from_slice(&[0x00, 0x00]);
+    section(&mut res, 7, &exports);
+
+    // loop { i += 1; br_if i < 100000 }
+    let mut func = vec![0x01, 0x01, 0x7f, 0x03, 0x40, 0x20, 0x00];
+    i32_const(&mut func, 1);
+    func.extend_from_slice(&[0x6a, 0x22, 0x00]);
+    i32_const(&mut func, 100_000);
+    func.extend_from_slice(&[0x49, 0x0d, 0x00, 0x0b, 0x0b]);
+    let mut code = vec![0x01];
+    push_vec(&mut code, &func);
+    section(&mut res, 10, &code);
+
+    let mut module_name = Vec::new();
+    push_vec(&mut module_name, b"init");
+    let mut names = Vec::new();
+    push_vec(&mut names, b"name");
+    names.push(0x00);
+    push_vec(&mut names, &module_name);
+    section(&mut res, 0, &names);
+
+    res
+}
+
+#[test]
+fn restored_snapshot_runs_out_of_gas_as_initialize() {
+    let lib = ustar(&[
+        ("runner.json", br#"{ "LinkWasm": "init.wasm" }"#),
+        ("init.wasm", &burning_init_module()),
+    ]);
+
+    let run_init = |name: &str, gas_limit: u64| {
+        let mut config = make_config(name);
+        config.snapshots = true;
+        let lib_id = install_lib_runner(&mut config, name, &lib);
+
+        let runner_json = serde_json::json!({ "Seq": [
+            { "Depends": lib_id },
+            { "StartWasm": "contract.wasm" },
+        ] });
+        let code = contract_v0_1_6_with_runner(runner_json.to_string().as_bytes(), EMPTY_CONTRACT);
+        let mut state = make_state(&code);
+        state.gas_limit = gas_limit;
+
+        let state = run_configured(config, name, state, make_message_v0_1_6(), "rwscn", None);
+        state.result.expect("result must be consumed")
+    };
+
+    for name in ["snapshot-gas-fresh", "snapshot-gas-restored"] {
+        let _ = std::fs::remove_dir_all(make_config(name).cache_dir);
+    }
+
+    // captures snapshot of `_initialize`
+    let full = run_init("snapshot-gas-restored", 100_000_000);
+    assert_eq!(full.code, genvm::public_abi::ResultCode::Return as u8);
+
+    let fresh = run_init("snapshot-gas-fresh", 10_000);
+    let restored = run_init("snapshot-gas-restored", 10_000);
+
+    for result in [&fresh, &restored] {
+        assert_eq!(result.code, genvm::public_abi::ResultCode::VmError as u8);
+        assert_eq!(result.gas_used, 10_000);
+    }
+}
@@ -8,6 +8,7 @@ This module provides some "advanced" features that can be used for optimizations
 __all__ = (
 	'user_error_immediate',
 	'emit_raw_event',
+	'emit_declared_event',
 )
 
 import typing
@@ -36,6 +37,35 @@ def emit_raw_event(
 	).get()
 
 
+def emit_declared_event(
+	name: str,
+	blob: dict[str, calldata.Encodable],
+	*,
+	indexed: list[str],
+	has_blob: bool,
+) -> None:
+	"""
+	Emits an event declared in contract schema, GenVM validates ``blob`` against ``indexed`` and ``has_blob`` and computes topics.
+	Requires contract version ``v0.1.6``
+
+	.. note::
+		:py:meth:`genlayer.gl.Event.emit` uses this function
+	"""
+	gl_call.gl_call_generic(
+		{
+			'EmitEvent': {
+				'name': name,
+				'schema': {
+					'indexed': indexed,
+					'blob': has_blob,
+				},
+				'blob': blob,
+			}
+		},
+		lambda _x: None,
+	).get()
+
+
 def user_error_immediate(reason: str) -> typing.NoReturn:
 	"""
 	Performs an immediate error, current VM won't be able to handle it, stack unwind will not happen
@@ -5,9 +5,7 @@ from genlayer.py.keccak import Keccak256
 import genlayer.py.calldata as calldata
 
 
-def _emit(self: Event) -> None:
-	from genlayer.gl.advanced import emit_raw_event
-
+def _raw_topics(self: Event) -> list[bytes]:
 	topics = [Keccak256(self.signature.encode('utf-8')).digest()]
 	for i in self.indexed:
 		d = self._blob[i]
@@ -17,8 +15,20 @@ def _emit(self: Event) -> None:
 		else:
 			as_cd = as_cd + b'\x00' * (32 - len(as_cd))
 		topics.append(as_cd)
+	return topics
+
+
+def _emit(self: Event) -> None:
+	from genlayer.gl.advanced import emit_raw_event, emit_declared_event
 
-	emit_raw_event(topics, self._blob)
+	try:
+		# topics are computed by GenVM from the schema
+		emit_declared_event(
+			self.name, self._blob, indexed=list(self.indexed), has_blob=self.has_blob
+		)
+	except SystemError:
+		# contracts older than v0.1.6 can emit only raw events, topics are the same
+		emit_raw_event(_raw_topics(self), self._blob)
 
 
 Event.emit = _emit
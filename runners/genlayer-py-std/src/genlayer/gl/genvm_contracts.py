@@ -18,6 +18,9 @@ import _genlayer_wasi as wasi
 
 from ._internal.gl_call import gl_call_generic
 
+if typing.TYPE_CHECKING:
+	from genlayer.gl.vm import Result
+
 type ON = typing.Literal['accepted', 'finalized']
 
 
@@ -81,6 +84,24 @@ class _ContractAtViewMethod:
 			_decode_sub_vm_result,
 		)
 
+	def lazy_result(self, *args, **kwargs) -> Lazy['Result']:
+		"""
+		Same as :py:meth:`lazy`, but errors of the called contract, including ``VMError``, are returned instead of being raised
+		"""
+		from genlayer.gl.vm import _decode_call_result
+
+		return gl_call_generic(
+			{
+				'CallContract': {
+					'address': self._addr,
+					'calldata': _make_calldata_obj(self._name, args, kwargs),
+					'state': self._state.value,
+					'structured': True,
+				}
+			},
+			_decode_call_result,
+		)
+
 
 class _ContractAtEmitMethod:
 	__slots__ = ('_addr', '_name', '_value', '_on')
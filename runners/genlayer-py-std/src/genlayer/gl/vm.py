@@ -2,6 +2,7 @@ __all__ = (
 	'spawn_sandbox',
 	'run_nondet_unsafe',
 	'run_nondet',
+	'run_nondet_builtin',
 	'unpack_result',
 	'Return',
 	'VMError',
@@ -82,6 +83,21 @@ def _decode_sub_vm_result_retn(
 	assert False, f'unknown type {mem[0]}'
 
 
+def _decode_call_result(
+	data: collections.abc.Buffer,
+) -> Result:
+	envelope = calldata.decode(data)
+	assert isinstance(envelope, dict)
+	kind = envelope['kind']
+	if kind == 'return':
+		return Return(calldata.decode(envelope['payload']))
+	if kind == 'user_error':
+		return UserError(envelope['payload'])
+	if kind == 'vm_error':
+		return VMError(envelope['payload'])
+	assert False, f'unknown kind {kind}'
+
+
 def unpack_result[T: calldata.Decoded](res: Result[T], /) -> T:
 	"""
 	Extracts the successful result from a VM operation result.
@@ -139,6 +155,35 @@ def spawn_sandbox[T: calldata.Decoded](
 	)
 
 
+@_lazy_api
+def run_nondet_builtin[T: calldata.Decoded](
+	leader_fn: typing.Callable[[], T], equivalence: calldata.Encodable, /
+) -> Lazy[T]:
+	"""
+	Executes a non-deterministic block, validators run ``leader_fn`` as well and compare results with a built-in strategy.
+
+	:param leader_fn: Function executed by the leader and validator nodes (must be serializable)
+	:param equivalence: one of ``'Exact'``, ``{'Tolerance': {'abs': 1, 'rel_ppm': 1000}}``,
+		``{'Fields': {'fields': ['a.b']}}`` or ``{'Majority': {'runs': 3, 'quorum': 2}}``
+	:return: The result from the leader (iff validation passes, otherwise VM will be terminated)
+
+	Example:
+		>>> value = gl.vm.run_nondet_builtin(lambda: fetch_price(), {'Tolerance': {'rel_ppm': 5000}})
+	"""
+	import cloudpickle
+
+	return gl_call.gl_call_generic(
+		{
+			'RunNondet': {
+				'data_leader': cloudpickle.dumps(lambda _: leader_fn()),
+				'data_validator': b'',
+				'equivalence': equivalence,
+			}
+		},
+		_decode_sub_vm_result,
+	)
+
+
 @_lazy_api
 def run_nondet_unsafe[T: calldata.Decoded](
 	leader_fn: typing.Callable[[], T], validator_fn: typing.Callable[[Result], bool], /
@@ -1,6 +1,7 @@
 __all__ = ('Event',)
 
 import inspect
+import typing
 import genlayer.py.calldata as calldata
 import genlayer.py._internal.reflect as reflect
 
@@ -36,6 +37,15 @@ class Event:
 	tuple of indexed arguments name in **sorted** order
 	"""
 
+	params: dict[str, typing.Any]
+	"""
+	annotations of indexed fields, as they are written in ``__init__``
+	"""
+	has_blob: bool
+	"""
+	whether event accepts fields that are not indexed (``**blob``)
+	"""
+
 	_blob: dict[str, calldata.Encodable]
 
 	__slots__ = ('_blob',)
@@ -50,6 +60,8 @@ class Event:
 		sig = inspect.signature(old_init)
 
 		indexed_args_lst: list[str] = []
+		params: dict[str, typing.Any] = {}
+		has_blob = False
 
 		event_name = getattr(cls, 'name', cls.__name__)
 
@@ -68,9 +80,10 @@ class Event:
 					case inspect.Parameter.POSITIONAL_OR_KEYWORD:
 						raise TypeError('specify `/` after indexed fields')
 					case inspect.Parameter.VAR_KEYWORD:
-						pass
+						has_blob = True
 					case inspect.Parameter.POSITIONAL_ONLY:
 						indexed_args_lst.append(name)
+						params[name] = param.annotation
 
 		indexed_args = tuple(sorted(indexed_args_lst))
 
@@ -102,8 +115,12 @@ class Event:
 		cls.name = event_name
 		cls.signature = signature
 		cls.indexed = indexed_args
+		cls.params = params
+		cls.has_blob = has_blob
 		cls.__init__ = __init__
 
+		declared[event_name] = cls
+
 	def __init_subclass__(cls) -> None:
 		with reflect.context_notes('generating event class'):
 			with reflect.context_type(cls):
@@ -114,3 +131,9 @@ class Event:
 		emit this event
 		"""
 		...
+
+
+declared: dict[str, type[Event]] = {}
+"""
+all declared events by name, they are included in contract schema. Later declaration replaces earlier one with the same name
+"""
@@ -154,6 +154,19 @@ def _get_params(m: types.FunctionType, *, is_ctor: bool) -> dict:
 		) from e
 
 
+def _get_events() -> dict:
+	from genlayer.py._internal.event import declared
+
+	return {
+		name: {
+			'indexed': list(ev.indexed),
+			'params': {k: _repr_type(v, True) for k, v in ev.params.items()},
+			'blob': ev.has_blob,
+		}
+		for name, ev in sorted(declared.items())
+	}
+
+
 def _get_ctor(contract: type) -> types.FunctionType:
 	if not hasattr(contract, '__dict__') or '__init__' not in contract.__dict__:
 		raise TypeError('__init__ is absent', contract)
@@ -184,7 +197,12 @@ def get_schema(contract: type) -> typing.Any:
 		if k.startswith('__'):
 			raise TypeError(f'public method names should not start with `__`, `{k}`')
 
-	return {
+	ret = {
 		'ctor': _get_params(ctor, is_ctor=True),
 		'methods': {k: _get_params(v, is_ctor=False) for k, v in meths.items()},
 	}
+
+	if events := _get_events():
+		ret['events'] = events
+
+	return ret
@@ -1,6 +1,14 @@
 import pytest
 
-from genlayer.py._internal.event import Event
+from genlayer.py._internal.event import Event, declared
+
+
+@pytest.fixture(autouse=True)
+def restore_declared():
+	saved = dict(declared)
+	yield
+	declared.clear()
+	declared.update(saved)
 
 
 def test_invalid_sig():
@@ -43,3 +51,12 @@ def test_name_override():
 
 	assert Name1.name == 'Name2'
 	assert Name1.signature == 'Name2(a,b)'
+
+
+def test_declared():
+	class Declared(Event):
+		def __init__(self, b: int, a: str, /): ...
+
+	assert declared['Declared'] is Declared
+	assert Declared.params == {'a': str, 'b': int}
+	assert not Declared.has_blob